@@ -1,6 +1,7 @@
-use std::cmp::min;
+use std::cmp::{max, min};
 
 use crate::line::Line;
+use crate::mark::Mark;
 
 #[derive(Clone)]
 enum Action {
@@ -10,24 +11,25 @@ enum Action {
     RemoveRegion((usize, usize), (usize, usize), Vec<Line>),
     JoinLine(usize, usize),
     SplitLine(usize, usize),
+    ReplaceLine(usize, Line, Line),
+    ReplaceLines(usize, Vec<Line>, Vec<Line>, Vec<Option<usize>>),
+    Group(Vec<Action>),
 }
 
+#[derive(Default)]
 pub struct Buffer {
+    // The first line changed and how many lines at the end are unchanged
+    changed: Option<(usize, usize)>,
     dirty: bool,
+    folds: Vec<usize>,
+    group: Option<Vec<Action>>,
+    group_depth: usize,
     history: Vec<Action>,
     index: usize,
     lines: Vec<Line>,
-}
-
-impl Default for Buffer {
-    fn default() -> Self {
-        Buffer {
-            dirty: false,
-            history: vec![],
-            index: 0,
-            lines: vec![],
-        }
-    }
+    marks: Vec<Mark>,
+    // Counts changes to the lines, so caches can tell when they're out of date
+    version: usize,
 }
 
 impl Buffer {
@@ -42,7 +44,7 @@ impl Buffer {
         let line = self.lines.get(line_index).unwrap();
         let mut s = line.get_raw().to_string();
         s.insert(column, c);
-        self.lines[line_index] = Line::new(s);
+        self.replace_line(line_index, s);
         if log {
             self.log(Action::InsertChar(line_index, column, c));
         }
@@ -57,11 +59,11 @@ impl Buffer {
                 self.log(Action::DeleteChar(
                     line_index,
                     column,
-                    *s.chars().collect::<Vec<char>>().get(column).unwrap(),
+                    s.split_at(column).1.chars().next().unwrap(),
                 ));
             }
             s.remove(column);
-            self.lines[line_index] = Line::new(s);
+            self.replace_line(line_index, s);
             self.dirty = true;
         } else if line_index + 1 < self.get_line_count() {
             let line = line.get_clean_raw();
@@ -80,18 +82,95 @@ impl Buffer {
         }
     }
 
+    // Replaces the whole contents of a line, including its line ending
+    pub fn set_line(&mut self, line_index: usize, contents: String, log: bool) {
+        let old = self.lines.get(line_index).unwrap().clone();
+        if old.get_raw() == contents {
+            return;
+        }
+        if log {
+            self.log(Action::ReplaceLine(
+                line_index,
+                old,
+                Line::new(contents.clone()),
+            ));
+        }
+        self.replace_line(line_index, contents);
+        self.dirty = true;
+    }
+
+    // Replaces `count` whole lines starting at `line_index` with `lines`. `origins` has the
+    // replaced line each new one came from, counted from `line_index`, for the marks on it to
+    // follow, and can be left empty when the lines are new.
+    pub fn replace_lines(
+        &mut self,
+        line_index: usize,
+        count: usize,
+        lines: Vec<Line>,
+        origins: &[Option<usize>],
+        log: bool,
+    ) {
+        let old: Vec<Line> = self
+            .lines
+            .splice(line_index..line_index + count, lines.clone())
+            .collect();
+        self.mark_changed(line_index, line_index + lines.len());
+        for mark in self.marks.iter_mut() {
+            let y = mark.get_line();
+            if y < line_index {
+                continue;
+            }
+            let moved_to = origins
+                .iter()
+                .position(|origin| *origin == Some(y - line_index));
+            if y >= line_index + count {
+                mark.set_line(y + lines.len() - count);
+            } else if let Some(i) = moved_to {
+                mark.set_line(line_index + i);
+            } else if y >= line_index + lines.len() {
+                mark.set_line(line_index + lines.len().saturating_sub(1));
+            }
+        }
+        self.dedup_marks();
+        self.folds
+            .retain(|y| *y < line_index || *y >= line_index + count);
+        for y in self.folds.iter_mut().filter(|y| **y >= line_index + count) {
+            *y = *y + lines.len() - count;
+        }
+        if log {
+            self.log(Action::ReplaceLines(
+                line_index,
+                old,
+                lines,
+                origins.to_vec(),
+            ));
+        }
+        self.dirty = true;
+    }
+
+    pub fn get_lines(&self, start: usize, end: usize) -> &[Line] {
+        &self.lines[start..end]
+    }
+
     pub fn get_line(&self, line_index: usize) -> Option<&Line> {
         self.lines.get(line_index)
     }
 
     pub fn split_line(&mut self, line_index: usize, column: usize, log: bool) {
         let line = self.lines.get(line_index).unwrap();
-        let line_ending = line.get_raw().split_at(line.get_clean_raw().len()).1;
+        let line_ending = match line.get_line_ending() {
+            "" => self.get_line_ending(),
+            ending => ending,
+        }
+        .to_string();
         let raw = line.get_raw().to_string();
         let parts = raw.split_at(column);
-        let split_row = parts.0.to_string() + line_ending;
+        let split_row = parts.0.to_string() + &line_ending;
         self.replace_line(line_index, split_row);
         self.insert_line(line_index + 1, Line::new(parts.1.to_string()));
+        if column == 0 {
+            self.move_marks(line_index, line_index + 1);
+        }
         if log {
             self.log(Action::SplitLine(line_index, parts.0.len()));
         }
@@ -119,20 +198,17 @@ impl Buffer {
             std::cmp::Ordering::Greater => {
                 self.replace_line(
                     start_y,
-                    first_half.to_string() + lines.get(0).unwrap().get_raw(),
+                    first_half.to_string() + lines.first().unwrap().get_raw(),
                 );
                 for i in 1..lines.len() - 1 {
                     self.insert_line(start_y + i, lines.get(i).unwrap().clone());
                 }
-                if self.get_line_count() > start_y + lines.len() {
-                    self.insert_line(
-                        start_y + lines.len() - 1,
-                        Line::new(lines.last().unwrap().get_clean_raw() + &second_half),
-                    );
-                } else {
-                    self.lines.push(Line::new(
-                        lines.last().unwrap().get_clean_raw() + &second_half,
-                    ));
+                self.insert_line(
+                    start_y + lines.len() - 1,
+                    Line::new(lines.last().unwrap().get_clean_raw() + &second_half),
+                );
+                if start_x == 0 {
+                    self.move_marks(start_y, start_y + lines.len() - 1);
                 }
                 self.dirty = true;
                 (
@@ -143,11 +219,11 @@ impl Buffer {
             std::cmp::Ordering::Equal => {
                 self.replace_line(
                     start_y,
-                    first_half.to_string() + &lines.get(0).unwrap().get_clean_raw() + &second_half,
+                    first_half.to_string() + &lines.first().unwrap().get_clean_raw() + &second_half,
                 );
                 self.dirty = true;
                 (
-                    first_half.len() + lines.get(0).unwrap().get_clean_raw().len(),
+                    first_half.len() + lines.first().unwrap().get_clean_raw().len(),
                     start_y,
                 )
             }
@@ -240,6 +316,15 @@ impl Buffer {
         self.lines.len()
     }
 
+    // The line ending used by the file, defaulting to "\n" when there are no line breaks
+    pub fn get_line_ending(&self) -> &str {
+        self.lines
+            .iter()
+            .map(|l| l.get_line_ending())
+            .find(|e| !e.is_empty())
+            .unwrap_or("\n")
+    }
+
     pub fn get_all(&self) -> String {
         self.lines
             .iter()
@@ -249,45 +334,10 @@ impl Buffer {
     }
 
     pub fn undo(&mut self) {
+        self.close_group();
         if self.index > 0 {
             let last_item = self.history.get(self.index - 1).unwrap().clone();
-            match last_item {
-                Action::InsertChar(line_index, column, _) => {
-                    self.delete_char(line_index, column, false);
-                }
-                Action::DeleteChar(line_index, column, c) => {
-                    self.insert_char(line_index, column, c, false);
-                }
-                Action::InsertRegion((start_x, start_y), lines) => match lines.len().cmp(&1) {
-                    std::cmp::Ordering::Greater => {
-                        let end_y = start_y + lines.len() - 1;
-                        let end_x = lines.last().unwrap().get_clean_raw().len();
-                        self.remove_region((start_x, start_y), (end_x, end_y), false);
-                    }
-                    std::cmp::Ordering::Equal => {
-                        let end_y = start_y;
-                        let end_x = start_x + lines.get(0).unwrap().get_clean_raw().len();
-                        self.remove_region((start_x, start_y), (end_x, end_y), false);
-                    }
-                    _ => {}
-                },
-                Action::RemoveRegion(start, _, lines) => {
-                    self.insert_region(start, &lines, false);
-                }
-                Action::JoinLine(line_index, column) => {
-                    self.split_line(line_index, column, false);
-                }
-                Action::SplitLine(line_index, _) => {
-                    let line = self.get_line(line_index).unwrap().get_clean_raw();
-                    let other_line = self
-                        .lines
-                        .get(line_index + 1)
-                        .unwrap()
-                        .get_raw()
-                        .to_string();
-                    self.replace_line(line_index, line + &other_line);
-                }
-            }
+            self.undo_action(last_item);
             self.index -= 1;
             if self.index == 0 {
                 self.dirty = false;
@@ -296,36 +346,142 @@ impl Buffer {
     }
 
     pub fn redo(&mut self) {
+        self.close_group();
         if self.index < self.history.len() {
             let last_item = self.history.get(self.index).unwrap().clone();
-            match last_item {
-                Action::InsertChar(line_index, column, c) => {
-                    self.insert_char(line_index, column, c, false);
-                }
-                Action::DeleteChar(line_index, column, _) => {
-                    self.delete_char(line_index, column, false);
+            self.redo_action(last_item);
+            self.index += 1;
+        }
+    }
+
+    // Actions logged between begin_group and end_group are undone and redone as one
+    pub fn begin_group(&mut self) {
+        if self.group_depth == 0 {
+            self.group = Some(vec![]);
+        }
+        self.group_depth += 1;
+    }
+
+    pub fn end_group(&mut self) {
+        if self.group_depth == 0 {
+            return;
+        }
+        self.group_depth -= 1;
+        if self.group_depth == 0 {
+            if let Some(actions) = self.group.take() {
+                if !actions.is_empty() {
+                    self.log(Action::Group(actions));
                 }
-                Action::InsertRegion(start, lines) => {
-                    self.insert_region(start, &lines, false);
+            }
+        }
+    }
+
+    // Logs the actions of an open group so far, so undoing inside the group undoes them and
+    // not what came before. Later actions start a new group.
+    fn close_group(&mut self) {
+        if let Some(actions) = self.group.take() {
+            if !actions.is_empty() {
+                self.log(Action::Group(actions));
+            }
+            self.group = Some(vec![]);
+        }
+    }
+
+    fn undo_action(&mut self, action: Action) {
+        match action {
+            Action::InsertChar(line_index, column, _) => {
+                self.delete_char(line_index, column, false);
+            }
+            Action::DeleteChar(line_index, column, c) => {
+                self.insert_char(line_index, column, c, false);
+            }
+            Action::InsertRegion((start_x, start_y), lines) => match lines.len().cmp(&1) {
+                std::cmp::Ordering::Greater => {
+                    let end_y = start_y + lines.len() - 1;
+                    let end_x = lines.last().unwrap().get_clean_raw().len();
+                    self.remove_region((start_x, start_y), (end_x, end_y), false);
                 }
-                Action::RemoveRegion(start, end, _) => {
-                    self.remove_region(start, end, false);
+                std::cmp::Ordering::Equal => {
+                    let end_y = start_y;
+                    let end_x = start_x + lines.first().unwrap().get_clean_raw().len();
+                    self.remove_region((start_x, start_y), (end_x, end_y), false);
                 }
-                Action::JoinLine(line_index, _) => {
-                    let line = self.get_line(line_index).unwrap().get_clean_raw();
-                    let other_line = self
-                        .lines
-                        .get(line_index + 1)
-                        .unwrap()
-                        .get_raw()
-                        .to_string();
-                    self.replace_line(line_index, line + &other_line);
+                _ => {}
+            },
+            Action::RemoveRegion(start, _, lines) => {
+                self.insert_region(start, &lines, false);
+            }
+            Action::JoinLine(line_index, column) => {
+                self.split_line(line_index, column, false);
+            }
+            Action::SplitLine(line_index, _) => {
+                let line = self.get_line(line_index).unwrap().get_clean_raw();
+                let other_line = self
+                    .lines
+                    .get(line_index + 1)
+                    .unwrap()
+                    .get_raw()
+                    .to_string();
+                self.replace_line(line_index, line + &other_line);
+                self.remove_line(line_index + 1);
+            }
+            Action::ReplaceLine(line_index, old, _) => {
+                self.set_line(line_index, old.get_raw().to_string(), false);
+            }
+            Action::ReplaceLines(line_index, old, new, origins) => {
+                // Each old line comes back from wherever it was moved to
+                let origins: Vec<Option<usize>> = (0..old.len())
+                    .map(|i| origins.iter().position(|origin| *origin == Some(i)))
+                    .collect();
+                self.replace_lines(line_index, new.len(), old, &origins, false);
+            }
+            Action::Group(actions) => {
+                for action in actions.into_iter().rev() {
+                    self.undo_action(action);
                 }
-                Action::SplitLine(line_index, column) => {
-                    self.split_line(line_index, column, false);
+            }
+        }
+    }
+
+    fn redo_action(&mut self, action: Action) {
+        match action {
+            Action::InsertChar(line_index, column, c) => {
+                self.insert_char(line_index, column, c, false);
+            }
+            Action::DeleteChar(line_index, column, _) => {
+                self.delete_char(line_index, column, false);
+            }
+            Action::InsertRegion(start, lines) => {
+                self.insert_region(start, &lines, false);
+            }
+            Action::RemoveRegion(start, end, _) => {
+                self.remove_region(start, end, false);
+            }
+            Action::JoinLine(line_index, _) => {
+                let line = self.get_line(line_index).unwrap().get_clean_raw();
+                let other_line = self
+                    .lines
+                    .get(line_index + 1)
+                    .unwrap()
+                    .get_raw()
+                    .to_string();
+                self.replace_line(line_index, line + &other_line);
+                self.remove_line(line_index + 1);
+            }
+            Action::SplitLine(line_index, column) => {
+                self.split_line(line_index, column, false);
+            }
+            Action::ReplaceLine(line_index, _, new) => {
+                self.set_line(line_index, new.get_raw().to_string(), false);
+            }
+            Action::ReplaceLines(line_index, old, new, origins) => {
+                self.replace_lines(line_index, old.len(), new, &origins, false);
+            }
+            Action::Group(actions) => {
+                for action in actions {
+                    self.redo_action(action);
                 }
             }
-            self.index += 1;
         }
     }
 
@@ -337,19 +493,146 @@ impl Buffer {
         self.dirty = false;
     }
 
+    // The lines changed since clear_changed, for anything caching per-line state, as the first
+    // line changed and how many lines at the end of the buffer weren't
+    pub fn get_changed(&self) -> Option<(usize, usize)> {
+        self.changed
+    }
+
+    pub fn get_version(&self) -> usize {
+        self.version
+    }
+
+    pub fn clear_changed(&mut self) {
+        self.changed = None;
+    }
+
+    // Notes that the lines from `start` up to (not including) `end` changed
+    fn mark_changed(&mut self, start: usize, end: usize) {
+        self.version += 1;
+        let tail = self.lines.len() - end;
+        self.changed = Some(match self.changed {
+            Some((from, unchanged)) => (min(from, start), min(unchanged, tail)),
+            None => (start, tail),
+        });
+    }
+
+    pub fn toggle_bookmark(&mut self, line_index: usize) {
+        let bookmark = Mark::new(line_index, None);
+        if self.marks.contains(&bookmark) {
+            self.marks.retain(|m| *m != bookmark);
+        } else {
+            self.marks.push(bookmark);
+        }
+    }
+
+    pub fn set_mark(&mut self, line_index: usize, name: char) {
+        self.marks.retain(|m| m.get_name() != Some(name));
+        self.marks.push(Mark::new(line_index, Some(name)));
+    }
+
+    pub fn get_mark(&self, name: char) -> Option<usize> {
+        self.marks
+            .iter()
+            .find(|m| m.get_name() == Some(name))
+            .map(|m| m.get_line())
+    }
+
+    // All marks ordered by line
+    pub fn get_marks(&self) -> Vec<Mark> {
+        let mut marks = self.marks.clone();
+        marks.sort_by_key(|m| (m.get_line(), m.get_name()));
+        marks
+    }
+
+    pub fn clear_marks(&mut self) {
+        self.marks.clear();
+    }
+
+    fn move_marks(&mut self, from: usize, to: usize) {
+        for mark in self.marks.iter_mut().filter(|m| m.get_line() == from) {
+            mark.set_line(to);
+        }
+        self.dedup_marks();
+        for y in self.folds.iter_mut().filter(|y| **y == from) {
+            *y = to;
+        }
+    }
+
+    // Folds are stored as the lines that start a folded region
+    pub fn add_fold(&mut self, line_index: usize) {
+        if !self.folds.contains(&line_index) {
+            self.folds.push(line_index);
+        }
+    }
+
+    pub fn remove_fold(&mut self, line_index: usize) {
+        self.folds.retain(|y| *y != line_index);
+    }
+
+    pub fn is_folded(&self, line_index: usize) -> bool {
+        self.folds.contains(&line_index)
+    }
+
+    pub fn get_folds(&self) -> Vec<usize> {
+        let mut folds = self.folds.clone();
+        folds.sort_unstable();
+        folds
+    }
+
+    pub fn clear_folds(&mut self) {
+        self.folds.clear();
+    }
+
+    // Drops bookmarks that ended up on the same line after lines were removed
+    fn dedup_marks(&mut self) {
+        let mut seen = vec![];
+        self.marks.retain(|m| {
+            if m.get_name().is_some() {
+                return true;
+            }
+            let new = !seen.contains(&m.get_line());
+            seen.push(m.get_line());
+            new
+        });
+    }
+
+    // Marks and folds are shifted along with the lines they were set on
     fn insert_line(&mut self, line_index: usize, line: Line) {
         self.lines.insert(line_index, line);
+        self.mark_changed(line_index, line_index + 1);
+        for mark in self.marks.iter_mut().filter(|m| m.get_line() >= line_index) {
+            mark.set_line(mark.get_line() + 1);
+        }
+        for y in self.folds.iter_mut().filter(|y| **y >= line_index) {
+            *y += 1;
+        }
     }
 
+    // Marks on a removed line move to the line before it, which it was usually joined into
     fn remove_line(&mut self, line_index: usize) {
         self.lines.remove(line_index);
+        self.mark_changed(line_index, line_index);
+        for mark in self.marks.iter_mut().filter(|m| m.get_line() >= line_index) {
+            mark.set_line(max(mark.get_line(), 1) - 1);
+        }
+        self.dedup_marks();
+        self.folds.retain(|y| *y != line_index);
+        for y in self.folds.iter_mut().filter(|y| **y > line_index) {
+            *y -= 1;
+        }
     }
 
     fn replace_line(&mut self, line_index: usize, contents: String) {
         self.lines[line_index] = Line::new(contents);
+        self.mark_changed(line_index, line_index + 1);
     }
 
     fn log(&mut self, action: Action) {
+        if let Some(group) = self.group.as_mut() {
+            group.push(action);
+            return;
+        }
         if self.index < self.history.len() {
             self.history = self.history.split_at(self.index).0.to_vec();
         }
@@ -357,3 +640,36 @@ impl Buffer {
         self.index += 1;
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn buffer(lines: &[&str]) -> Buffer {
+        Buffer::new(lines.iter().map(|l| Line::new(l.to_string())).collect())
+    }
+
+    #[test]
+    fn multi_line_insert_before_the_last_line() {
+        let mut b = buffer(&["a\n", "b"]);
+        let region = [Line::new("x\n".to_string()), Line::new("y".to_string())];
+        assert_eq!(b.insert_region((0, 0), &region, true), (1, 1));
+        assert_eq!(b.get_all(), "x\nya\nb");
+        b.undo();
+        assert_eq!(b.get_all(), "a\nb");
+        b.redo();
+        assert_eq!(b.get_all(), "x\nya\nb");
+    }
+
+    #[test]
+    fn multi_line_insert_in_the_last_line() {
+        let mut b = buffer(&["a\n", "bc"]);
+        let region = [
+            Line::new("x\n".to_string()),
+            Line::new("y\n".to_string()),
+            Line::new("z".to_string()),
+        ];
+        b.insert_region((1, 1), &region, true);
+        assert_eq!(b.get_all(), "a\nbx\ny\nzc");
+    }
+}
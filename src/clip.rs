@@ -0,0 +1,92 @@
+use crate::line::Line;
+
+const BASE64: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+
+fn encode_base64(bytes: &[u8]) -> String {
+    let mut encoded = String::new();
+    for chunk in bytes.chunks(3) {
+        let n = chunk
+            .iter()
+            .enumerate()
+            .fold(0u32, |n, (i, b)| n | (*b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            if i <= chunk.len() {
+                encoded.push(BASE64[(n >> (18 - 6 * i) & 0x3F) as usize] as char);
+            } else {
+                encoded.push('=');
+            }
+        }
+    }
+    encoded
+}
+
+// Text taken from an editor by copy or cut
+#[derive(Clone)]
+pub struct Clip {
+    block: bool,
+    linewise: bool,
+    lines: Vec<Line>,
+}
+
+impl Clip {
+    pub fn new(lines: Vec<Line>, block: bool) -> Self {
+        Clip {
+            block,
+            linewise: false,
+            lines,
+        }
+    }
+
+    // Whole lines, each with its line break, that paste above or below the cursor line
+    pub fn new_lines(lines: Vec<Line>) -> Self {
+        Clip {
+            block: false,
+            linewise: true,
+            lines,
+        }
+    }
+
+    pub fn get_lines(&self) -> &[Line] {
+        &self.lines
+    }
+
+    // Block clips hold one line per row of a rectangular selection
+    pub fn is_block(&self) -> bool {
+        self.block
+    }
+
+    pub fn is_linewise(&self) -> bool {
+        self.linewise
+    }
+
+    // Adds text killed right after this one to its end, as consecutive kills do. Whole lines
+    // and block rows are added after the others rather than joined onto the last one.
+    pub fn append(&mut self, other: &Clip) {
+        let mut rest = other.lines.iter();
+        if let Some(last) = self.lines.last_mut() {
+            if !self.block && !other.block && last.get_line_ending().is_empty() {
+                if let Some(first) = rest.next() {
+                    *last = Line::new(last.get_raw().to_string() + first.get_raw());
+                }
+            }
+        }
+        self.lines.extend(rest.cloned());
+    }
+
+    pub fn get_text(&self) -> String {
+        if self.block {
+            let rows: Vec<String> = self.lines.iter().map(|l| l.get_clean_raw()).collect();
+            rows.join("\n")
+        } else {
+            self.lines.iter().map(|l| l.get_raw()).collect()
+        }
+    }
+
+    // The escape sequence asking the terminal to put the text on the system clipboard
+    pub fn to_osc52(&self) -> String {
+        format!(
+            "\x1b]52;c;{}\x07",
+            encode_base64(self.get_text().as_bytes())
+        )
+    }
+}
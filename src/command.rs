@@ -0,0 +1,326 @@
+use crate::editor::Movement;
+use crate::fuzzy::fuzzy_score;
+
+// Every named operation as (name, arguments, description), usable from the command palette
+// and bindable to keys. Movements can also be prefixed with `select-` to extend the selection.
+pub const COMMANDS: &[(&str, &str, &str)] = &[
+    ("palette", "", "Opens the command palette"),
+    (
+        "quit",
+        "",
+        "Closes the current editor, or the program in the last one",
+    ),
+    (
+        "save",
+        "[file_path]",
+        "Saves the current file, as file_path if given",
+    ),
+    ("open", "file_path", "Opens a file in the current editor"),
+    ("reload", "", "Reloads the current file from disk"),
+    ("new-editor", "", "Creates a new editor"),
+    ("prev-editor", "", "Switches to the previous editor"),
+    ("next-editor", "", "Switches to the next editor"),
+    ("undo", "", "Undoes the last change"),
+    ("redo", "", "Redoes the last undone change"),
+    ("copy", "", "Copies the selection"),
+    ("cut", "", "Cuts the selection"),
+    ("paste", "", "Pastes the last copied or cut text"),
+    (
+        "copy-to",
+        "register",
+        "Copies the selection to a register from a to z",
+    ),
+    (
+        "cut-to",
+        "register",
+        "Cuts the selection to a register from a to z",
+    ),
+    (
+        "paste-from",
+        "[register]",
+        "Pastes a register or recent copy, or lists them",
+    ),
+    (
+        "yank-pop",
+        "",
+        "Replaces the text just pasted with the previous kill",
+    ),
+    ("kill-line", "", "Cuts the rest of the line"),
+    ("set-mark", "", "Starts a selection that movements extend"),
+    ("cancel", "", "Drops the selection and the mark"),
+    ("search-forward", "", "Searches forward as you type"),
+    ("search-backward", "", "Searches backward as you type"),
+    (
+        "goto",
+        "line[:column]",
+        "Moves the cursor to a line and column",
+    ),
+    ("back", "", "Goes back through the jump list"),
+    ("forward", "", "Goes forward through the jump list"),
+    ("bracket", "", "Jumps to the matching bracket"),
+    ("mark", "[name]", "Toggles a bookmark or sets a named mark"),
+    ("jump", "name", "Moves to a named mark"),
+    ("marks", "", "Lists the marks of the current file"),
+    ("next-mark", "", "Moves to the next mark"),
+    ("prev-mark", "", "Moves to the previous mark"),
+    ("clear-marks", "", "Removes every mark of the current file"),
+    ("fold", "", "Folds the region around the cursor"),
+    ("unfold", "", "Unfolds the fold on the current line"),
+    ("fold-all", "", "Folds every region"),
+    ("unfold-all", "", "Unfolds every region"),
+    (
+        "wrap",
+        "[off|window|column]",
+        "Toggles or sets soft wrapping",
+    ),
+    (
+        "indent",
+        "[tabs|spaces [width]]",
+        "Indents the selection, or converts the indentation",
+    ),
+    ("dedent", "", "Dedents the selected lines"),
+    ("duplicate", "", "Duplicates the selection or current line"),
+    ("move-up", "", "Moves the current or selected lines up"),
+    ("move-down", "", "Moves the current or selected lines down"),
+    (
+        "join",
+        "",
+        "Joins the selected lines or the current line with the next",
+    ),
+    ("delete-lines", "", "Deletes the current or selected lines"),
+    ("sort", "", "Sorts the selected lines"),
+    ("reverse", "", "Reverses the selected lines"),
+    ("unique", "", "Removes duplicates from the selected lines"),
+    (
+        "comment",
+        "",
+        "Comments or uncomments the current or selected lines",
+    ),
+    ("newline", "", "Breaks the line keeping the indentation"),
+    ("backspace", "", "Deletes the character before the cursor"),
+    ("delete", "", "Deletes the character after the cursor"),
+    ("delete-word-left", "", "Deletes the word before the cursor"),
+    ("delete-word-right", "", "Deletes the word after the cursor"),
+    ("block-left", "", "Extends a rectangular selection left"),
+    ("block-right", "", "Extends a rectangular selection right"),
+    ("block-up", "", "Extends a rectangular selection up"),
+    ("block-down", "", "Extends a rectangular selection down"),
+    (
+        "syntax",
+        "[name]",
+        "Sets the syntax, or goes back to the detected one",
+    ),
+    (
+        "theme",
+        "[name]",
+        "Applies a theme, or lists the themes to preview",
+    ),
+    ("save-theme", "", "Saves the current theme to settings.toml"),
+    (
+        "reload-config",
+        "",
+        "Reloads the settings, themes and syntaxes",
+    ),
+    ("bindings", "", "Lists the active key bindings"),
+    ("record-macro", "", "Starts or stops recording a macro"),
+    (
+        "play-macro",
+        "[name] [count]",
+        "Plays the last recorded or a saved macro",
+    ),
+    (
+        "play-macro-lines",
+        "[name]",
+        "Plays a macro from the start of each selected line",
+    ),
+    (
+        "save-macro",
+        "name",
+        "Saves the last recorded macro for later sessions",
+    ),
+    ("macros", "", "Lists the saved macros"),
+];
+
+// Movement commands, each also available as `select-<name>`
+pub const MOVEMENTS: &[&str] = &[
+    "left",
+    "right",
+    "up",
+    "down",
+    "word-left",
+    "word-right",
+    "subword-left",
+    "subword-right",
+    "home",
+    "end",
+    "page-up",
+    "page-down",
+    "scroll-up",
+    "scroll-down",
+    "file-start",
+    "file-end",
+];
+
+pub fn get_movement(name: &str) -> Option<Movement> {
+    Some(match name {
+        "left" => Movement::Relative(-1, 0),
+        "right" => Movement::Relative(1, 0),
+        "up" => Movement::Relative(0, -1),
+        "down" => Movement::Relative(0, 1),
+        "word-left" => Movement::WordLeft,
+        "word-right" => Movement::WordRight,
+        "subword-left" => Movement::SubwordLeft,
+        "subword-right" => Movement::SubwordRight,
+        "home" => Movement::Home,
+        "end" => Movement::End,
+        "page-up" => Movement::PageUp,
+        "page-down" => Movement::PageDown,
+        "scroll-up" => Movement::ScrollUp(1),
+        "scroll-down" => Movement::ScrollDown(1),
+        "file-start" => Movement::BegFile,
+        "file-end" => Movement::EndFile,
+        _ => return None,
+    })
+}
+
+pub fn is_command(name: &str) -> bool {
+    let movement = name.strip_prefix("select-").unwrap_or(name);
+    COMMANDS.iter().any(|(command, _, _)| *command == name) || MOVEMENTS.contains(&movement)
+}
+
+// The commands whose names fuzzily match `query`, best first
+pub fn find_commands(query: &str) -> Vec<&'static (&'static str, &'static str, &'static str)> {
+    let mut matches: Vec<(usize, &(&str, &str, &str))> = COMMANDS
+        .iter()
+        .filter_map(|command| fuzzy_score(query, command.0).map(|score| (score, command)))
+        .collect();
+    matches.sort_by(|a, b| b.0.cmp(&a.0).then(a.1 .0.len().cmp(&b.1 .0.len())));
+    matches.into_iter().map(|(_, command)| command).collect()
+}
+
+// Splits a command line into words at whitespace as (start, word). Single or double quotes
+// starting a word keep whitespace in it, with `\"` for a quote inside double quotes, while
+// quotes inside a word (`it's.txt`) are kept as typed. Also returns whether a quote was left
+// open.
+pub fn split_words(line: &str) -> (Vec<(usize, String)>, bool) {
+    let mut words = vec![];
+    let mut word: Option<(usize, String)> = None;
+    let mut quote = None;
+    let mut chars = line.char_indices().peekable();
+    while let Some((i, c)) = chars.next() {
+        if quote.is_none() && c.is_whitespace() {
+            words.extend(word.take());
+            continue;
+        }
+        let starts_word = word.is_none();
+        let text = &mut word.get_or_insert((i, String::new())).1;
+        match (quote, c) {
+            (None, '"') | (None, '\'') if starts_word => quote = Some(c),
+            (Some(q), c) if c == q => quote = None,
+            (Some('"'), '\\') if chars.peek().is_some_and(|(_, c)| *c == '"') => {
+                text.push('"');
+                chars.next();
+            }
+            (_, c) => text.push(c),
+        }
+    }
+    words.extend(word);
+    (words, quote.is_some())
+}
+
+// Splits a command line into the command name and its arguments
+pub fn parse_command(line: &str) -> Result<(String, Vec<String>), String> {
+    let (words, open) = split_words(line);
+    if open {
+        return Err("Missing closing quote".to_string());
+    }
+    let mut words = words.into_iter().map(|(_, word)| word);
+    Ok((words.next().unwrap_or_default(), words.collect()))
+}
+
+// Quotes a word for a command line if it needs it, leaving the quote open if `close` isn't set
+// so more can be typed
+pub fn quote_word(word: &str, close: bool) -> String {
+    let plain = |c: char| !c.is_whitespace() && c != '"' && c != '\'';
+    if !word.is_empty() && word.chars().all(plain) {
+        return word.to_string();
+    }
+    let quoted = format!("\"{}", word.replace('"', "\\\""));
+    if close {
+        quoted + "\""
+    } else {
+        quoted
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn words(line: &str) -> Vec<String> {
+        split_words(line)
+            .0
+            .into_iter()
+            .map(|(_, word)| word)
+            .collect()
+    }
+
+    #[test]
+    fn splits_at_whitespace_outside_quotes() {
+        assert_eq!(
+            split_words("save  a.txt"),
+            (
+                vec![(0, "save".to_string()), (6, "a.txt".to_string())],
+                false
+            )
+        );
+        assert_eq!(words("save \"my file.txt\""), vec!["save", "my file.txt"]);
+        assert_eq!(words("open 'a b' c"), vec!["open", "a b", "c"]);
+        assert_eq!(words("open \"\""), vec!["open", ""]);
+        assert_eq!(words(""), Vec::<String>::new());
+    }
+
+    #[test]
+    fn escaped_quotes_and_apostrophes() {
+        assert_eq!(words("save \"a \\\"b\\\"\""), vec!["save", "a \"b\""]);
+        assert_eq!(words("save \"it's here\""), vec!["save", "it's here"]);
+        assert_eq!(words("open it's.txt"), vec!["open", "it's.txt"]);
+        assert_eq!(words("open a\"b"), vec!["open", "a\"b"]);
+    }
+
+    #[test]
+    fn unclosed_quotes() {
+        assert_eq!(
+            split_words("save \"my fi"),
+            (
+                vec![(0, "save".to_string()), (5, "my fi".to_string())],
+                true
+            )
+        );
+        assert_eq!(
+            parse_command("open 'a.txt"),
+            Err("Missing closing quote".to_string())
+        );
+        assert!(parse_command("open it's.txt").is_ok());
+    }
+
+    #[test]
+    fn parses_name_and_arguments() {
+        assert_eq!(
+            parse_command("goto 12:4"),
+            Ok(("goto".to_string(), vec!["12:4".to_string()]))
+        );
+        assert_eq!(parse_command("  "), Ok((String::new(), vec![])));
+    }
+
+    #[test]
+    fn quotes_words_that_need_it() {
+        assert_eq!(quote_word("a.txt", true), "a.txt");
+        assert_eq!(quote_word("my file", true), "\"my file\"");
+        assert_eq!(quote_word("my file", false), "\"my file");
+        assert_eq!(quote_word("", true), "\"\"");
+        for word in ["a \"b\"", "it's", "'a", "tab\there"] {
+            assert_eq!(words(&quote_word(word, true)), vec![word]);
+        }
+    }
+}
@@ -0,0 +1,68 @@
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+
+// Comment markers for a syntax; line comments are preferred when both exist
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct CommentTokens {
+    pub line: Option<String>,
+    pub block: Option<(String, String)>,
+}
+
+// Used when a syntax has no TM_COMMENT_* metadata
+fn get_builtin_tokens(name: &str) -> Option<CommentTokens> {
+    let line = |token: &str| {
+        Some(CommentTokens {
+            line: Some(token.to_string()),
+            block: None,
+        })
+    };
+    let block = |start: &str, end: &str| {
+        Some(CommentTokens {
+            line: None,
+            block: Some((start.to_string(), end.to_string())),
+        })
+    };
+    match name {
+        "Rust" | "C" | "C++" | "C#" | "Objective-C" | "Objective-C++" | "Java" | "JavaScript"
+        | "TypeScript" | "Go" | "Scala" | "Swift" | "Kotlin" | "D" | "Groovy" | "ActionScript"
+        | "JSON" => line("// "),
+        "Python"
+        | "Ruby"
+        | "Shell-Unix-Generic"
+        | "Bourne Again Shell (bash)"
+        | "Perl"
+        | "R"
+        | "YAML"
+        | "TOML"
+        | "Makefile"
+        | "Dockerfile"
+        | "CMake"
+        | "Nim"
+        | "Elixir"
+        | "Julia"
+        | "Tcl" => line("# "),
+        "SQL" | "Lua" | "Haskell" | "Ada" | "Elm" => line("-- "),
+        "Lisp" | "Clojure" | "Scheme" => line(";; "),
+        "LaTeX" | "TeX" | "Erlang" | "MATLAB" => line("% "),
+        "VimL" => line("\" "),
+        "Batch File" => line("REM "),
+        "HTML" | "XML" | "Markdown" => block("<!-- ", " -->"),
+        "CSS" => block("/* ", " */"),
+        "OCaml" => block("(* ", " *)"),
+        _ => None,
+    }
+}
+
+pub fn get_comment_tokens(syntax: &SyntaxReference, syntaxes: &SyntaxSet) -> CommentTokens {
+    let metadata = syntaxes.metadata().metadata_for_scope(&[syntax.scope]);
+    let tokens = CommentTokens {
+        line: metadata.line_comment().map(|s| s.to_string()),
+        block: metadata
+            .block_comment()
+            .map(|(start, end)| (start.to_string(), end.to_string())),
+    };
+    if tokens.line.is_some() || tokens.block.is_some() {
+        tokens
+    } else {
+        get_builtin_tokens(&syntax.name).unwrap_or_default()
+    }
+}
@@ -1,5 +1,6 @@
 #![allow(unused)]
 
+use std::cell::RefCell;
 use std::cmp::{max, min};
 use std::fs::File;
 use std::io::prelude::*;
@@ -9,22 +10,31 @@ use std::path::{Path, PathBuf};
 use chrono::Local;
 use crossterm::{execute, style::Color, style::SetBackgroundColor, style::SetForegroundColor};
 use syntect::{
-    easy::HighlightLines,
-    highlighting::{Color as SynColor, Style, StyleModifier, Theme},
-    parsing::SyntaxSet,
-    util::modify_range,
+    highlighting::{Color as SynColor, FontStyle, Style, StyleModifier, Theme},
+    parsing::{SyntaxReference, SyntaxSet},
+    util::{modify_range, split_at},
 };
 use tui::{
     buffer::Buffer as TuiBuffer,
     layout::{Constraint, Direction, Layout, Rect},
-    style::{Color as TuiColor, Style as TuiStyle},
+    style::{Color as TuiColor, Modifier, Style as TuiStyle},
     text::{Span, Spans},
     widgets::{Block, Borders, Paragraph, Widget, Wrap},
 };
 
 use crate::buffer::Buffer;
-use crate::line::Line;
+use crate::clip::Clip;
+use crate::comment::get_comment_tokens;
+use crate::editorconfig::{Charset, EditorConfig};
+use crate::fold::{bracket_folds, indent_folds, FoldMethod};
+use crate::highlight::{HighlightWorker, HighlightedLine};
+use crate::indentation::{IndentOverrides, IndentStyle};
+use crate::line::{render_index_at_col, split_lines, Line};
 use crate::render_config::RenderConfig;
+use crate::settings::Settings;
+use crate::syntax::{detect_syntax, find_syntax, SyntaxOverrides};
+use crate::word::{next_word_boundary, prev_word_boundary, word_at};
+use crate::wrap::{wrap_rows, WrapMode, WrapOverrides};
 
 pub enum Movement {
     BegFile,
@@ -38,37 +48,154 @@ pub enum Movement {
     Absolute(usize, usize),
     AbsoluteScreen(u16, u16),
     Relative(isize, isize),
+    WordLeft,
+    WordRight,
+    SubwordLeft,
+    SubwordRight,
 }
 
+pub const OPENERS: &str = "{([";
+pub const CLOSERS: &str = "})]";
+
+// Characters that are closed automatically when typed
+fn get_pair_closer(c: char) -> Option<char> {
+    match c {
+        '"' | '\'' => Some(c),
+        _ => get_closer(c),
+    }
+}
+
+fn get_closer(opener: char) -> Option<char> {
+    OPENERS.find(opener).and_then(|i| CLOSERS.chars().nth(i))
+}
+
+// The (first, last) range of hidden lines containing line `y`
+fn get_hidden_range(hidden: &[(usize, usize)], y: usize) -> Option<(usize, usize)> {
+    hidden
+        .iter()
+        .find(|(start, end)| *start <= y && y <= *end)
+        .copied()
+}
+
+// Number of lines from `from` up to (but not including) `to` that aren't hidden
+fn count_visible(hidden: &[(usize, usize)], from: usize, to: usize) -> usize {
+    let hidden_count: usize = hidden
+        .iter()
+        .map(|(start, end)| min(end + 1, to).saturating_sub(max(*start, from)))
+        .sum();
+    to.saturating_sub(from) - hidden_count
+}
+
+// Index of the wrapped row (given by its starting byte indices) that byte `x` is drawn on
+fn get_row_index(rows: &[usize], x: usize) -> usize {
+    rows.iter().rposition(|start| *start <= x).unwrap_or(0)
+}
+
+// Styles `text` like highlighted ranges of the line, character by character, so a line
+// changed since it was highlighted keeps its old styles with any new text in the last one
+fn fit_ranges(ranges: &[(Style, String)], text: &str) -> Vec<(Style, String)> {
+    let mut chars = text.chars();
+    let mut styled: Vec<(Style, String)> = ranges
+        .iter()
+        .map(|(style, old)| (*style, chars.by_ref().take(old.chars().count()).collect()))
+        .filter(|(_, part): &(Style, String)| !part.is_empty())
+        .collect();
+    let rest: String = chars.collect();
+    if !rest.is_empty() {
+        match styled.last_mut() {
+            Some((_, last)) => last.push_str(&rest),
+            None => styled.push((ranges[0].0, rest)),
+        }
+    }
+    styled
+}
+
+fn to_tui_spans<'a>(line: &[(Style, &'a str)]) -> Spans<'a> {
+    Spans::from(
+        line.iter()
+            .map(|(style, text)| {
+                let fg_rgb = style.foreground;
+                let bg_rgb = style.background;
+                let mut modifier = Modifier::empty();
+                if style.font_style.contains(FontStyle::BOLD) {
+                    modifier |= Modifier::BOLD;
+                }
+                if style.font_style.contains(FontStyle::ITALIC) {
+                    modifier |= Modifier::ITALIC;
+                }
+                if style.font_style.contains(FontStyle::UNDERLINE) {
+                    modifier |= Modifier::UNDERLINED;
+                }
+                Span {
+                    content: std::borrow::Cow::Borrowed(text),
+                    style: TuiStyle::default()
+                        .fg(TuiColor::Rgb(fg_rgb.r, fg_rgb.g, fg_rgb.b))
+                        .bg(TuiColor::Rgb(bg_rgb.r, bg_rgb.g, bg_rgb.b))
+                        .add_modifier(modifier),
+                }
+            })
+            .collect::<Vec<Span>>(),
+    )
+}
+
+// Positions the jump list remembers are a file with a cursor position in it
+type Jump = (Option<PathBuf>, usize, usize);
+const MAX_JUMPS: usize = 100;
+
+// Fold regions with the buffer version, fold method and tab size they were found for
+type FoldCache = ((usize, FoldMethod, usize), Vec<(usize, usize)>);
+
 #[derive(Default)]
 pub struct Editor {
+    auto_closers: Vec<(usize, usize)>,
+    // Screen column of the block selection's cursor side, which can be past the end of the
+    // cursor's line
+    block_col: Option<usize>,
+    block_highlight: bool,
     buffer: Buffer,
+    charset: Option<Charset>,
     col_offset: usize,
     confirm_dirty: bool,
     cx: usize,
     cy: usize,
+    default_indent_style: IndentStyle,
+    default_tab_size: usize,
+    default_wrap: WrapMode,
+    detected_syntax: Option<String>,
     pub draw_area: Rect,
+    editorconfig: EditorConfig,
     file_path: Option<PathBuf>,
+    fold_method: FoldMethod,
+    fold_regions: RefCell<Option<FoldCache>>,
+    // (version, first line changed) of edits the highlighting thread may not have seen when
+    // it highlighted lines it hasn't sent yet
+    highlight_edits: Vec<(usize, usize)>,
+    highlight_syntax: Option<String>,
+    highlight_version: usize,
+    highlight_worker: Option<HighlightWorker>,
     highlighting: bool,
+    highlights: Vec<HighlightedLine>,
     hx: usize,
     hy: usize,
+    indent_overrides: IndentOverrides,
+    indent_style: IndentStyle,
+    jump_index: usize,
+    jumps: Vec<Jump>,
+    line_numbers: bool,
     message: Option<String>,
+    mode: Option<String>,
     prompt_message: Option<String>,
     render_opts: RenderConfig,
     row_offset: usize,
+    rulers: Vec<usize>,
     rx: usize,
+    scrolloff: usize,
+    syntax_name: Option<String>,
+    syntax_overrides: SyntaxOverrides,
     syntaxes: SyntaxSet,
     theme: Theme,
-}
-
-// Essentially just replaces tabs with 4 spaces
-fn convert_cx_to_rx(line: &Line, cx: usize, render_opts: &RenderConfig) -> usize {
-    if cx >= line.get_clean_raw().len() {
-        line.render(render_opts).len()
-    } else {
-        let raw = line.get_raw().split_at(cx).0;
-        raw.matches('\t').count() * 3 + cx
-    }
+    wrap: WrapMode,
+    wrap_overrides: WrapOverrides,
 }
 
 impl Widget for &mut Editor {
@@ -98,92 +225,179 @@ impl Widget for &mut Editor {
             .constraints([Constraint::Min(inner_area.height - 2), Constraint::Min(2)])
             .split(inner_area);
         self.draw_area = chunks[0];
-        let syntax = self
-            .file_path
-            .as_ref()
-            .and_then(|f| f.extension())
-            .and_then(|e| self.syntaxes.find_syntax_by_extension(&e.to_string_lossy()));
-        let lines = self.buffer.get_line_count();
-        let max_gutter_size = (if lines < 2 { 2 } else { lines + 1 } as f32)
-            .log10()
-            .ceil();
-        for y in 0..self.draw_area.height as usize {
-            if let Some(line) = self
-                .buffer
-                .get_line(self.row_offset + y)
-                .map(|l| l.render(&self.render_opts))
-            {
-                let line_number = self.row_offset + y;
-                let gutter_size = (if line_number < 2 { 2 } else { line_number + 2 } as f32)
-                    .log10()
-                    .ceil();
-                let raw_line = if (self.col_offset >= line.len()) {
-                    ""
-                } else {
-                    line.split_at(self.col_offset).1
+        self.receive_highlights();
+        let syntax = self.get_syntax();
+        let tab = " ".repeat(self.render_opts.tab_size);
+        let hidden = self.get_hidden_lines();
+        // Only a match on screen is shown, so the scan stops at the lines drawn
+        let last_drawn =
+            self.step_visible(&hidden, self.row_offset, self.draw_area.height as isize);
+        let brackets = self.get_bracket_pair((self.row_offset, last_drawn));
+        let bracket_style = StyleModifier {
+            background: self
+                .theme
+                .settings
+                .brackets_background
+                .or(self.theme.settings.selection),
+            foreground: self.theme.settings.brackets_foreground,
+            font_style: Some(FontStyle::BOLD | FontStyle::UNDERLINE),
+        };
+        let ruler_color = self
+            .theme
+            .settings
+            .guide
+            .or(self.theme.settings.line_highlight)
+            .map(|c| TuiColor::Rgb(c.r, c.g, c.b));
+        let marks = self.buffer.get_marks();
+        let gutter_width = self.get_gutter_width();
+        let number_width = gutter_width - 2;
+        let mut line_number = self.row_offset;
+        let mut y = 0;
+        while y < self.draw_area.height as usize {
+            if let Some(buffer_line) = self.buffer.get_line(line_number) {
+                let line = buffer_line.render(&self.render_opts);
+                let skip = render_index_at_col(&line, self.col_offset);
+                let raw_line = line.split_at(skip).1;
+                // Maps a byte index into the buffer line to one into raw_line
+                let to_span = |cx: usize| {
+                    min(
+                        buffer_line
+                            .get_render_index(cx, &self.render_opts)
+                            .saturating_sub(skip),
+                        raw_line.len(),
+                    )
                 };
 
-                let mut h = syntax.map(|s| HighlightLines::new(s, &self.theme));
-                let mut line = h
-                    .map(|mut h| h.highlight(raw_line, &self.syntaxes))
-                    .unwrap_or_else(|| vec![(default_style, raw_line)]);
+                // Lines the highlighting thread hasn't got to yet are drawn in the default style,
+                // and lines changed since it did keep their old styles until it catches up
+                let rendered: Vec<(Style, String)> = match self.highlights.get(line_number) {
+                    Some(highlighted) if syntax.is_some() && !highlighted.ranges.is_empty() => {
+                        fit_ranges(&highlighted.ranges, buffer_line.get_raw())
+                            .into_iter()
+                            .map(|(style, text)| {
+                                let text = text.replace("\r", "").replace("\n", "");
+                                (style, text.replace('\t', &tab))
+                            })
+                            .collect()
+                    }
+                    _ => vec![(default_style, line.clone())],
+                };
+                let rendered: Vec<(Style, &str)> = rendered
+                    .iter()
+                    .map(|(style, text)| (*style, text.as_str()))
+                    .collect();
+                let mut line = split_at(&rendered, skip).1;
                 if self.highlighting
                     && line_number >= min(self.cy, self.hy)
                     && line_number <= max(self.cy, self.hy)
                 {
-                    if self.cy == self.hy {
-                        if self.cx < self.hx {
-                            line = modify_range(&line, self.cx..self.hx, highlight_style);
-                        } else {
-                            line = modify_range(&line, self.hx..self.cx, highlight_style);
-                        }
+                    let (start, end) = if self.block_highlight {
+                        let (left, right) = self.get_block_cols();
+                        (
+                            buffer_line.get_byte_index(left, &self.render_opts),
+                            buffer_line.get_byte_index(right, &self.render_opts),
+                        )
+                    } else if self.cy == self.hy {
+                        (min(self.cx, self.hx), max(self.cx, self.hx))
                     } else if line_number == min(self.cy, self.hy) {
                         if self.cy < self.hy {
-                            line = modify_range(&line, self.cx..raw_line.len(), highlight_style);
+                            (self.cx, buffer_line.get_raw().len())
                         } else {
-                            line = modify_range(&line, self.hx..raw_line.len(), highlight_style);
+                            (self.hx, buffer_line.get_raw().len())
                         }
                     } else if line_number == max(self.cy, self.hy) {
                         if self.cy < self.hy {
-                            line = modify_range(&line, 0..self.hx, highlight_style);
+                            (0, self.hx)
                         } else {
-                            line = modify_range(&line, 0..self.cx, highlight_style);
+                            (0, self.cx)
                         }
                     } else {
-                        line = modify_range(&line, 0..raw_line.len(), highlight_style);
+                        (0, buffer_line.get_raw().len())
+                    };
+                    line = modify_range(&line, to_span(start)..to_span(end), highlight_style);
+                }
+                if let Some((a, b)) = brackets {
+                    for (x, _) in [a, b].iter().filter(|(_, y)| *y == line_number) {
+                        line = modify_range(&line, to_span(*x)..to_span(x + 1), bracket_style);
                     }
                 }
-                let line = Spans::from(
-                    line.iter()
-                        .map(|(style, text)| {
-                            let fg_rgb = style.foreground;
-                            let bg_rgb = style.background;
-                            Span {
-                                content: std::borrow::Cow::Borrowed(text),
-                                style: TuiStyle::default()
-                                    .fg(TuiColor::Rgb(fg_rgb.r, fg_rgb.g, fg_rgb.b))
-                                    .bg(TuiColor::Rgb(bg_rgb.r, bg_rgb.g, bg_rgb.b)),
+                // Marked lines show the mark's name (or `*` for bookmarks) in place of the pipe
+                let marker = marks
+                    .iter()
+                    .find(|m| m.get_line() == line_number)
+                    .map(|m| m.get_name().unwrap_or('*'))
+                    .unwrap_or('|');
+                let folded = if hidden.iter().any(|(start, _)| *start == line_number + 1) {
+                    '+'
+                } else {
+                    ' '
+                };
+                let rows = self.get_line_rows(line_number);
+                for (i, row_start) in rows.iter().enumerate() {
+                    if y >= self.draw_area.height as usize {
+                        break;
+                    }
+                    let (row, rest) = match rows.get(i + 1) {
+                        Some(next) => split_at(&line, to_span(*next) - to_span(*row_start)),
+                        None => (line.clone(), vec![]),
+                    };
+                    line = rest;
+                    // Wrapped rows after the first are marked with `↪` instead of a line number
+                    let gutter = match (i, self.line_numbers) {
+                        (0, true) => format!(
+                            "{:>width$}{}{}",
+                            line_number + 1,
+                            marker,
+                            folded,
+                            width = number_width
+                        ),
+                        (0, false) => format!("{}{}", marker, folded),
+                        (_, true) => format!("{:>width$}| ", "\u{21aa}", width = number_width),
+                        (_, false) => "\u{21aa} ".to_string(),
+                    };
+                    buf.set_string(
+                        self.draw_area.x,
+                        self.draw_area.y + y as u16,
+                        gutter,
+                        TuiStyle::default(),
+                    );
+                    buf.set_spans(
+                        self.draw_area.x + gutter_width as u16,
+                        self.draw_area.y + y as u16,
+                        &to_tui_spans(&row),
+                        self.draw_area.width - gutter_width as u16,
+                    );
+                    // Rulers tint the column just past their width
+                    for ruler in &self.rulers {
+                        let x = (gutter_width + ruler).checked_sub(self.col_offset);
+                        if let (Some(x), Some(color)) = (x, ruler_color) {
+                            if x >= gutter_width && x < self.draw_area.width as usize {
+                                buf.get_mut(
+                                    self.draw_area.x + x as u16,
+                                    self.draw_area.y + y as u16,
+                                )
+                                .set_bg(color);
                             }
-                        })
-                        .collect::<Vec<Span>>(),
-                );
-                buf.set_string(
-                    self.draw_area.x,
-                    self.draw_area.y + y as u16,
-                    format!(
-                        "{}{}| ",
-                        " ".repeat((max_gutter_size - gutter_size) as usize),
-                        line_number + 1
-                    ),
-                    TuiStyle::default(),
-                );
-                buf.set_spans(
-                    self.draw_area.x + max_gutter_size as u16 + 2, //+1 for pipe and space
-                    self.draw_area.y + y as u16,
-                    &line,
-                    self.draw_area.width as u16 - max_gutter_size as u16 - 2, // -1 for pipe and space
-                );
+                        }
+                    }
+                    y += 1;
+                }
+            }
+            let next = self.step_visible(&hidden, line_number, 1);
+            if next == line_number {
+                break;
             }
+            line_number = next;
+        }
+
+        // Ask for a screen's worth of lines past the last one drawn so scrolling finds them ready,
+        // or every line when folding needs to know where the strings and comments are
+        if let Some(worker) = &self.highlight_worker {
+            let mut until = line_number + 1 + self.draw_area.height as usize;
+            if self.fold_method == FoldMethod::Brackets {
+                until = max(until, self.buffer.get_line_count());
+            }
+            worker.show(until);
         }
 
         // Draw the message
@@ -195,7 +409,17 @@ impl Widget for &mut Editor {
         ))
         .block(
             Block::default()
-                .title(format!("L{}:C{} {}", self.cy + 1, self.cx + 1, "Message "))
+                .title(format!(
+                    "{}L{}:C{} {} {} {}",
+                    self.mode
+                        .as_ref()
+                        .map_or(String::new(), |m| format!("-- {} -- ", m)),
+                    self.cy + 1,
+                    self.cx + 1,
+                    self.indent_style,
+                    self.get_syntax().map_or("Plain Text", |s| s.name.as_str()),
+                    "Message "
+                ))
                 .borders(Borders::TOP),
         )
         .wrap(Wrap { trim: true });
@@ -207,60 +431,157 @@ impl Editor {
     pub fn new(syntaxes: SyntaxSet) -> Self {
         Editor {
             buffer: Buffer::new(vec![Line::new("Redit version 0.1.0".to_string())]),
+            default_tab_size: RenderConfig::default().tab_size,
+            line_numbers: true,
             syntaxes,
             ..Editor::default()
         }
     }
 
     pub fn open_file(&mut self, file_name: &dyn AsRef<Path>) -> std::io::Result<()> {
-        let file = File::open(file_name)?;
-        let mut reader = BufReader::new(file);
-        let mut rows = vec![];
-
-        loop {
-            let mut temp = String::new();
-            let n = reader.read_line(&mut temp)?;
-            rows.push(Line::new(temp));
-            if n == 0 {
-                break;
-            }
+        if self.file_path.is_some() {
+            self.record_jump();
         }
+        self.syntax_name = None;
+        self.load_file(file_name)?;
+        self.move_cursor(Movement::BegFile, false);
+        self.set_message(&"File opened.");
+        Ok(())
+    }
 
+    fn load_file(&mut self, file_name: &dyn AsRef<Path>) -> std::io::Result<()> {
         let file_name = file_name.as_ref().to_path_buf();
+        let editorconfig = EditorConfig::load(&file_name);
+        let bytes = std::fs::read(&file_name)?;
+        let charset = Charset::detect(&bytes).or(editorconfig.charset);
+        let contents = Charset::decode(charset, &bytes)?;
+        let mut rows: Vec<Line> = split_lines(&contents)
+            .into_iter()
+            .map(|l| Line::new(l.to_string()))
+            .collect();
+        if contents.is_empty() || contents.ends_with('\n') || contents.ends_with('\r') {
+            rows.push(Line::new(String::new()));
+        }
+
+        self.indent_style = IndentStyle::detect(&rows).unwrap_or(self.default_indent_style);
         self.buffer = Buffer::new(rows);
+        self.fold_regions.get_mut().take();
+        self.charset = charset;
+        self.render_opts.tab_size = editorconfig.tab_width.unwrap_or(self.default_tab_size);
+        self.editorconfig = editorconfig;
         self.file_path = Some(file_name);
-        self.set_message(&"File opened.");
+        self.wrap = self.default_wrap;
+        self.apply_indent_settings();
+        self.apply_wrap_settings();
+        self.detect_syntax();
+        self.reset_highlights(false);
         self.confirm_dirty = false;
+        self.auto_closers.clear();
+        self.move_cursor(Movement::Absolute(self.cx, self.cy), false);
 
         Ok(())
     }
 
     pub fn save(&mut self) -> std::io::Result<bool> {
-        if let Some(file_path) = &self.file_path {
-            let file = std::fs::OpenOptions::new()
-                .truncate(true)
-                .write(true)
-                .create(true)
-                .open(file_path)?;
-            let mut br = std::io::BufWriter::new(file);
-
-            let contents = self.buffer.get_all();
-            br.write_all(contents.as_bytes())?;
-            self.set_message(&"File saved.");
-            self.buffer.set_clean();
-            self.confirm_dirty = false;
-            Ok(true)
-        } else {
-            Ok(false)
+        if self.file_path.is_none() {
+            return Ok(false);
+        }
+        self.apply_save_settings(None);
+        self.write_file()?;
+        self.set_message(&"File saved.");
+        Ok(true)
+    }
+
+    // Saves while typing is paused, keeping the whitespace on the cursor's line and the message
+    pub fn autosave(&mut self) -> std::io::Result<bool> {
+        if self.file_path.is_none() {
+            return Ok(false);
+        }
+        self.apply_save_settings(Some(self.cy));
+        self.write_file()?;
+        Ok(true)
+    }
+
+    fn write_file(&mut self) -> std::io::Result<()> {
+        let file_path = self.file_path.clone().unwrap();
+        let file = std::fs::OpenOptions::new()
+            .truncate(true)
+            .write(true)
+            .create(true)
+            .open(file_path)?;
+        let mut br = std::io::BufWriter::new(file);
+
+        let contents = self.buffer.get_all();
+        let charset = self.charset.unwrap_or(Charset::Utf8);
+        br.write_all(&charset.encode(&contents))?;
+        self.buffer.set_clean();
+        self.confirm_dirty = false;
+        Ok(())
+    }
+
+    // Applies end_of_line, trim_trailing_whitespace and insert_final_newline from EditorConfig,
+    // leaving the trailing whitespace of line `keep` alone
+    fn apply_save_settings(&mut self, keep: Option<usize>) {
+        let config = self.editorconfig.clone();
+        self.buffer.begin_group();
+        for y in 0..self.buffer.get_line_count() {
+            let line = self.buffer.get_line(y).unwrap();
+            let mut clean = line.get_clean_raw();
+            let mut ending = line.get_line_ending().to_string();
+            if config.trim_trailing_whitespace == Some(true) && keep != Some(y) {
+                clean = clean.trim_end().to_string();
+            }
+            if let Some(eol) = config.end_of_line {
+                if !ending.is_empty() {
+                    ending = eol.to_string();
+                }
+            }
+            self.buffer.set_line(y, clean + &ending, true);
+        }
+        let last = self.buffer.get_line_count() - 1;
+        let last_len = self.buffer.get_line(last).unwrap().get_clean_raw().len();
+        match config.insert_final_newline {
+            Some(true) if last_len > 0 => {
+                let ending = config
+                    .end_of_line
+                    .unwrap_or_else(|| self.buffer.get_line_ending())
+                    .to_string();
+                self.buffer.insert_region(
+                    (last_len, last),
+                    &[Line::new(ending), Line::new(String::new())],
+                    true,
+                );
+            }
+            Some(false) if last > 0 && last_len == 0 => {
+                let previous = self.buffer.get_line(last - 1).unwrap().get_clean_raw();
+                self.buffer
+                    .remove_region((previous.len(), last - 1), (0, last), true);
+            }
+            _ => {}
         }
+        self.buffer.end_group();
+        self.move_cursor(Movement::Absolute(self.cx, self.cy), self.highlighting);
     }
 
     pub fn save_as(&mut self, path: PathBuf) -> std::io::Result<()> {
+        // The new path may fall under other EditorConfig sections, applied when saving
+        let editorconfig = EditorConfig::load(&path);
+        self.charset = editorconfig.charset.or(self.charset);
+        self.render_opts.tab_size = editorconfig.tab_width.unwrap_or(self.default_tab_size);
+        self.editorconfig = editorconfig;
         self.file_path = Some(path);
+        let lines = self.buffer.get_lines(0, self.buffer.get_line_count());
+        self.indent_style = IndentStyle::detect(lines).unwrap_or(self.default_indent_style);
+        self.apply_indent_settings();
+        self.detect_syntax();
         self.save()?;
         Ok(())
     }
 
+    pub fn is_dirty(&self) -> bool {
+        self.buffer.is_dirty()
+    }
+
     pub fn try_quit(&mut self) -> bool {
         if !self.buffer.is_dirty() || self.confirm_dirty {
             true
@@ -274,7 +595,7 @@ impl Editor {
     pub fn try_reload(&mut self) -> std::io::Result<()> {
         if !self.buffer.is_dirty() || self.confirm_dirty {
             if let Some(file) = self.file_path.clone() {
-                self.open_file(&file)?;
+                self.load_file(&file)?;
                 self.set_message(&"File reloaded from disk");
             } else {
                 self.set_message(&"No file to reload");
@@ -298,26 +619,185 @@ impl Editor {
         self.prompt_message.take()
     }
 
+    // Switches to a rebuilt syntax set, keeping a chosen syntax if it still exists
+    pub fn set_syntaxes(&mut self, syntaxes: SyntaxSet) {
+        self.syntaxes = syntaxes;
+        if let Some(name) = &self.syntax_name {
+            if self.syntaxes.find_syntax_by_name(name).is_none() {
+                self.syntax_name = None;
+            }
+        }
+        self.detect_syntax();
+        // The highlighting thread has its own copy of the old syntaxes
+        self.highlight_worker = None;
+        self.reset_highlights(true);
+    }
+
     pub fn load_theme(&mut self, theme: Theme) {
         self.theme = theme;
+        self.reset_highlights(true);
+    }
+
+    // Starts highlighting the whole buffer over, as after opening a file or changing theme,
+    // keeping the old styles until new ones arrive if the text is the same
+    fn reset_highlights(&mut self, keep: bool) {
+        if self.highlight_worker.is_none() {
+            self.highlight_worker = Some(HighlightWorker::new(self.syntaxes.clone()));
+        }
+        let worker = self.highlight_worker.as_ref().unwrap();
+        let syntax = self.get_syntax().map(|s| s.name.clone());
+        let count = self.buffer.get_line_count();
+        let lines = self.buffer.get_lines(0, count).to_vec();
+        self.highlight_version += 1;
+        worker.reset(self.highlight_version, syntax.clone(), self.theme.clone());
+        worker.edit(self.highlight_version, 0, 0, lines);
+        self.highlight_syntax = syntax;
+        self.highlight_edits.push((self.highlight_version, 0));
+        if !keep {
+            self.highlights.clear();
+        }
+        self.highlights.resize(count, HighlightedLine::default());
+        self.buffer.clear_changed();
+    }
+
+    // Sends edits to the highlighting thread and takes the lines it has finished, returning
+    // whether any arrived
+    pub fn receive_highlights(&mut self) -> bool {
+        let syntax = self.get_syntax().map(|s| s.name.clone());
+        if self.highlight_worker.is_none() || syntax != self.highlight_syntax {
+            self.reset_highlights(false);
+        }
+        if let Some((from, unchanged)) = self.buffer.get_changed() {
+            self.buffer.clear_changed();
+            // Only the changed lines are sent, replacing the old ones between the same lines
+            let from = min(from, self.highlights.len());
+            let old_end = max(self.highlights.len().saturating_sub(unchanged), from);
+            let new_end = max(self.buffer.get_line_count().saturating_sub(unchanged), from);
+            let old: Vec<HighlightedLine> = self.highlights.drain(from..old_end).collect();
+            let stale = (0..new_end - from).map(|i| old.get(i).cloned().unwrap_or_default());
+            self.highlights.splice(from..from, stale);
+            self.highlight_version += 1;
+            self.highlight_edits.push((self.highlight_version, from));
+            let lines = self.buffer.get_lines(from, new_end).to_vec();
+            let worker = self.highlight_worker.as_ref().unwrap();
+            worker.edit(self.highlight_version, from, old_end, lines);
+        }
+        let mut received = false;
+        let worker = self.highlight_worker.as_ref().unwrap();
+        while let Some((version, start, lines)) = worker.try_receive() {
+            // Lines highlighted for an older version are still right above every later edit
+            self.highlight_edits.retain(|(edit, _)| *edit > version);
+            let valid = self.highlight_edits.iter().map(|(_, from)| *from).min();
+            let valid = min(valid.unwrap_or(usize::MAX), self.highlights.len());
+            for (y, line) in (start..valid).zip(lines) {
+                self.highlights[y] = line;
+                received = true;
+            }
+        }
+        // Bracket folds skip the strings and comments just found
+        if received && self.fold_method == FoldMethod::Brackets {
+            self.fold_regions.get_mut().take();
+        }
+        received
     }
 
     pub fn get_rel_cursor(&self) -> (u16, u16) {
-        let lines = self.buffer.get_line_count();
-        let max_gutter_size = (if lines < 2 { 2 } else { lines + 1 } as f32)
-            .log10()
-            .ceil();
+        let rows = self.get_line_rows(self.cy);
+        let row = get_row_index(&rows, self.cx);
+        let row_col = self
+            .buffer
+            .get_line(self.cy)
+            .map_or(0, |l| l.get_display_col(rows[row], &self.render_opts));
         (
-            (self.rx - self.col_offset + max_gutter_size as usize + 2) as u16 + self.draw_area.x, // +2 for pipe and space
-            (self.cy - self.row_offset) as u16 + self.draw_area.y,
+            (self.rx - self.col_offset - row_col + self.get_gutter_width()) as u16
+                + self.draw_area.x,
+            (self.count_rows(&self.get_hidden_lines(), self.row_offset, self.cy) + row) as u16
+                + self.draw_area.y,
         )
     }
 
+    pub fn get_cursor(&self) -> (usize, usize) {
+        (self.cx, self.cy)
+    }
+
+    pub fn get_line(&self, y: usize) -> Option<&Line> {
+        self.buffer.get_line(y)
+    }
+
+    pub fn get_line_count(&self) -> usize {
+        self.buffer.get_line_count()
+    }
+
+    // Selects from `start` to `end`, leaving the cursor at `end`
+    pub fn select_region(&mut self, start: (usize, usize), end: (usize, usize)) {
+        self.move_cursor(Movement::Absolute(start.0, start.1), false);
+        self.move_cursor(Movement::Absolute(end.0, end.1), true);
+    }
+
+    // The selected text as (start, end) in file order, unless there's no selection or it's a
+    // block
+    pub fn get_selection(&self) -> Option<((usize, usize), (usize, usize))> {
+        if !self.highlighting || self.block_highlight {
+            return None;
+        }
+        let (cursor, anchor) = ((self.cx, self.cy), (self.hx, self.hy));
+        if (cursor.1, cursor.0) <= (anchor.1, anchor.0) {
+            Some((cursor, anchor))
+        } else {
+            Some((anchor, cursor))
+        }
+    }
+
+    pub fn clear_selection(&mut self) {
+        self.highlighting = false;
+    }
+
+    // Finds the closest match of `query` starting at or after `from` (at or before it searching
+    // backward), wrapping around the file. Case is ignored unless the query has capitals.
+    pub fn find(&self, query: &str, from: (usize, usize), forward: bool) -> Option<(usize, usize)> {
+        if query.is_empty() {
+            return None;
+        }
+        let fold = !query.chars().any(char::is_uppercase);
+        let query = if fold {
+            query.to_ascii_lowercase()
+        } else {
+            query.to_string()
+        };
+        let count = self.buffer.get_line_count();
+        let from_y = min(from.1, count - 1);
+        // The starting line is searched again last for the matches on the other side of `from`
+        for i in 0..=count {
+            let y = if forward {
+                (from_y + i) % count
+            } else {
+                (from_y + count - i % count) % count
+            };
+            let mut text = self.buffer.get_line(y).unwrap().get_clean_raw();
+            if fold {
+                text = text.to_ascii_lowercase();
+            }
+            let mut matches = text.match_indices(&query).map(|(x, _)| x);
+            let x = match (forward, i) {
+                (true, 0) => matches.find(|x| *x >= from.0),
+                (false, 0) => matches.filter(|x| *x <= from.0).last(),
+                (true, _) => matches.next(),
+                (false, _) => matches.last(),
+            };
+            if let Some(x) = x {
+                return Some((x, y));
+            }
+        }
+        None
+    }
+
     pub fn move_cursor(&mut self, pos: Movement, with_highlight: bool) {
+        self.block_col = None;
         if with_highlight && !self.highlighting {
             self.hx = self.cx;
             self.hy = self.cy;
             self.highlighting = true;
+            self.block_highlight = false;
         } else if !with_highlight && self.highlighting {
             self.highlighting = false;
         }
@@ -337,124 +817,142 @@ impl Editor {
                     self.cx = line.len();
                 }
             }
-            Movement::PageUp => {
-                let rel = self.cy - self.row_offset;
-                self.cy = self.row_offset;
-                let rollback = self.row_offset >= self.draw_area.height as usize;
-                self.move_cursor(
-                    Movement::Relative(0, 0 - (self.draw_area.height as isize)),
-                    with_highlight,
-                );
-                if rollback {
-                    self.move_cursor(Movement::Relative(0, rel as isize), with_highlight);
+            Movement::WordLeft | Movement::SubwordLeft => {
+                let line = self.buffer.get_line(self.cy).unwrap().get_clean_raw();
+                if self.cx == 0 {
+                    if self.cy > 0 {
+                        self.move_cursor(Movement::Relative(0, -1), with_highlight);
+                        self.move_cursor(Movement::End, with_highlight);
+                    }
+                } else {
+                    let subword = matches!(pos, Movement::SubwordLeft);
+                    self.cx = prev_word_boundary(&line, self.cx, subword);
                 }
             }
-            Movement::PageDown => {
-                let rel = self.cy - self.row_offset;
-                self.cy = self.row_offset + self.draw_area.height as usize;
-                let rollback = self.cy < self.buffer.get_line_count() - 1; // -1 because row_offset can never get bigger
-                self.move_cursor(
-                    Movement::Relative(0, self.draw_area.height as isize),
-                    with_highlight,
-                );
-                if rollback {
-                    self.move_cursor(
-                        Movement::Relative(0, 1 - (self.draw_area.height as usize - rel) as isize), // 1- so not to go off screen
-                        with_highlight,
-                    );
+            Movement::WordRight | Movement::SubwordRight => {
+                let line = self.buffer.get_line(self.cy).unwrap().get_clean_raw();
+                if self.cx >= line.len() {
+                    if self.cy < self.buffer.get_line_count() - 1 {
+                        self.move_cursor(Movement::Relative(0, 1), with_highlight);
+                        self.move_cursor(Movement::Home, with_highlight);
+                    }
+                } else {
+                    let subword = matches!(pos, Movement::SubwordRight);
+                    self.cx = next_word_boundary(&line, self.cx, subword);
                 }
             }
+            Movement::PageUp | Movement::PageDown => {
+                // Moves the cursor a screen height and keeps it on the same row of the screen
+                self.record_jump();
+                let hidden = self.get_hidden_lines();
+                let rel =
+                    self.count_rows(&hidden, self.row_offset, self.cy) + self.get_cursor_row();
+                let height = self.draw_area.height as isize;
+                let dy = if matches!(pos, Movement::PageUp) {
+                    -height
+                } else {
+                    height
+                };
+                self.move_cursor(Movement::Relative(0, dy), with_highlight);
+                let above = rel.saturating_sub(self.get_cursor_row());
+                self.row_offset = self.step_rows_back(&hidden, self.cy, above);
+            }
             Movement::ScrollUp(dy) => {
-                self.row_offset -= min(self.row_offset, dy);
-                if self.cy >= self.row_offset + self.draw_area.height as usize
-                    && self.draw_area.height != 0
-                {
-                    self.cy = self.row_offset + self.draw_area.height as usize - 1;
+                let hidden = self.get_hidden_lines();
+                let height = self.draw_area.height as usize;
+                self.row_offset = self.step_visible(&hidden, self.row_offset, -(dy as isize));
+                let rel =
+                    self.count_rows(&hidden, self.row_offset, self.cy) + self.get_cursor_row();
+                if height != 0 && rel >= height {
+                    self.move_to_screen_row(&hidden, height - 1, self.get_row_col());
                 }
             }
             Movement::ScrollDown(dy) => {
-                self.row_offset += min(self.buffer.get_line_count() - self.row_offset - 1, dy);
+                let hidden = self.get_hidden_lines();
+                self.row_offset = self.step_visible(&hidden, self.row_offset, dy as isize);
                 if self.cy < self.row_offset {
                     self.cy = self.row_offset;
                 }
             }
-            // Up
-            Movement::Relative(0, dy) if dy < 0 => {
-                let new_cy = self.cy as isize + dy;
-                let new_cy = if new_cy < 0 { 0 } else { new_cy };
-                if new_cy >= 0 {
-                    if let Some(line) = self
-                        .buffer
-                        .get_line(new_cy as usize)
-                        .map(|l| l.get_clean_raw())
-                    {
-                        self.cy = new_cy as usize;
-                        if self.cx > line.len() {
-                            self.move_cursor(Movement::End, with_highlight);
+            // Up or down by screen row, skipping folded lines
+            Movement::Relative(0, dy) if dy != 0 => {
+                let hidden = self.get_hidden_lines();
+                let col = self.get_row_col();
+                let mut y = self.cy;
+                let mut row = self.get_cursor_row();
+                for _ in 0..dy.unsigned_abs() {
+                    if dy > 0 && row + 1 < self.get_line_rows(y).len() {
+                        row += 1;
+                    } else if dy < 0 && row > 0 {
+                        row -= 1;
+                    } else {
+                        let next = self.step_visible(&hidden, y, dy.signum());
+                        if next == y {
+                            break;
                         }
+                        y = next;
+                        row = if dy > 0 {
+                            0
+                        } else {
+                            self.get_line_rows(y).len() - 1
+                        };
                     }
                 }
-            }
-            // Down
-            Movement::Relative(0, dy) if dy > 0 => {
-                let new_cy = self.cy + dy as usize;
-                let new_cy = if new_cy >= self.buffer.get_line_count() {
-                    self.buffer.get_line_count() - 1
-                } else {
-                    new_cy
-                };
-                if let Some(line) = self.buffer.get_line(new_cy).map(|l| l.get_clean_raw()) {
-                    self.cy = new_cy;
-                    if self.cx > line.len() {
-                        self.move_cursor(Movement::End, with_highlight);
-                    }
-                }
+                self.cy = y;
+                self.cx = self.get_row_byte_index(y, row, col);
             }
             // Left
             Movement::Relative(dx, 0) if dx < 0 => {
-                if self.cx as isize + dx < 0 {
+                let line = self.buffer.get_line(self.cy).unwrap().get_clean_raw();
+                let before = line.split_at(min(self.cx, line.len())).0;
+                if before.chars().count() < (-dx) as usize {
                     if self.cy > 0 {
                         self.move_cursor(Movement::Relative(0, -1), with_highlight);
                         self.move_cursor(Movement::End, with_highlight);
                     }
                 } else {
-                    self.cx = (self.cx as isize + dx) as usize;
+                    self.cx = before
+                        .char_indices()
+                        .rev()
+                        .nth((-dx - 1) as usize)
+                        .map(|(i, _)| i)
+                        .unwrap_or(0);
                 }
             }
             // Right
             Movement::Relative(dx, 0) if dx > 0 => {
                 if let Some(line) = self.buffer.get_line(self.cy).map(|l| l.get_clean_raw()) {
-                    if self.cx + dx as usize > line.len() {
+                    let after = line.split_at(min(self.cx, line.len())).1;
+                    if after.chars().count() < dx as usize {
                         if self.cy < self.buffer.get_line_count() - 1 {
                             self.move_cursor(Movement::Relative(0, 1), with_highlight);
                             self.move_cursor(Movement::Home, with_highlight);
                         }
                     } else {
-                        self.cx += dx as usize;
+                        self.cx += after
+                            .char_indices()
+                            .nth(dx as usize)
+                            .map(|(i, _)| i)
+                            .unwrap_or_else(|| after.len());
                     }
                 }
             }
             Movement::Absolute(x, y) => {
                 self.cy = min(y, self.buffer.get_line_count() - 1); // There should be at least one row
-                self.cx = min(x, self.buffer.get_line(self.cy).unwrap().get_raw().len());
+                self.reveal_line(self.cy);
+                let line = self.buffer.get_line(self.cy).unwrap().get_clean_raw();
+                self.cx = min(x, line.len());
+                while !line.is_char_boundary(self.cx) {
+                    self.cx -= 1;
+                }
             }
             Movement::AbsoluteScreen(x, y) => {
                 let x = x as usize;
                 let y = y as usize;
-                let lines = self.buffer.get_line_count();
-                self.cy = min(self.row_offset + y, lines - 1);
-                let row_len = self.buffer.get_line(self.cy).unwrap().get_raw().len();
-                let max_gutter_size = (if lines < 2 { 2 } else { lines + 1 } as f32)
-                    .log10()
-                    .ceil() as usize
-                    + 1;
-                self.cx = min(
-                    if max_gutter_size >= x {
-                        0
-                    } else {
-                        x - max_gutter_size
-                    },
-                    if row_len > 0 { row_len - 1 } else { 0 },
+                self.move_to_screen_row(
+                    &self.get_hidden_lines(),
+                    y,
+                    self.col_offset + x.saturating_sub(self.get_gutter_width() - 1),
                 );
             }
             _ => {}
@@ -463,7 +961,126 @@ impl Editor {
         self.scroll();
     }
 
+    // Starts or extends a rectangular selection of screen columns
+    pub fn move_cursor_block(&mut self, pos: Movement) {
+        if !self.block_highlight {
+            self.highlighting = false;
+        }
+        let (cy, width) = (self.cy, self.get_line_width(self.cy));
+        let unwrapped = self.get_wrap_width().is_none();
+        // The column the block should end up in, when it can't be left to the cursor
+        let col = match pos {
+            Movement::Relative(0, _) => Some(self.rx),
+            Movement::Relative(dx, 0) if dx > 0 && self.rx >= width => Some(self.rx + dx as usize),
+            Movement::Relative(dx, 0) if dx < 0 && self.rx > width => {
+                Some(max(self.rx - dx.unsigned_abs(), width))
+            }
+            Movement::AbsoluteScreen(x, _) if unwrapped => {
+                Some(self.col_offset + (x as usize).saturating_sub(self.get_gutter_width() - 1))
+            }
+            _ => None,
+        };
+        match pos {
+            Movement::Relative(dx, 0) if col.is_some() && dx != 0 => {
+                self.move_cursor(Movement::End, true)
+            }
+            _ => self.move_cursor(pos, true),
+        }
+        self.block_highlight = true;
+        // Moving between the rows of a wrapped line is left to the cursor
+        if let Some(col) = col.filter(|_| unwrapped || self.cy != cy) {
+            self.cx = self
+                .buffer
+                .get_line(self.cy)
+                .unwrap()
+                .get_byte_index(col, &self.render_opts);
+            self.block_col = Some(col);
+            self.scroll();
+        }
+    }
+
+    // Screen columns taken up by the text of line `y`
+    fn get_line_width(&self, y: usize) -> usize {
+        self.buffer
+            .get_line(y)
+            .map_or(0, |l| l.get_display_width(&self.render_opts))
+    }
+
+    // Returns the screen columns spanned by the block selection, end exclusive
+    fn get_block_cols(&self) -> (usize, usize) {
+        let anchor = self
+            .buffer
+            .get_line(self.hy)
+            .map(|l| l.get_display_col(self.hx, &self.render_opts))
+            .unwrap_or(0);
+        (min(anchor, self.rx), max(anchor, self.rx))
+    }
+
+    fn remove_block(&mut self) {
+        let (left, right) = self.get_block_cols();
+        let top = min(self.cy, self.hy);
+        let bottom = max(self.cy, self.hy);
+        self.buffer.begin_group();
+        for y in (top..bottom + 1).rev() {
+            let line = self.buffer.get_line(y).unwrap();
+            let start = line.get_byte_index(left, &self.render_opts);
+            let end = line.get_byte_index(right, &self.render_opts);
+            if start < end {
+                self.buffer.remove_region((start, y), (end, y), true);
+            }
+        }
+        self.buffer.end_group();
+        let cx = self
+            .buffer
+            .get_line(top)
+            .unwrap()
+            .get_byte_index(left, &self.render_opts);
+        self.move_cursor(Movement::Absolute(cx, top), false);
+        self.confirm_dirty = false;
+    }
+
+    fn paste_block(&mut self, lines: &[Line]) {
+        let col = self.rx;
+        let top = self.cy;
+        self.buffer.begin_group();
+        for (i, text) in lines.iter().enumerate() {
+            let y = top + i;
+            if y >= self.buffer.get_line_count() {
+                let last = self.buffer.get_line_count() - 1;
+                let last_line = self.buffer.get_line(last).unwrap();
+                let ending = match self.buffer.get_line(last.saturating_sub(1)) {
+                    Some(l) if !l.get_line_ending().is_empty() => l.get_line_ending().to_string(),
+                    _ => "\n".to_string(),
+                };
+                self.buffer.insert_region(
+                    (last_line.get_clean_raw().len(), last),
+                    &[Line::new(ending), Line::new("".to_string())],
+                    true,
+                );
+            }
+            let line = self.buffer.get_line(y).unwrap();
+            let width = line.get_display_width(&self.render_opts);
+            // Pad short rows so the text lands in the right column
+            let padding = " ".repeat(col.saturating_sub(width));
+            let x = line.get_byte_index(col, &self.render_opts);
+            self.buffer
+                .insert_region((x, y), &[Line::new(padding + &text.get_clean_raw())], true);
+        }
+        self.buffer.end_group();
+        let cx = self
+            .buffer
+            .get_line(top)
+            .unwrap()
+            .get_byte_index(col, &self.render_opts);
+        self.move_cursor(Movement::Absolute(cx, top), false);
+        self.confirm_dirty = false;
+    }
+
     fn remove_highlight(&mut self) {
+        if self.block_highlight {
+            self.remove_block();
+            return;
+        }
         if self.cy < self.hy || (self.cy == self.hy && self.cx <= self.hx) {
             self.buffer
                 .remove_region((self.cx, self.cy), (self.hx, self.hy), true);
@@ -478,94 +1095,1434 @@ impl Editor {
 
     pub fn write_char(&mut self, c: char) {
         if self.cy < self.buffer.get_line_count() {
+            let line = self.buffer.get_line(self.cy).unwrap();
+            let next = line.get_clean_raw().split_at(self.cx).1.chars().next();
+            let cy = self.cy;
+            self.auto_closers.retain(|(_, y)| *y == cy);
+            // Typing over a closer that was inserted automatically just moves past it
+            if let Some(i) = self
+                .auto_closers
+                .iter()
+                .position(|p| *p == (self.cx, self.cy))
+            {
+                if next == Some(c) {
+                    self.auto_closers.remove(i);
+                    self.move_cursor(Movement::Relative(1, 0), false);
+                    return;
+                }
+            }
+            // Closing brackets typed at the start of a line move back one indent level
+            let dedent = CLOSERS.contains(c)
+                && self.cx > 0
+                && self.cx == line.get_indent().len()
+                && self.cx == line.get_clean_raw().len();
+            let closer = get_pair_closer(c).filter(|_| self.should_auto_pair(c));
+            self.buffer.begin_group();
+            if dedent {
+                self.move_cursor(Movement::Home, false);
+                self.dedent_line(self.cy);
+                self.move_cursor(Movement::End, false);
+            }
             self.buffer.insert_char(self.cy, self.cx, c, true);
+            self.shift_auto_closers(self.cx, c.len_utf8() as isize);
+            if let Some(closer) = closer {
+                let x = self.cx + c.len_utf8();
+                self.buffer.insert_char(self.cy, x, closer, true);
+                self.shift_auto_closers(x, closer.len_utf8() as isize);
+                self.auto_closers.push((x, self.cy));
+            }
+            self.buffer.end_group();
             self.move_cursor(Movement::Relative(1, 0), false);
             self.confirm_dirty = false;
         }
     }
 
-    pub fn delete_char(&mut self) {
-        if self.highlighting {
-            self.remove_highlight();
-            self.highlighting = false;
-        } else if self.cy < self.buffer.get_line_count() {
-            self.buffer.delete_char(self.cy, self.cx, true);
-            self.confirm_dirty = false;
+    // Moves recorded auto-inserted closers on the cursor line at or after `x`
+    fn shift_auto_closers(&mut self, x: usize, dx: isize) {
+        for pos in self.auto_closers.iter_mut() {
+            if pos.1 == self.cy && pos.0 >= x {
+                pos.0 = (pos.0 as isize + dx) as usize;
+            }
         }
     }
 
-    pub fn backspace_char(&mut self) {
-        if self.highlighting {
-            self.remove_highlight();
-            self.highlighting = false;
-        } else if self.cx > 0 || self.cy > 0 {
-            self.move_cursor(Movement::Relative(-1, 0), false);
-            self.delete_char();
-            self.confirm_dirty = false;
-        }
+    fn should_auto_pair(&self, c: char) -> bool {
+        let line = self.buffer.get_line(self.cy).unwrap().get_clean_raw();
+        let (before, after) = line.split_at(self.cx);
+        let next = after.chars().next();
+        let previous = before.chars().last();
+        let next_ok = next.is_none_or(|n| n.is_whitespace() || CLOSERS.contains(n));
+        let quote_ok =
+            !(c == '"' || c == '\'') || !previous.is_some_and(|p| p.is_alphanumeric() || p == c);
+        next_ok && quote_ok && !self.is_in_string_or_comment(self.cx, self.cy)
     }
 
-    pub fn do_return(&mut self) {
-        if self.highlighting {
-            self.remove_highlight();
-            self.highlighting = false;
-        }
-        if self.cy < self.buffer.get_line_count() {
-            self.buffer.split_line(self.cy, self.cx, true);
-            self.move_cursor(Movement::Relative(0, 1), false);
-            self.move_cursor(Movement::Home, false);
+    // Whether a position is inside a string or comment, going by the highlighting thread. A
+    // line changed since it was highlighted only tells about the text before the change, which
+    // an edit at the end of a string or comment is taken to stay inside.
+    fn is_in_string_or_comment(&self, x: usize, y: usize) -> bool {
+        let highlighted = match self.highlights.get(y) {
+            Some(highlighted) if self.get_syntax().is_some() => highlighted,
+            _ => return false,
+        };
+        let text = self.buffer.get_line(y).unwrap().get_raw();
+        let old = highlighted.ranges.iter().flat_map(|(_, t)| t.bytes());
+        let old_len = old.clone().count();
+        let same = old.zip(text.bytes()).take_while(|(a, b)| a == b).count();
+        if same == old_len && same == text.len() {
+            highlighted.is_literal(x)
+        } else {
+            same > 0 && highlighted.is_literal(min(x, same - 1))
         }
     }
 
-    pub fn cut(&mut self) -> Vec<Line> {
-        let clipboard = self.copy();
-        if self.highlighting {
-            self.remove_highlight();
-            self.highlighting = false;
+    // Finds the bracket under or just before the cursor and its match between the first and
+    // last lines in `rows`. Brackets in strings and comments don't count.
+    fn get_bracket_pair(&self, rows: (usize, usize)) -> Option<((usize, usize), (usize, usize))> {
+        let line = self.buffer.get_line(self.cy)?.get_clean_raw();
+        let at = line.split_at(self.cx).1.chars().next();
+        let before = line.split_at(self.cx).0.chars().last();
+        let (x, c) = match (at, before) {
+            (Some(c), _) if OPENERS.contains(c) || CLOSERS.contains(c) => (self.cx, c),
+            (_, Some(c)) if OPENERS.contains(c) || CLOSERS.contains(c) => {
+                (self.cx - c.len_utf8(), c)
+            }
+            _ => return None,
+        };
+        if self.is_in_string_or_comment(x, self.cy) {
+            return None;
         }
-        clipboard
+        self.find_matching_bracket(x, self.cy, c, rows)
+            .map(|pos| ((x, self.cy), pos))
     }
-    pub fn copy(&mut self) -> Vec<Line> {
-        let mut clipboard = vec![];
-        if self.highlighting {
-            if self.cy < self.hy || (self.cy == self.hy && self.cx <= self.hx) {
-                clipboard = self
-                    .buffer
-                    .get_region((self.cx, self.cy), (self.hx, self.hy));
+
+    fn find_matching_bracket(
+        &self,
+        x: usize,
+        y: usize,
+        c: char,
+        rows: (usize, usize),
+    ) -> Option<(usize, usize)> {
+        let forward = OPENERS.contains(c);
+        let other = if forward {
+            get_closer(c)?
+        } else {
+            OPENERS.chars().nth(CLOSERS.find(c)?)?
+        };
+        let mut depth = 0;
+        let mut row = y;
+        loop {
+            let line = self.buffer.get_line(row)?.get_clean_raw();
+            let mut chars: Vec<(usize, char)> = line.char_indices().collect();
+            if forward {
+                chars.retain(|(i, _)| row > y || *i >= x);
             } else {
-                clipboard = self
-                    .buffer
-                    .get_region((self.hx, self.hy), (self.cx, self.cy));
+                chars.retain(|(i, _)| row < y || *i <= x);
+                chars.reverse();
             }
-        }
-        clipboard
-    }
-    pub fn paste(&mut self, clipboard: &Option<Vec<Line>>) {
-        if let Some(clipboard) = clipboard {
-            if self.highlighting {
-                self.remove_highlight();
-                self.highlighting = false;
+            for (i, ch) in chars {
+                if (ch != c && ch != other) || self.is_in_string_or_comment(i, row) {
+                    continue;
+                }
+                if ch == c {
+                    depth += 1;
+                } else {
+                    depth -= 1;
+                    if depth == 0 {
+                        return Some((i, row));
+                    }
+                }
             }
-            if self.cy < self.buffer.get_line_count() {
-                let new_pos = self
-                    .buffer
-                    .insert_region((self.cx, self.cy), clipboard, true);
-                self.move_cursor(Movement::Absolute(new_pos.0, new_pos.1), false);
-                self.confirm_dirty = false;
+            if forward && row < rows.1 {
+                row += 1;
+            } else if !forward && row > rows.0 {
+                row -= 1;
+            } else {
+                return None;
             }
         }
     }
 
-    pub fn undo(&mut self) {
+    pub fn jump_to_bracket(&mut self) {
+        match self.get_bracket_pair((0, self.buffer.get_line_count() - 1)) {
+            Some((_, (x, y))) => {
+                self.record_jump();
+                self.move_cursor(Movement::Absolute(x, y), false);
+            }
+            None => self.set_message(&"No matching bracket"),
+        }
+    }
+
+    pub fn set_fold_method(&mut self, method: FoldMethod) {
+        self.fold_method = method;
+    }
+
+    // Regions that can be folded as (first line, last line), ordered by first line, found again
+    // only after the lines change
+    fn get_fold_regions(&self) -> Vec<(usize, usize)> {
+        let key = (
+            self.buffer.get_version(),
+            self.fold_method,
+            self.render_opts.tab_size,
+        );
+        if let Some((cached, regions)) = self.fold_regions.borrow().as_ref() {
+            if *cached == key {
+                return regions.clone();
+            }
+        }
+        let lines = self.buffer.get_lines(0, self.buffer.get_line_count());
+        let regions = match self.fold_method {
+            FoldMethod::Indent => indent_folds(lines, &self.render_opts),
+            FoldMethod::Brackets => {
+                bracket_folds(lines, &|x, y| self.is_in_string_or_comment(x, y))
+            }
+        };
+        *self.fold_regions.borrow_mut() = Some((key, regions.clone()));
+        regions
+    }
+
+    // Ranges of lines hidden by folds, ordered and not overlapping
+    fn get_hidden_lines(&self) -> Vec<(usize, usize)> {
+        let folds = self.buffer.get_folds();
+        if folds.is_empty() {
+            return vec![];
+        }
+        let mut hidden: Vec<(usize, usize)> = vec![];
+        for (start, end) in self.get_fold_regions() {
+            // Folds nested inside a folded region are already hidden
+            if folds.contains(&start) && hidden.last().is_none_or(|(_, last)| start > *last) {
+                hidden.push((start + 1, end));
+            }
+        }
+        hidden
+    }
+
+    // Moves `dy` lines up or down from line `y`, skipping lines hidden by folds
+    fn step_visible(&self, hidden: &[(usize, usize)], y: usize, dy: isize) -> usize {
+        let last = self.buffer.get_line_count() - 1;
+        let mut y = y;
+        for _ in 0..dy.unsigned_abs() {
+            let next = if dy > 0 {
+                get_hidden_range(hidden, y + 1).map_or(y + 1, |(_, end)| end + 1)
+            } else if y == 0 {
+                break;
+            } else {
+                get_hidden_range(hidden, y - 1).map_or(y - 1, |(start, _)| start - 1)
+            };
+            if next > last {
+                break;
+            }
+            y = next;
+        }
+        y
+    }
+
+    // Columns taken by the line number, the mark or pipe and the fold marker
+    fn get_gutter_width(&self) -> usize {
+        if !self.line_numbers {
+            return 2;
+        }
+        let lines = self.buffer.get_line_count();
+        let max_gutter_size = (if lines < 2 { 2 } else { lines + 1 } as f32)
+            .log10()
+            .ceil();
+        max_gutter_size as usize + 2
+    }
+
+    pub fn set_wrap_overrides(&mut self, overrides: WrapOverrides) {
+        self.wrap_overrides = overrides;
+        self.apply_wrap_settings();
+    }
+
+    fn apply_wrap_settings(&mut self) {
+        let mode = self
+            .file_path
+            .as_ref()
+            .and_then(|f| f.extension())
+            .and_then(|e| self.wrap_overrides.get(e.to_string_lossy().as_ref()))
+            .copied();
+        if let Some(mode) = mode {
+            self.wrap = mode;
+        }
+    }
+
+    pub fn set_wrap(&mut self, mode: WrapMode) {
+        self.wrap = mode;
+        self.scroll();
+    }
+
+    pub fn toggle_wrap(&mut self) {
+        self.set_wrap(match self.wrap {
+            WrapMode::Off => WrapMode::Window,
+            _ => WrapMode::Off,
+        });
+    }
+
+    // Column lines wrap at, leaving a column for the cursor at the end of a row
+    fn get_wrap_width(&self) -> Option<usize> {
+        let available = (self.draw_area.width as usize).saturating_sub(self.get_gutter_width() + 1);
+        self.wrap.get_width(available)
+    }
+
+    // Byte indices where each screen row of line `y` starts
+    fn get_line_rows(&self, y: usize) -> Vec<usize> {
+        match (self.get_wrap_width(), self.buffer.get_line(y)) {
+            (Some(width), Some(line)) => wrap_rows(&line.get_clean_raw(), width, &self.render_opts),
+            _ => vec![0],
+        }
+    }
+
+    fn get_cursor_row(&self) -> usize {
+        get_row_index(&self.get_line_rows(self.cy), self.cx)
+    }
+
+    // Screen column of the cursor relative to the start of its row
+    fn get_row_col(&self) -> usize {
+        let rows = self.get_line_rows(self.cy);
+        let start = rows[get_row_index(&rows, self.cx)];
+        self.rx
+            - self
+                .buffer
+                .get_line(self.cy)
+                .unwrap()
+                .get_display_col(start, &self.render_opts)
+    }
+
+    // Byte index of the character at screen column `col` of a row of line `y`, staying in the row
+    fn get_row_byte_index(&self, y: usize, row: usize, col: usize) -> usize {
+        let line = self.buffer.get_line(y).unwrap();
+        let rows = self.get_line_rows(y);
+        let start = rows[min(row, rows.len() - 1)];
+        let x = line.get_byte_index(
+            line.get_display_col(start, &self.render_opts) + col,
+            &self.render_opts,
+        );
+        match rows.get(row + 1) {
+            Some(next) if x >= *next => line.get_clean_raw()[..*next]
+                .char_indices()
+                .last()
+                .map_or(start, |(i, _)| i),
+            _ => x,
+        }
+    }
+
+    // Number of screen rows taken up by the visible lines from `from` up to (not including) `to`
+    fn count_rows(&self, hidden: &[(usize, usize)], from: usize, to: usize) -> usize {
+        if self.get_wrap_width().is_none() {
+            return count_visible(hidden, from, to);
+        }
+        let mut rows = 0;
+        let mut y = from;
+        while y < to {
+            rows += self.get_line_rows(y).len();
+            let next = self.step_visible(hidden, y, 1);
+            if next == y {
+                break;
+            }
+            y = next;
+        }
+        rows
+    }
+
+    // The first line above `y` that fits at most `rows` screen rows between it and `y`
+    fn step_rows_back(&self, hidden: &[(usize, usize)], y: usize, rows: usize) -> usize {
+        let mut top = y;
+        let mut count = 0;
+        loop {
+            let previous = self.step_visible(hidden, top, -1);
+            if previous == top {
+                return top;
+            }
+            count += self.get_line_rows(previous).len();
+            if count > rows {
+                return top;
+            }
+            top = previous;
+        }
+    }
+
+    // Moves the cursor to screen column `col` of the `row`th screen row of the editor
+    fn move_to_screen_row(&mut self, hidden: &[(usize, usize)], row: usize, col: usize) {
+        let mut y = self.row_offset;
+        let mut row = row;
+        loop {
+            let rows = self.get_line_rows(y).len();
+            let next = self.step_visible(hidden, y, 1);
+            if row < rows || next == y {
+                row = min(row, rows - 1);
+                break;
+            }
+            row -= rows;
+            y = next;
+        }
+        self.cy = y;
+        self.cx = self.get_row_byte_index(y, row, col);
+    }
+
+    // Opens any folds hiding line `y`
+    fn reveal_line(&mut self, y: usize) {
+        while let Some((start, _)) = get_hidden_range(&self.get_hidden_lines(), y) {
+            self.buffer.remove_fold(start - 1);
+        }
+    }
+
+    // Folds the innermost region around the cursor that isn't folded yet
+    pub fn fold(&mut self) {
+        let region = self
+            .get_fold_regions()
+            .into_iter()
+            .rev()
+            .find(|(start, end)| {
+                *start <= self.cy && self.cy <= *end && !self.buffer.is_folded(*start)
+            });
+        match region {
+            Some((start, _)) => {
+                self.buffer.add_fold(start);
+                self.move_cursor(Movement::Absolute(self.cx, start), false);
+            }
+            None => self.set_message(&"Nothing to fold"),
+        }
+    }
+
+    pub fn unfold(&mut self) {
+        if self.buffer.is_folded(self.cy) {
+            self.buffer.remove_fold(self.cy);
+        } else {
+            self.set_message(&"No fold on this line");
+        }
+    }
+
+    pub fn fold_all(&mut self) {
+        for (start, _) in self.get_fold_regions() {
+            self.buffer.add_fold(start);
+        }
+        // Keep the cursor on the line that now hides it rather than unfolding it again
+        let hidden = self.get_hidden_lines();
+        if let Some((start, _)) = get_hidden_range(&hidden, self.cy) {
+            self.move_cursor(Movement::Absolute(0, start - 1), false);
+        }
+        self.scroll();
+    }
+
+    pub fn unfold_all(&mut self) {
+        self.buffer.clear_folds();
+        self.scroll();
+    }
+
+    pub fn toggle_bookmark(&mut self) {
+        self.buffer.toggle_bookmark(self.cy);
+        self.set_message(&"Bookmark toggled");
+    }
+
+    pub fn set_mark(&mut self, name: char) {
+        self.buffer.set_mark(self.cy, name);
+        self.set_message(&format!("Mark {} set", name));
+    }
+
+    pub fn jump_to_mark(&mut self, name: char) {
+        match self.buffer.get_mark(name) {
+            Some(y) => self.jump_to_line(y),
+            None => self.set_message(&format!("No mark named {}", name)),
+        }
+    }
+
+    // Moves to the next (or previous) marked line, wrapping around the file
+    pub fn next_mark(&mut self, forward: bool) {
+        let marks: Vec<usize> = self
+            .buffer
+            .get_marks()
+            .iter()
+            .map(|m| m.get_line())
+            .collect();
+        let next = if forward {
+            marks
+                .iter()
+                .find(|y| **y > self.cy)
+                .or_else(|| marks.first())
+        } else {
+            marks
+                .iter()
+                .rev()
+                .find(|y| **y < self.cy)
+                .or_else(|| marks.last())
+        };
+        match next {
+            Some(y) => self.jump_to_line(*y),
+            None => self.set_message(&"No marks set"),
+        }
+    }
+
+    fn jump_to_line(&mut self, y: usize) {
+        self.record_jump();
+        self.move_cursor(Movement::Absolute(0, y), false);
+    }
+
+    pub fn list_marks(&mut self) {
+        let marks: Vec<String> = self
+            .buffer
+            .get_marks()
+            .iter()
+            .map(|m| match m.get_name() {
+                Some(name) => format!("{}:{}", name, m.get_line() + 1),
+                None => (m.get_line() + 1).to_string(),
+            })
+            .collect();
+        if marks.is_empty() {
+            self.set_message(&"No marks set");
+        } else {
+            self.set_message(&format!("Marks: {}", marks.join(", ")));
+        }
+    }
+
+    pub fn clear_marks(&mut self) {
+        self.buffer.clear_marks();
+        self.set_message(&"Marks cleared");
+    }
+
+    // Moves to a 1-based line and (character) column, remembering where the cursor was
+    pub fn goto(&mut self, line: usize, col: usize) {
+        let y = min(line.saturating_sub(1), self.buffer.get_line_count() - 1);
+        let x = self
+            .buffer
+            .get_line(y)
+            .unwrap()
+            .get_clean_raw()
+            .char_indices()
+            .nth(col.saturating_sub(1))
+            .map(|(i, _)| i)
+            .unwrap_or(usize::MAX);
+        self.record_jump();
+        self.move_cursor(Movement::Absolute(x, y), false);
+    }
+
+    fn get_jump(&self) -> Jump {
+        (self.file_path.clone(), self.cx, self.cy)
+    }
+
+    // Remembers the cursor position before a large jump, dropping any forward history
+    pub fn record_jump(&mut self) {
+        let jump = self.get_jump();
+        self.jumps.truncate(self.jump_index);
+        if self.jumps.last() != Some(&jump) {
+            self.jumps.push(jump);
+        }
+        if self.jumps.len() > MAX_JUMPS {
+            self.jumps.remove(0);
+        }
+        self.jump_index = self.jumps.len();
+    }
+
+    pub fn jump_back(&mut self) {
+        if self.jump_index == self.jumps.len() {
+            // Remember the current position so jumping forward can come back to it
+            let jump = self.get_jump();
+            if self.jumps.last() != Some(&jump) {
+                self.jumps.push(jump);
+            }
+            self.jump_index = self.jumps.len() - 1;
+        }
+        if self.jump_index == 0 {
+            self.set_message(&"No earlier position in the jump list");
+        } else if self.go_to_jump(self.jump_index - 1) {
+            self.jump_index -= 1;
+        }
+    }
+
+    pub fn jump_forward(&mut self) {
+        if self.jump_index + 1 >= self.jumps.len() {
+            self.set_message(&"No later position in the jump list");
+        } else if self.go_to_jump(self.jump_index + 1) {
+            self.jump_index += 1;
+        }
+    }
+
+    // Moves to a jump list entry, opening its file if it isn't the current one
+    fn go_to_jump(&mut self, index: usize) -> bool {
+        let (path, x, y) = self.jumps[index].clone();
+        if path != self.file_path {
+            let path = match path {
+                Some(path) => path,
+                None => {
+                    self.set_message(&"Jump is in an unsaved buffer");
+                    return false;
+                }
+            };
+            if self.buffer.is_dirty() {
+                self.set_message(&"Save changes before jumping to another file");
+                return false;
+            }
+            if let Err(err) = self.load_file(&path) {
+                self.set_message(&format!("Couldn't open {}: {}", path.display(), err));
+                return false;
+            }
+        }
+        self.move_cursor(Movement::Absolute(x, y), false);
+        true
+    }
+
+    // Inserts one level of indentation at the cursor, or before every selected line
+    pub fn indent(&mut self) {
+        if !self.highlighting || self.cy == self.hy {
+            if self.highlighting {
+                self.remove_highlight();
+                self.highlighting = false;
+            }
+            let unit = self.get_indent_unit();
+            self.buffer
+                .insert_region((self.cx, self.cy), &[Line::new(unit.clone())], true);
+            self.move_cursor(Movement::Absolute(self.cx + unit.len(), self.cy), false);
+            self.confirm_dirty = false;
+            return;
+        }
+        let unit = self.get_indent_unit();
+        self.buffer.begin_group();
+        for y in self.get_selected_lines() {
+            if !self.buffer.get_line(y).unwrap().get_clean_raw().is_empty() {
+                self.buffer
+                    .insert_region((0, y), &[Line::new(unit.clone())], true);
+                self.shift_selection(y, 0, unit.len() as isize);
+            }
+        }
+        self.buffer.end_group();
+        self.confirm_dirty = false;
+    }
+
+    // Removes one level of indentation from the current line or every selected line
+    pub fn dedent(&mut self) {
+        self.buffer.begin_group();
+        for y in self.get_selected_lines() {
+            let removed = self.dedent_line(y);
+            self.shift_selection(y, 0, -(removed as isize));
+        }
+        self.buffer.end_group();
+        self.scroll();
+        self.confirm_dirty = false;
+    }
+
+    // Removes a tab or up to one indent width of spaces from the start of a line
+    fn dedent_line(&mut self, y: usize) -> usize {
+        let indent = self.buffer.get_line(y).unwrap().get_indent();
+        let width = self.indent_style.get_width(self.render_opts.tab_size);
+        let removed = if indent.starts_with('\t') {
+            1
+        } else {
+            indent.chars().take(width).take_while(|c| *c == ' ').count()
+        };
+        if removed > 0 {
+            self.buffer.remove_region((0, y), (removed, y), true);
+        }
+        removed
+    }
+
+    // Lines touched by the selection, not counting a final line selected up to column 0
+    fn get_selected_lines(&self) -> std::ops::Range<usize> {
+        if !self.highlighting {
+            return self.cy..self.cy + 1;
+        }
+        let (top, bottom) = if (self.cy, self.cx) < (self.hy, self.hx) {
+            ((self.cx, self.cy), (self.hx, self.hy))
+        } else {
+            ((self.hx, self.hy), (self.cx, self.cy))
+        };
+        if bottom.1 > top.1 && bottom.0 == 0 {
+            top.1..bottom.1
+        } else {
+            top.1..bottom.1 + 1
+        }
+    }
+
+    // Keeps the cursor and selection anchor on the same text after text at `col` on a line
+    // is inserted (dx > 0) or removed (dx < 0)
+    fn shift_selection(&mut self, y: usize, col: usize, dx: isize) {
+        let shift = |x: usize| {
+            if x > col {
+                max(x as isize + dx, col as isize) as usize
+            } else {
+                x
+            }
+        };
+        if self.cy == y {
+            self.cx = shift(self.cx);
+        }
+        if self.highlighting && self.hy == y {
+            self.hx = shift(self.hx);
+        }
+    }
+
+    // Comments or uncomments the current line or every selected line as one undo step
+    pub fn toggle_comment(&mut self) {
+        let tokens = match self.get_syntax() {
+            Some(syntax) => get_comment_tokens(syntax, &self.syntaxes),
+            None => {
+                self.set_message(&"No comment syntax for this file");
+                return;
+            }
+        };
+        let lines = self.get_selected_lines();
+        self.buffer.begin_group();
+        if let Some(token) = tokens.line {
+            self.toggle_line_comment(lines, &token);
+        } else if let Some((start, end)) = tokens.block {
+            self.toggle_block_comment(lines, &start, &end);
+        } else {
+            self.set_message(&"No comment syntax for this file");
+        }
+        self.buffer.end_group();
+        self.scroll();
+        self.confirm_dirty = false;
+    }
+
+    fn toggle_line_comment(&mut self, lines: std::ops::Range<usize>, token: &str) {
+        let marker = token.trim_end();
+        let rows: Vec<usize> = lines
+            .filter(|y| {
+                !self
+                    .buffer
+                    .get_line(*y)
+                    .unwrap()
+                    .get_clean_raw()
+                    .trim()
+                    .is_empty()
+            })
+            .collect();
+        let commented = !rows.is_empty()
+            && rows.iter().all(|y| {
+                let line = self.buffer.get_line(*y).unwrap();
+                line.get_clean_raw()
+                    .split_at(line.get_indent().len())
+                    .1
+                    .starts_with(marker)
+            });
+        // Insert at the smallest indent so markers stay aligned
+        let col = rows
+            .iter()
+            .map(|y| self.buffer.get_line(*y).unwrap().get_indent().len())
+            .min()
+            .unwrap_or(0);
+        for y in rows {
+            let line = self.buffer.get_line(y).unwrap();
+            let indent = line.get_indent().len();
+            if commented {
+                let rest = line.get_clean_raw().split_at(indent).1.to_string();
+                let len = if rest.starts_with(token) {
+                    token.len()
+                } else {
+                    marker.len()
+                };
+                self.buffer
+                    .remove_region((indent, y), (indent + len, y), true);
+                self.shift_selection(y, indent, -(len as isize));
+            } else {
+                self.buffer
+                    .insert_region((col, y), &[Line::new(token.to_string())], true);
+                self.shift_selection(y, col, token.len() as isize);
+            }
+        }
+    }
+
+    fn toggle_block_comment(&mut self, lines: std::ops::Range<usize>, start: &str, end: &str) {
+        let (top, bottom) = (lines.start, lines.end - 1);
+        let first = self.buffer.get_line(top).unwrap();
+        let first_indent = first.get_indent().len();
+        let first_text = first.get_clean_raw().split_at(first_indent).1.to_string();
+        let last_text = self.buffer.get_line(bottom).unwrap().get_clean_raw();
+        let last_trimmed = last_text.trim_end();
+        if first_text.starts_with(start.trim_end()) && last_trimmed.ends_with(end.trim_start()) {
+            let end_len = if last_trimmed.ends_with(end) {
+                end.len()
+            } else {
+                end.trim_start().len()
+            };
+            let end_x = last_trimmed.len();
+            self.buffer
+                .remove_region((end_x - end_len, bottom), (end_x, bottom), true);
+            self.shift_selection(bottom, end_x - end_len, -(end_len as isize));
+            let start_len = if first_text.starts_with(start) {
+                start.len()
+            } else {
+                start.trim_end().len()
+            };
+            self.buffer
+                .remove_region((first_indent, top), (first_indent + start_len, top), true);
+            self.shift_selection(top, first_indent, -(start_len as isize));
+        } else {
+            let end_x = last_text.trim_end().len();
+            self.buffer
+                .insert_region((end_x, bottom), &[Line::new(end.to_string())], true);
+            self.buffer
+                .insert_region((first_indent, top), &[Line::new(start.to_string())], true);
+            self.shift_selection(top, first_indent, start.len() as isize);
+        }
+    }
+
+    fn get_indent_unit(&self) -> String {
+        self.indent_style.get_unit()
+    }
+
+    fn get_indent_override(&self) -> Option<IndentStyle> {
+        self.file_path
+            .as_ref()
+            .and_then(|f| f.extension())
+            .and_then(|e| self.indent_overrides.get(e.to_string_lossy().as_ref()))
+            .copied()
+    }
+
+    pub fn set_indent_overrides(&mut self, overrides: IndentOverrides) {
+        self.indent_overrides = overrides;
+        self.apply_indent_settings();
+    }
+
+    // Settings take precedence over the detected style and EditorConfig over both
+    fn apply_indent_settings(&mut self) {
+        if let Some(style) = self.get_indent_override() {
+            self.indent_style = style;
+        }
+        self.indent_style = self.editorconfig.get_indent_style(self.indent_style);
+    }
+
+    pub fn get_indent_style(&self) -> IndentStyle {
+        self.indent_style
+    }
+
+    // Switches the indentation style and rewrites the leading whitespace of every line to match
+    pub fn convert_indentation(&mut self, style: IndentStyle) {
+        let level_width = max(self.indent_style.get_width(self.render_opts.tab_size), 1);
+        self.buffer.begin_group();
+        for y in 0..self.buffer.get_line_count() {
+            let line = self.buffer.get_line(y).unwrap();
+            let indent = line.get_indent().to_string();
+            let width = line.get_display_col(indent.len(), &self.render_opts);
+            let new_indent =
+                style.get_unit().repeat(width / level_width) + &" ".repeat(width % level_width);
+            if new_indent != indent {
+                self.buffer.remove_region((0, y), (indent.len(), y), true);
+                self.buffer
+                    .insert_region((0, y), &[Line::new(new_indent.clone())], true);
+                if self.cy == y {
+                    self.cx = (self.cx + new_indent.len()).saturating_sub(indent.len());
+                }
+            }
+        }
+        self.buffer.end_group();
+        self.highlighting = false;
+        self.indent_style = style;
+        self.move_cursor(Movement::Absolute(self.cx, self.cy), false);
+        self.set_message(&format!("Indentation converted to {}", style));
+        self.confirm_dirty = false;
+    }
+
+    pub fn delete_char(&mut self) {
+        if self.highlighting {
+            self.remove_highlight();
+            self.highlighting = false;
+        } else if self.cy < self.buffer.get_line_count() {
+            self.buffer.delete_char(self.cy, self.cx, true);
+            self.confirm_dirty = false;
+        }
+    }
+
+    pub fn backspace_char(&mut self) {
+        if self.highlighting {
+            self.remove_highlight();
+            self.highlighting = false;
+        } else if self.cx > 0 || self.cy > 0 {
+            if self.cx == 0 {
+                self.auto_closers.clear();
+            }
+            self.move_cursor(Movement::Relative(-1, 0), false);
+            let line = self.buffer.get_line(self.cy).unwrap().get_clean_raw();
+            let mut chars = line.split_at(self.cx).1.chars();
+            if let (Some(c), next) = (chars.next(), chars.next()) {
+                let closer = (self.cx + c.len_utf8(), self.cy);
+                if let Some(i) = self.auto_closers.iter().position(|p| *p == closer) {
+                    // Deleting an opener also removes the closer inserted with it
+                    if next.is_some() && get_pair_closer(c) == next {
+                        self.auto_closers.remove(i);
+                        self.buffer.begin_group();
+                        self.buffer.delete_char(self.cy, self.cx, true);
+                        self.buffer.delete_char(self.cy, self.cx, true);
+                        self.buffer.end_group();
+                        self.shift_auto_closers(self.cx, -2 * c.len_utf8() as isize);
+                        self.confirm_dirty = false;
+                        return;
+                    }
+                }
+                self.shift_auto_closers(self.cx + 1, -(c.len_utf8() as isize));
+            }
+            self.delete_char();
+            self.confirm_dirty = false;
+        }
+    }
+
+    pub fn delete_word_left(&mut self) {
+        if self.highlighting {
+            self.remove_highlight();
+            self.highlighting = false;
+        } else {
+            let end = (self.cx, self.cy);
+            self.move_cursor(Movement::WordLeft, false);
+            if (self.cx, self.cy) != end {
+                self.buffer.remove_region((self.cx, self.cy), end, true);
+                self.confirm_dirty = false;
+            }
+        }
+    }
+
+    pub fn delete_word_right(&mut self) {
+        if self.highlighting {
+            self.remove_highlight();
+            self.highlighting = false;
+        } else {
+            let start = (self.cx, self.cy);
+            self.move_cursor(Movement::WordRight, false);
+            if (self.cx, self.cy) != start {
+                self.buffer.remove_region(start, (self.cx, self.cy), true);
+                self.move_cursor(Movement::Absolute(start.0, start.1), false);
+                self.confirm_dirty = false;
+            }
+        }
+    }
+
+    pub fn select_word(&mut self) {
+        let line = self.buffer.get_line(self.cy).unwrap().get_clean_raw();
+        let (start, end) = word_at(&line, self.cx);
+        self.move_cursor(Movement::Absolute(start, self.cy), false);
+        self.move_cursor(Movement::Absolute(end, self.cy), true);
+    }
+
+    pub fn select_line(&mut self) {
+        self.move_cursor(Movement::Home, false);
+        if self.cy + 1 < self.buffer.get_line_count() {
+            self.move_cursor(Movement::Absolute(0, self.cy + 1), true);
+        } else {
+            self.move_cursor(Movement::End, true);
+        }
+    }
+
+    pub fn do_return(&mut self) {
+        self.auto_closers.clear();
+        if self.highlighting {
+            self.remove_highlight();
+            self.highlighting = false;
+        }
+        if self.cy < self.buffer.get_line_count() {
+            let line = self.buffer.get_line(self.cy).unwrap().get_clean_raw();
+            let (before, after) = line.split_at(min(self.cx, line.len()));
+            let indent = Line::new(before.to_string()).get_indent().to_string();
+            let opener = before
+                .trim_end()
+                .chars()
+                .last()
+                .filter(|c| self.get_indent_triggers().contains(*c));
+            let closes = match opener.and_then(get_closer) {
+                Some(closer) => after.trim_start().starts_with(closer),
+                None => false,
+            };
+            // Splitting inside the indentation moves the text with all of it kept
+            let line_indent = Line::new(line.clone()).get_indent().len();
+            let in_indent = self.cx <= line_indent;
+            let stripped = if in_indent {
+                0
+            } else {
+                after.len() - after.trim_start().len()
+            };
+            let y = self.cy;
+
+            self.buffer.begin_group();
+            self.buffer.split_line(y, self.cx, true);
+            if stripped > 0 {
+                self.buffer
+                    .remove_region((0, y + 1), (stripped, y + 1), true);
+            }
+            let mut new_indent = indent.clone();
+            if opener.is_some() {
+                new_indent += &self.get_indent_unit();
+            }
+            if closes {
+                // Leave the closing bracket on its own line below the cursor
+                self.buffer.split_line(y + 1, 0, true);
+                self.buffer
+                    .insert_region((0, y + 2), &[Line::new(indent)], true);
+            }
+            self.buffer
+                .insert_region((0, y + 1), &[Line::new(new_indent.clone())], true);
+            self.buffer.end_group();
+            let x = if in_indent {
+                line_indent
+            } else {
+                new_indent.len()
+            };
+            self.move_cursor(Movement::Absolute(x, y + 1), false);
+            self.confirm_dirty = false;
+        }
+    }
+
+    // Characters that increase the indentation of the next line when typed before Enter
+    fn get_indent_triggers(&self) -> &'static str {
+        match self.get_syntax().map(|s| s.name.as_str()) {
+            Some("Python") | Some("YAML") => "{([:",
+            _ => OPENERS,
+        }
+    }
+
+    pub fn get_syntax(&self) -> Option<&SyntaxReference> {
+        self.syntax_name
+            .as_ref()
+            .or(self.detected_syntax.as_ref())
+            .and_then(|name| self.syntaxes.find_syntax_by_name(name))
+    }
+
+    // Takes on new settings, keeping the file's detected and EditorConfig settings
+    pub fn apply_settings(&mut self, settings: &Settings) {
+        self.default_indent_style = settings.indent_style;
+        self.default_tab_size = settings.tab_size;
+        self.default_wrap = settings.soft_wrap;
+        self.line_numbers = settings.line_numbers;
+        self.rulers = settings.rulers.clone();
+        self.scrolloff = settings.scrolloff;
+        if self.editorconfig.tab_width.is_none() {
+            self.render_opts.tab_size = settings.tab_size;
+        }
+        if self.file_path.is_none() {
+            self.indent_style = settings.indent_style;
+            self.wrap = settings.soft_wrap;
+        }
+        self.set_indent_overrides(settings.indent.clone());
+        self.set_fold_method(settings.fold);
+        self.set_wrap_overrides(settings.wrap.clone());
+        self.set_syntax_overrides(settings.syntax.clone());
+        self.scroll();
+    }
+
+    pub fn set_syntax_overrides(&mut self, overrides: SyntaxOverrides) {
+        self.syntax_overrides = overrides;
+        self.detect_syntax();
+    }
+
+    fn detect_syntax(&mut self) {
+        let lines = self.buffer.get_lines(0, self.buffer.get_line_count());
+        self.detected_syntax = detect_syntax(
+            &self.syntaxes,
+            self.file_path.as_deref(),
+            lines,
+            &self.syntax_overrides,
+        )
+        .map(|s| s.name.clone());
+    }
+
+    // Sets the syntax by name, or goes back to the detected one when the name is empty
+    pub fn set_syntax(&mut self, name: &str) {
+        if name.is_empty() {
+            self.syntax_name = None;
+            self.detect_syntax();
+        } else if let Some(syntax) = find_syntax(&self.syntaxes, name) {
+            self.syntax_name = Some(syntax.name.clone());
+        } else {
+            self.set_message(&format!("Unknown syntax {}", name));
+            return;
+        }
+        let name = self.get_syntax().map_or("Plain Text", |s| s.name.as_str());
+        self.set_message(&format!("Syntax set to {}", name));
+    }
+
+    // Names of the syntaxes that can be chosen, for completion
+    pub fn get_syntax_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self
+            .syntaxes
+            .syntaxes()
+            .iter()
+            .filter(|s| !s.hidden)
+            .map(|s| s.name.clone())
+            .collect();
+        names.sort_by_key(|n| n.to_lowercase());
+        names.dedup();
+        names
+    }
+
+    pub fn cut(&mut self) -> Clip {
+        let clipboard = self.copy();
+        if self.highlighting {
+            self.remove_highlight();
+            self.highlighting = false;
+        }
+        clipboard
+    }
+    pub fn copy(&mut self) -> Clip {
+        let mut clipboard = vec![];
+        if self.highlighting && self.block_highlight {
+            let (left, right) = self.get_block_cols();
+            for y in min(self.cy, self.hy)..max(self.cy, self.hy) + 1 {
+                let line = self.buffer.get_line(y).unwrap();
+                let start = line.get_byte_index(left, &self.render_opts);
+                let end = line.get_byte_index(right, &self.render_opts);
+                clipboard.push(Line::new(
+                    line.get_clean_raw().get(start..end).unwrap().to_string(),
+                ));
+            }
+            return Clip::new(clipboard, true);
+        }
+        if self.highlighting {
+            if self.cy < self.hy || (self.cy == self.hy && self.cx <= self.hx) {
+                clipboard = self
+                    .buffer
+                    .get_region((self.cx, self.cy), (self.hx, self.hy));
+            } else {
+                clipboard = self
+                    .buffer
+                    .get_region((self.hx, self.hy), (self.cx, self.cy));
+            }
+        }
+        Clip::new(clipboard, false)
+    }
+    pub fn paste(&mut self, clipboard: Option<&Clip>) {
+        self.auto_closers.clear();
+        if let Some(clipboard) = clipboard {
+            if self.highlighting {
+                self.remove_highlight();
+                self.highlighting = false;
+            }
+            if clipboard.is_block() {
+                self.paste_block(clipboard.get_lines());
+            } else if self.cy < self.buffer.get_line_count() {
+                let new_pos =
+                    self.buffer
+                        .insert_region((self.cx, self.cy), clipboard.get_lines(), true);
+                self.move_cursor(Movement::Absolute(new_pos.0, new_pos.1), false);
+                self.confirm_dirty = false;
+            }
+        }
+    }
+
+    // Cuts from the cursor to the end of the line, or through the line break when only
+    // whitespace is left
+    pub fn kill_line(&mut self) -> Clip {
+        let (x, y) = (self.cx, self.cy);
+        let line = self.buffer.get_line(y).unwrap().get_clean_raw();
+        let rest = line.get(x..).unwrap_or("");
+        let end = if rest.trim().is_empty() && y + 1 < self.buffer.get_line_count() {
+            (0, y + 1)
+        } else {
+            (line.len(), y)
+        };
+        self.select_region((x, y), end);
+        self.cut()
+    }
+
+    // Copies lines start..end as whole lines, giving the last one a line break if it has none
+    pub fn copy_lines(&self, start: usize, end: usize) -> Clip {
+        let ending = self.buffer.get_line_ending();
+        let lines = self
+            .buffer
+            .get_lines(start, end)
+            .iter()
+            .map(|l| Line::new(l.get_clean_raw() + ending))
+            .collect();
+        Clip::new_lines(lines)
+    }
+
+    pub fn cut_lines(&mut self, start: usize, end: usize) -> Clip {
+        let clipboard = self.copy_lines(start, end);
+        self.highlighting = false;
+        self.cy = start;
+        self.replace_lines(start, end, vec![], &[]);
+        clipboard
+    }
+
+    // Pastes whole lines above or below the cursor line, moving to the first of them
+    pub fn paste_lines(&mut self, clipboard: &Clip, below: bool) {
+        let mut lines = clipboard.get_lines().to_vec();
+        if lines.is_empty() {
+            return;
+        }
+        self.auto_closers.clear();
+        self.highlighting = false;
+        let y = if below { self.cy + 1 } else { self.cy };
+        if y < self.buffer.get_line_count() {
+            lines.push(Line::new(String::new()));
+            self.buffer.insert_region((0, y), &lines, true);
+        } else {
+            // Below the last line the line break goes before the pasted lines instead
+            let last = self.buffer.get_line_count() - 1;
+            let x = self.buffer.get_line(last).unwrap().get_clean_raw().len();
+            let ending = Line::new(self.buffer.get_line_ending().to_string());
+            let end = Line::new(lines.pop().unwrap().get_clean_raw());
+            lines.insert(0, ending);
+            lines.push(end);
+            self.buffer.insert_region((x, last), &lines, true);
+        }
+        let indent = self.buffer.get_line(y).unwrap().get_indent().len();
+        self.move_cursor(Movement::Absolute(indent, y), false);
+        self.confirm_dirty = false;
+    }
+
+    // Starts a new line above or below the cursor line with the same indentation
+    pub fn open_line(&mut self, above: bool) {
+        let line = self.buffer.get_line(self.cy).unwrap();
+        let indent = line.get_indent().to_string();
+        let x = line.get_clean_raw().len();
+        let ending = self.buffer.get_line_ending().to_string();
+        self.auto_closers.clear();
+        self.highlighting = false;
+        if above {
+            let lines = [
+                Line::new(indent.clone() + &ending),
+                Line::new(String::new()),
+            ];
+            self.buffer.insert_region((0, self.cy), &lines, true);
+            self.move_cursor(Movement::Absolute(indent.len(), self.cy), false);
+        } else {
+            let lines = [Line::new(ending), Line::new(indent.clone())];
+            self.buffer.insert_region((x, self.cy), &lines, true);
+            self.move_cursor(Movement::Absolute(indent.len(), self.cy + 1), false);
+        }
+        self.confirm_dirty = false;
+    }
+
+    // Indents or dedents lines start..end by one level as one undo step
+    pub fn shift_lines(&mut self, start: usize, end: usize, dedent: bool) {
+        let unit = self.get_indent_unit();
+        self.buffer.begin_group();
+        for y in start..end {
+            if dedent {
+                self.dedent_line(y);
+            } else if !self.buffer.get_line(y).unwrap().get_clean_raw().is_empty() {
+                self.buffer
+                    .insert_region((0, y), &[Line::new(unit.clone())], true);
+            }
+        }
+        self.buffer.end_group();
+        self.highlighting = false;
+        self.clamp_selection();
+        self.confirm_dirty = false;
+    }
+
+    // Changes made between these are undone and redone together
+    pub fn begin_undo_group(&mut self) {
+        self.buffer.begin_group();
+    }
+
+    pub fn end_undo_group(&mut self) {
+        self.buffer.end_group();
+    }
+
+    // Replaces lines start..end as one undo step, keeping every line but the last terminated
+    // Replaces lines `start` to `end` with `lines`, with `origins` as in Buffer::replace_lines
+    fn replace_lines(
+        &mut self,
+        start: usize,
+        end: usize,
+        lines: Vec<Line>,
+        origins: &[Option<usize>],
+    ) {
+        let ending = self.buffer.get_line_ending().to_string();
+        let at_end = end == self.buffer.get_line_count();
+        let (start, lines) = match (at_end, lines.is_empty()) {
+            // The new last line of the file must not keep its line break
+            (true, true) if start > 0 => (
+                start - 1,
+                vec![Line::new(
+                    self.buffer.get_line(start - 1).unwrap().get_clean_raw(),
+                )],
+            ),
+            (true, true) => (start, vec![Line::new(String::new())]),
+            _ => (start, lines),
+        };
+        let count = lines.len();
+        let lines = lines
+            .into_iter()
+            .enumerate()
+            .map(|(i, line)| {
+                if at_end && i + 1 == count {
+                    Line::new(line.get_clean_raw())
+                } else if line.get_line_ending().is_empty() {
+                    Line::new(line.get_clean_raw() + &ending)
+                } else {
+                    line
+                }
+            })
+            .collect();
+        self.buffer
+            .replace_lines(start, end - start, lines, origins, true);
+        self.auto_closers.clear();
+        self.clamp_selection();
+        self.confirm_dirty = false;
+    }
+
+    fn clamp_selection(&mut self) {
+        let last = self.buffer.get_line_count() - 1;
+        self.hy = min(self.hy, last);
+        self.hx = min(
+            self.hx,
+            self.buffer.get_line(self.hy).unwrap().get_clean_raw().len(),
+        );
+        self.cy = min(self.cy, last);
+        self.cx = min(
+            self.cx,
+            self.buffer.get_line(self.cy).unwrap().get_clean_raw().len(),
+        );
+        self.scroll();
+    }
+
+    // Duplicates the selected text, or the current or selected lines
+    pub fn duplicate(&mut self) {
+        if self.highlighting && !self.block_highlight {
+            let (start, end) = if (self.cy, self.cx) < (self.hy, self.hx) {
+                ((self.cx, self.cy), (self.hx, self.hy))
+            } else {
+                ((self.hx, self.hy), (self.cx, self.cy))
+            };
+            let text = self.buffer.get_region(start, end);
+            self.buffer.insert_region(end, &text, true);
+            self.confirm_dirty = false;
+        } else {
+            let range = self.get_selected_lines();
+            let mut lines = self.buffer.get_lines(range.start, range.end).to_vec();
+            lines.extend(lines.clone());
+            self.replace_lines(range.start, range.end, lines, &[]);
+        }
+    }
+
+    // Swaps the current or selected lines with the line above or below them
+    pub fn move_lines(&mut self, up: bool) {
+        let range = self.get_selected_lines();
+        let count = self.buffer.get_line_count();
+        // Keep the empty line after a final line break at the end
+        let limit = if self
+            .buffer
+            .get_line(count - 1)
+            .unwrap()
+            .get_raw()
+            .is_empty()
+        {
+            count - 1
+        } else {
+            count
+        };
+        if (up && range.start == 0) || (!up && range.end >= limit) {
+            return;
+        }
+        let (start, end) = if up {
+            (range.start - 1, range.end)
+        } else {
+            (range.start, range.end + 1)
+        };
+        let mut lines = self.buffer.get_lines(start, end).to_vec();
+        let mut origins: Vec<Option<usize>> = (0..lines.len()).map(Some).collect();
+        if up {
+            lines.rotate_left(1);
+            origins.rotate_left(1);
+            self.cy -= 1;
+            self.hy = self.hy.saturating_sub(1);
+        } else {
+            lines.rotate_right(1);
+            origins.rotate_right(1);
+            self.cy += 1;
+            self.hy += 1;
+        }
+        self.replace_lines(start, end, lines, &origins);
+    }
+
+    // Joins the selected lines, or the current line with the next, separated by spaces
+    pub fn join_lines(&mut self) {
+        let range = self.get_selected_lines();
+        let (start, end) = if range.len() > 1 {
+            (range.start, range.end)
+        } else {
+            (range.start, range.start + 2)
+        };
+        if end > self.buffer.get_line_count() {
+            return;
+        }
+        let lines = self.buffer.get_lines(start, end);
+        let mut joined = lines[0].get_clean_raw();
+        for line in &lines[1..] {
+            let text = line.get_clean_raw();
+            let text = text.trim_start();
+            if !text.is_empty() {
+                if !joined.is_empty() && !joined.ends_with(' ') {
+                    joined.push(' ');
+                }
+                joined += text;
+            }
+        }
+        let ending = lines.last().unwrap().get_line_ending().to_string();
+        let first_len = lines[0].get_clean_raw().len();
+        if self.cy != start {
+            self.cx = first_len;
+        }
+        self.cy = start;
+        self.highlighting = false;
+        self.replace_lines(start, end, vec![Line::new(joined + &ending)], &[]);
+    }
+
+    pub fn delete_lines(&mut self) {
+        let range = self.get_selected_lines();
+        self.highlighting = false;
+        self.cy = range.start;
+        self.replace_lines(range.start, range.end, vec![], &[]);
+    }
+
+    // Applies `f` to the text of the selected lines
+    pub fn transform_lines<F: FnOnce(&mut Vec<String>)>(&mut self, f: F) {
+        let range = self.get_selected_lines();
+        if range.len() < 2 {
+            self.set_message(&"Select the lines first");
+            return;
+        }
+        let mut texts: Vec<String> = self
+            .buffer
+            .get_lines(range.start, range.end)
+            .iter()
+            .map(|l| l.get_clean_raw())
+            .collect();
+        let old = texts.clone();
+        f(&mut texts);
+        // Each line takes the marks of the first unclaimed old line with the same text
+        let mut claimed = vec![false; old.len()];
+        let origins: Vec<Option<usize>> = texts
+            .iter()
+            .map(|text| {
+                let i = (0..old.len()).find(|i| !claimed[*i] && old[*i] == *text)?;
+                claimed[i] = true;
+                Some(i)
+            })
+            .collect();
+        let lines = texts.into_iter().map(Line::new).collect();
+        self.replace_lines(range.start, range.end, lines, &origins);
+    }
+
+    pub fn sort_lines(&mut self) {
+        self.transform_lines(|lines| lines.sort());
+    }
+
+    pub fn reverse_lines(&mut self) {
+        self.transform_lines(|lines| lines.reverse());
+    }
+
+    pub fn unique_lines(&mut self) {
+        self.transform_lines(|lines| {
+            let mut seen = std::collections::HashSet::new();
+            lines.retain(|l| seen.insert(l.clone()));
+        });
+    }
+
+    pub fn undo(&mut self) {
+        self.auto_closers.clear();
         self.buffer.undo();
         self.confirm_dirty = false;
     }
 
     pub fn redo(&mut self) {
+        self.auto_closers.clear();
         self.buffer.redo();
         self.confirm_dirty = false;
     }
 
+    // The editing mode shown at the start of the status bar, such as `NORMAL`
+    pub fn set_mode(&mut self, mode: Option<String>) {
+        self.mode = mode;
+    }
+
     pub fn set_message(&mut self, message: &dyn AsRef<str>) {
         self.message = Some(format!(
             "{}: {}",
@@ -578,31 +2535,56 @@ impl Editor {
         if self.buffer.get_line(self.cy).is_none() {
             return;
         }
-        self.rx = convert_cx_to_rx(
-            self.buffer.get_line(self.cy).unwrap(),
-            self.cx,
-            &self.render_opts,
-        );
+        self.reveal_line(self.cy);
+        let hidden = self.get_hidden_lines();
+        if let Some((start, _)) = get_hidden_range(&hidden, self.row_offset) {
+            self.row_offset = start - 1;
+        }
+        self.rx = self.block_col.unwrap_or_else(|| {
+            self.buffer
+                .get_line(self.cy)
+                .unwrap()
+                .get_display_col(self.cx, &self.render_opts)
+        });
 
         if self.rx < self.col_offset {
             self.col_offset = self.rx;
         }
-        let lines = self.buffer.get_line_count();
-        let max_gutter_size = (if lines < 2 { 2 } else { lines + 1 } as f32)
-            .log10()
-            .ceil() as usize
-            + 1;
+        let max_gutter_size = self.get_gutter_width() - 1;
         if self.draw_area.width != 0
             && self.rx + max_gutter_size >= self.col_offset + self.draw_area.width as usize
         {
             self.col_offset = self.rx + max_gutter_size - self.draw_area.width as usize;
         }
+        // Wrapped lines never scroll sideways
+        if self.get_wrap_width().is_some() {
+            self.col_offset = 0;
+        }
         if self.cy < self.row_offset {
             self.row_offset = self.cy;
         }
-        if self.cy >= self.row_offset + self.draw_area.height as usize && self.draw_area.height != 0
+        let height = self.draw_area.height as usize;
+        let row = self.get_cursor_row();
+        // Keep scrolloff rows above and below the cursor where the file has them
+        let scrolloff = min(self.scrolloff, height.saturating_sub(1) / 2);
+        if self.count_rows(&hidden, self.row_offset, self.cy) + row < scrolloff {
+            self.row_offset = self.step_rows_back(&hidden, self.cy, scrolloff - row);
+        }
+        let mut below = self.get_line_rows(self.cy).len() - 1 - row;
+        let mut y = self.cy;
+        while below < scrolloff {
+            let next = self.step_visible(&hidden, y, 1);
+            if next == y {
+                break;
+            }
+            below += self.get_line_rows(next).len();
+            y = next;
+        }
+        let below = min(below, scrolloff);
+        if height != 0 && self.count_rows(&hidden, self.row_offset, self.cy) + row + below >= height
         {
-            self.row_offset = self.cy - self.draw_area.height as usize + 1;
+            self.row_offset =
+                self.step_rows_back(&hidden, self.cy, (height - 1).saturating_sub(row + below));
         }
     }
 }
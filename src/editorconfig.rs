@@ -0,0 +1,219 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use crate::glob::glob_match;
+use crate::indentation::IndentStyle;
+
+#[derive(Clone, Copy, PartialEq)]
+pub enum Charset {
+    Latin1,
+    Utf8,
+    Utf8Bom,
+    Utf16Be,
+    Utf16Le,
+}
+
+impl Charset {
+    pub fn encode(&self, contents: &str) -> Vec<u8> {
+        match self {
+            Charset::Latin1 => contents
+                .chars()
+                .map(|c| if (c as u32) < 0x100 { c as u8 } else { b'?' })
+                .collect(),
+            Charset::Utf8 => contents.as_bytes().to_vec(),
+            Charset::Utf8Bom => [&[0xEF, 0xBB, 0xBF], contents.as_bytes()].concat(),
+            Charset::Utf16Be => std::iter::once(0xFEFF)
+                .chain(contents.encode_utf16())
+                .flat_map(|u| u.to_be_bytes().to_vec())
+                .collect(),
+            Charset::Utf16Le => std::iter::once(0xFEFF)
+                .chain(contents.encode_utf16())
+                .flat_map(|u| u.to_le_bytes().to_vec())
+                .collect(),
+        }
+    }
+
+    // Detects the charset from a byte order mark
+    pub fn detect(bytes: &[u8]) -> Option<Charset> {
+        match bytes {
+            [0xEF, 0xBB, 0xBF, ..] => Some(Charset::Utf8Bom),
+            [0xFE, 0xFF, ..] => Some(Charset::Utf16Be),
+            [0xFF, 0xFE, ..] => Some(Charset::Utf16Le),
+            _ => None,
+        }
+    }
+
+    // Decodes file contents, letting a byte order mark take precedence over the charset
+    pub fn decode(charset: Option<Charset>, bytes: &[u8]) -> std::io::Result<String> {
+        let utf16 = |bytes: &[u8], be: bool| {
+            let units: Vec<u16> = bytes
+                .chunks(2)
+                .map(|c| {
+                    let pair = [c[0], *c.get(1).unwrap_or(&0)];
+                    if be {
+                        u16::from_be_bytes(pair)
+                    } else {
+                        u16::from_le_bytes(pair)
+                    }
+                })
+                .collect();
+            Ok(String::from_utf16_lossy(&units))
+        };
+        match (charset, bytes) {
+            (_, [0xEF, 0xBB, 0xBF, rest @ ..]) => Charset::decode(Some(Charset::Utf8), rest),
+            (_, [0xFE, 0xFF, rest @ ..]) => utf16(rest, true),
+            (_, [0xFF, 0xFE, rest @ ..]) => utf16(rest, false),
+            (Some(Charset::Utf16Be), _) => utf16(bytes, true),
+            (Some(Charset::Utf16Le), _) => utf16(bytes, false),
+            (Some(Charset::Latin1), _) => Ok(bytes.iter().map(|b| *b as char).collect()),
+            _ => String::from_utf8(bytes.to_vec())
+                .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e)),
+        }
+    }
+}
+
+// Settings from the `.editorconfig` files that apply to a file
+#[derive(Clone, Default)]
+pub struct EditorConfig {
+    pub charset: Option<Charset>,
+    pub end_of_line: Option<&'static str>,
+    pub indent_size: Option<usize>,
+    pub indent_tabs: Option<bool>,
+    pub insert_final_newline: Option<bool>,
+    pub tab_width: Option<usize>,
+    pub trim_trailing_whitespace: Option<bool>,
+}
+
+type Section = (String, HashMap<String, String>);
+
+// Returns the sections of an `.editorconfig` file and whether it is marked as root
+fn parse(contents: &str) -> (bool, Vec<Section>) {
+    let mut root = false;
+    let mut sections: Vec<Section> = vec![];
+    for line in contents.lines() {
+        let line = line.trim();
+        if line.is_empty() || line.starts_with('#') || line.starts_with(';') {
+            continue;
+        }
+        if line.starts_with('[') && line.ends_with(']') {
+            sections.push((line[1..line.len() - 1].to_string(), HashMap::new()));
+        } else if let Some(eq) = line.find('=') {
+            let key = line[..eq].trim().to_lowercase();
+            let value = line[eq + 1..].trim().to_lowercase();
+            match sections.last_mut() {
+                Some((_, properties)) => {
+                    properties.insert(key, value);
+                }
+                None if key == "root" => root = value == "true",
+                None => {}
+            }
+        }
+    }
+    (root, sections)
+}
+
+fn section_matches(section: &str, dir: &Path, file: &Path) -> bool {
+    let relative = match file.strip_prefix(dir) {
+        Ok(relative) => relative.to_string_lossy().replace('\\', "/"),
+        Err(_) => return false,
+    };
+    if let Some(anchored) = section.strip_prefix('/') {
+        glob_match(anchored, &relative)
+    } else if section.contains('/') {
+        glob_match(section, &relative)
+    } else {
+        // Patterns without a slash match the file name in any directory
+        let name = relative.rsplit('/').next().unwrap_or("");
+        glob_match(section, name)
+    }
+}
+
+impl EditorConfig {
+    // Walks up from the file's directory collecting matching sections until a root file
+    pub fn load(file: &Path) -> Self {
+        let file = file.canonicalize().unwrap_or_else(|_| file.to_path_buf());
+        let mut files = vec![];
+        let mut dir = file.parent();
+        while let Some(d) = dir {
+            if let Ok(contents) = std::fs::read_to_string(d.join(".editorconfig")) {
+                let (root, sections) = parse(&contents);
+                files.push((d.to_path_buf(), sections));
+                if root {
+                    break;
+                }
+            }
+            dir = d.parent();
+        }
+
+        // Closer files and later sections take precedence
+        let mut properties = HashMap::new();
+        for (dir, sections) in files.iter().rev() {
+            for (section, values) in sections {
+                if section_matches(section, dir, &file) {
+                    properties.extend(values.clone());
+                }
+            }
+        }
+        EditorConfig::from_properties(&properties)
+    }
+
+    fn from_properties(properties: &HashMap<String, String>) -> Self {
+        let get = |key: &str| properties.get(key).map(|v| v.as_str());
+        let get_bool = |key: &str| match get(key) {
+            Some("true") => Some(true),
+            Some("false") => Some(false),
+            _ => None,
+        };
+        // Widths of 0 are as invalid as ones that aren't numbers
+        let parse_width = |v: &str| v.parse().ok().filter(|width: &usize| *width > 0);
+        let tab_width = get("tab_width").and_then(parse_width);
+        let indent_size = match get("indent_size") {
+            Some("tab") => tab_width,
+            Some(size) => parse_width(size),
+            None => None,
+        };
+        EditorConfig {
+            charset: match get("charset") {
+                Some("latin1") => Some(Charset::Latin1),
+                Some("utf-8") => Some(Charset::Utf8),
+                Some("utf-8-bom") => Some(Charset::Utf8Bom),
+                Some("utf-16be") => Some(Charset::Utf16Be),
+                Some("utf-16le") => Some(Charset::Utf16Le),
+                _ => None,
+            },
+            end_of_line: match get("end_of_line") {
+                Some("lf") => Some("\n"),
+                Some("crlf") => Some("\r\n"),
+                Some("cr") => Some("\r"),
+                _ => None,
+            },
+            indent_size,
+            indent_tabs: match get("indent_style") {
+                Some("tab") => Some(true),
+                Some("space") => Some(false),
+                _ => None,
+            },
+            insert_final_newline: get_bool("insert_final_newline"),
+            tab_width: tab_width.or(if get("indent_size") == Some("tab") {
+                None
+            } else {
+                indent_size
+            }),
+            trim_trailing_whitespace: get_bool("trim_trailing_whitespace"),
+        }
+    }
+
+    // Applies indent_style and indent_size on top of the style the file would otherwise use
+    pub fn get_indent_style(&self, current: IndentStyle) -> IndentStyle {
+        match (self.indent_tabs, self.indent_size, current) {
+            (Some(true), _, _) => IndentStyle::Tabs,
+            (Some(false), Some(size), _) => IndentStyle::Spaces(size),
+            (Some(false), None, IndentStyle::Spaces(size)) => IndentStyle::Spaces(size),
+            (Some(false), None, IndentStyle::Tabs) => {
+                IndentStyle::Spaces(self.tab_width.unwrap_or(4))
+            }
+            (None, Some(size), IndentStyle::Spaces(_)) => IndentStyle::Spaces(size),
+            (None, _, current) => current,
+        }
+    }
+}
@@ -0,0 +1,81 @@
+use serde_derive::{Deserialize, Serialize};
+
+use crate::editor::{CLOSERS, OPENERS};
+use crate::line::Line;
+use crate::render_config::RenderConfig;
+
+// How fold regions are found, set in settings.toml with `fold = "indent"` or `fold = "brackets"`
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum FoldMethod {
+    #[default]
+    Indent,
+    Brackets,
+}
+
+// Keeps the largest region starting on each line, ordered by first line
+fn dedup_folds(mut folds: Vec<(usize, usize)>) -> Vec<(usize, usize)> {
+    folds.sort_unstable_by_key(|(start, end)| (*start, std::cmp::Reverse(*end)));
+    folds.dedup_by_key(|(start, _)| *start);
+    folds
+}
+
+// Foldable regions as (first line, last line) pairs where the first line stays visible.
+// A region covers the lines after a line that are indented further than it.
+pub fn indent_folds(lines: &[Line], options: &RenderConfig) -> Vec<(usize, usize)> {
+    let mut folds = vec![];
+    let mut open: Vec<(usize, usize)> = vec![];
+    let mut last = 0;
+    for (y, line) in lines.iter().enumerate() {
+        if line.get_clean_raw().trim().is_empty() {
+            continue;
+        }
+        let width = line.get_display_col(line.get_indent().len(), options);
+        while let Some(&(open_width, start)) = open.last() {
+            if open_width < width {
+                break;
+            }
+            open.pop();
+            if last > start {
+                folds.push((start, last));
+            }
+        }
+        open.push((width, y));
+        last = y;
+    }
+    for (_, start) in open {
+        if last > start {
+            folds.push((start, last));
+        }
+    }
+    dedup_folds(folds)
+}
+
+// Foldable regions between brackets on different lines, leaving the closing line visible.
+// Brackets at positions `is_literal` gives, such as inside strings and comments, are ignored.
+pub fn bracket_folds(
+    lines: &[Line],
+    is_literal: &dyn Fn(usize, usize) -> bool,
+) -> Vec<(usize, usize)> {
+    let mut open: Vec<(char, usize)> = vec![];
+    let mut folds = vec![];
+    for (y, line) in lines.iter().enumerate() {
+        for (x, c) in line.get_clean_raw().char_indices() {
+            if !(OPENERS.contains(c) || CLOSERS.contains(c)) || is_literal(x, y) {
+                continue;
+            }
+            if OPENERS.contains(c) {
+                open.push((c, y));
+            } else if let Some(opener) = CLOSERS.find(c).and_then(|i| OPENERS.chars().nth(i)) {
+                if let Some(i) = open.iter().rposition(|(o, _)| *o == opener) {
+                    let start = open[i].1;
+                    open.truncate(i);
+                    if y > start + 1 {
+                        folds.push((start, y - 1));
+                    }
+                }
+            }
+        }
+    }
+    dedup_folds(folds)
+}
@@ -0,0 +1,53 @@
+// Scores how well `query` matches `candidate` as a case-insensitive subsequence, or None if it
+// doesn't match. Characters starting a word or following the previous match score higher, and
+// an exact match beats everything.
+pub fn fuzzy_score(query: &str, candidate: &str) -> Option<usize> {
+    if query.eq_ignore_ascii_case(candidate) {
+        return Some(usize::MAX);
+    }
+    let chars: Vec<char> = candidate.chars().collect();
+    let mut score = 0;
+    let mut next = 0;
+    let mut last = None;
+    for q in query.chars() {
+        let i = (next..chars.len()).find(|i| chars[*i].eq_ignore_ascii_case(&q))?;
+        score += 1;
+        if i == 0 || !chars[i - 1].is_alphanumeric() {
+            score += 3;
+        }
+        if last.is_some_and(|last| last + 1 == i) {
+            score += 2;
+        }
+        last = Some(i);
+        next = i + 1;
+    }
+    Some(score)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::fuzzy_score;
+
+    #[test]
+    fn matches_subsequences_ignoring_case() {
+        assert!(fuzzy_score("sv", "save").is_some());
+        assert!(fuzzy_score("SV", "save").is_some());
+        assert_eq!(fuzzy_score("vs", "save"), None);
+        assert_eq!(fuzzy_score("saves", "save"), None);
+        assert_eq!(fuzzy_score("", "save"), Some(0));
+    }
+
+    #[test]
+    fn exact_matches_beat_everything() {
+        assert_eq!(fuzzy_score("Save", "save"), Some(usize::MAX));
+        assert!(fuzzy_score("save", "save-theme") < fuzzy_score("save", "save"));
+    }
+
+    #[test]
+    fn word_starts_and_runs_score_higher() {
+        // `dl` starts both words of delete-lines but is scattered through model
+        assert!(fuzzy_score("dl", "delete-lines") > fuzzy_score("dl", "model"));
+        // A run of characters beats the same characters spread out
+        assert!(fuzzy_score("old", "unfold") > fuzzy_score("old", "workload"));
+    }
+}
@@ -0,0 +1,215 @@
+// Glob matching as used by EditorConfig sections and file name mappings:
+// `*` matches anything but `/`, `**` matches anything, `?` matches one character,
+// `[abc]`/`[!a-z]` match character classes and `{a,b}`/`{1..3}` match alternatives
+pub fn glob_match(pattern: &str, path: &str) -> bool {
+    let path: Vec<char> = path.chars().collect();
+    expand_braces(pattern)
+        .iter()
+        .any(|p| match_here(&p.chars().collect::<Vec<char>>(), &path))
+}
+
+fn find_closing_brace(chars: &[char], open: usize) -> Option<usize> {
+    let mut depth = 0;
+    let mut i = open;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '{' => depth += 1,
+            '}' => {
+                depth -= 1;
+                if depth == 0 {
+                    return Some(i);
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+fn split_alternatives(chars: &[char]) -> Vec<String> {
+    let mut parts = vec![];
+    let mut current = String::new();
+    let mut depth = 0;
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' if i + 1 < chars.len() => {
+                current.push('\\');
+                current.push(chars[i + 1]);
+                i += 1;
+            }
+            '{' => {
+                depth += 1;
+                current.push('{');
+            }
+            '}' => {
+                depth -= 1;
+                current.push('}');
+            }
+            ',' if depth == 0 => parts.push(std::mem::take(&mut current)),
+            c => current.push(c),
+        }
+        i += 1;
+    }
+    parts.push(current);
+    parts
+}
+
+fn parse_range(inner: &str) -> Option<Vec<String>> {
+    let mut bounds = inner.splitn(2, "..");
+    let start: i64 = bounds.next()?.parse().ok()?;
+    let end: i64 = bounds.next()?.parse().ok()?;
+    let (start, end) = (start.min(end), start.max(end));
+    if end - start > 1000 {
+        return None;
+    }
+    Some((start..end + 1).map(|n| n.to_string()).collect())
+}
+
+fn expand_braces(pattern: &str) -> Vec<String> {
+    let chars: Vec<char> = pattern.chars().collect();
+    let mut i = 0;
+    while i < chars.len() {
+        match chars[i] {
+            '\\' => i += 1,
+            '{' => {
+                if let Some(close) = find_closing_brace(&chars, i) {
+                    let inner = &chars[i + 1..close];
+                    let alternatives = split_alternatives(inner);
+                    let options = if alternatives.len() > 1 {
+                        Some(alternatives)
+                    } else {
+                        parse_range(&inner.iter().collect::<String>())
+                    };
+                    if let Some(options) = options {
+                        let prefix: String = chars[..i].iter().collect();
+                        let suffix: String = chars[close + 1..].iter().collect();
+                        return options
+                            .iter()
+                            .flat_map(|o| expand_braces(&format!("{}{}{}", prefix, o, suffix)))
+                            .collect();
+                    }
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    vec![pattern.to_string()]
+}
+
+// Matches a `[...]` class starting at p[0], returning whether `c` matched and the class length
+fn match_class(p: &[char], c: char) -> Option<(bool, usize)> {
+    let mut i = 1;
+    let negated = matches!(p.get(i), Some('!') | Some('^'));
+    if negated {
+        i += 1;
+    }
+    let mut matched = false;
+    let mut first = true;
+    while i < p.len() {
+        if p[i] == ']' && !first {
+            return Some((matched != negated, i + 1));
+        }
+        let lo = if p[i] == '\\' && i + 1 < p.len() {
+            i += 1;
+            p[i]
+        } else {
+            p[i]
+        };
+        if p.get(i + 1) == Some(&'-') && i + 2 < p.len() && p[i + 2] != ']' {
+            if lo <= c && c <= p[i + 2] {
+                matched = true;
+            }
+            i += 3;
+        } else {
+            if lo == c {
+                matched = true;
+            }
+            i += 1;
+        }
+        first = false;
+    }
+    None
+}
+
+fn match_here(p: &[char], s: &[char]) -> bool {
+    match p.first() {
+        None => s.is_empty(),
+        Some('*') if p.get(1) == Some(&'*') => {
+            let rest = &p[2..];
+            (0..s.len() + 1).any(|i| match_here(rest, &s[i..]))
+        }
+        Some('*') => {
+            let rest = &p[1..];
+            for i in 0..s.len() + 1 {
+                if match_here(rest, &s[i..]) {
+                    return true;
+                }
+                if i < s.len() && s[i] == '/' {
+                    break;
+                }
+            }
+            false
+        }
+        Some('?') => !s.is_empty() && s[0] != '/' && match_here(&p[1..], &s[1..]),
+        Some('[') => match (s.first(), match_class(p, *s.first().unwrap_or(&'\0'))) {
+            (Some(c), Some((matched, len))) => {
+                *c != '/' && matched && match_here(&p[len..], &s[1..])
+            }
+            (Some('['), None) => match_here(&p[1..], &s[1..]),
+            _ => false,
+        },
+        Some('\\') if p.len() > 1 => s.first() == Some(&p[1]) && match_here(&p[2..], &s[1..]),
+        Some(c) => s.first() == Some(c) && match_here(&p[1..], &s[1..]),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::glob_match;
+
+    #[test]
+    fn stars_and_question_marks() {
+        assert!(glob_match("*.rs", "main.rs"));
+        assert!(!glob_match("*.rs", "src/main.rs"));
+        assert!(glob_match("**/*.rs", "src/bin/main.rs"));
+        assert!(glob_match("src/**", "src/a/b"));
+        assert!(glob_match("?.md", "a.md"));
+        assert!(!glob_match("?.md", "ab.md"));
+        assert!(!glob_match("a?b", "a/b"));
+    }
+
+    #[test]
+    fn character_classes() {
+        assert!(glob_match("[abc].txt", "b.txt"));
+        assert!(!glob_match("[abc].txt", "d.txt"));
+        assert!(glob_match("[!a-c].txt", "d.txt"));
+        assert!(!glob_match("[^a-c].txt", "b.txt"));
+        assert!(glob_match("[a-z]1", "q1"));
+        assert!(glob_match("[]]", "]"));
+        assert!(glob_match("[-a]", "-"));
+        // An unclosed class matches a literal bracket
+        assert!(glob_match("[ab", "[ab"));
+    }
+
+    #[test]
+    fn braces_and_ranges() {
+        assert!(glob_match("*.{js,ts}", "index.ts"));
+        assert!(!glob_match("*.{js,ts}", "index.rs"));
+        assert!(glob_match("{a,{b,c}}.x", "c.x"));
+        assert!(glob_match("file{1..3}", "file2"));
+        assert!(!glob_match("file{1..3}", "file4"));
+        assert!(glob_match("file{3..1}", "file1"));
+        assert!(glob_match("{single}", "{single}"));
+    }
+
+    #[test]
+    fn escapes() {
+        assert!(glob_match("\\*.txt", "*.txt"));
+        assert!(!glob_match("\\*.txt", "a.txt"));
+        assert!(glob_match("a\\{b,c\\}", "a{b,c}"));
+    }
+}
@@ -0,0 +1,252 @@
+use std::cmp::min;
+use std::sync::mpsc::{channel, Receiver, Sender, TryRecvError};
+use std::thread;
+use std::time::{Duration, Instant};
+
+use syntect::highlighting::{HighlightIterator, HighlightState, Highlighter, Style, Theme};
+use syntect::parsing::{ParseState, Scope, ScopeStack, ScopeStackOp, SyntaxReference, SyntaxSet};
+
+use crate::line::Line;
+
+// Parse and highlight state at the start of every line parsed so far, so a line is only
+// parsed again after an edit above it and highlighting always starts from the top of the file
+#[derive(Default)]
+pub struct HighlightCache {
+    states: Vec<(ParseState, HighlightState)>,
+    syntax: Option<String>,
+}
+
+impl HighlightCache {
+    pub fn clear(&mut self) {
+        self.states.clear();
+    }
+
+    // Drops the state of the lines after `line`, which an edit to it may have changed
+    pub fn invalidate(&mut self, line: usize) {
+        self.states.truncate(line + 1);
+    }
+
+    // State at the start of line `y`, parsing any lines above it that aren't cached yet
+    fn get_state(
+        &mut self,
+        lines: &[Line],
+        y: usize,
+        syntax: &SyntaxReference,
+        syntaxes: &SyntaxSet,
+        highlighter: &Highlighter,
+    ) -> (ParseState, HighlightState) {
+        if self.syntax.as_ref() != Some(&syntax.name) {
+            self.states.clear();
+            self.syntax = Some(syntax.name.clone());
+        }
+        if self.states.is_empty() {
+            self.states.push((
+                ParseState::new(syntax),
+                HighlightState::new(highlighter, ScopeStack::new()),
+            ));
+        }
+        while self.states.len() <= y {
+            let (mut parse, mut highlight) = self.states.last().unwrap().clone();
+            let line = lines[self.states.len() - 1].get_raw();
+            let ops = parse.parse_line(line, syntaxes);
+            HighlightIterator::new(&mut highlight, &ops, line, highlighter).for_each(drop);
+            self.states.push((parse, highlight));
+        }
+        self.states[y].clone()
+    }
+
+    pub fn highlight_line(
+        &mut self,
+        lines: &[Line],
+        y: usize,
+        syntax: &SyntaxReference,
+        syntaxes: &SyntaxSet,
+        highlighter: &Highlighter,
+    ) -> HighlightedLine {
+        let (mut parse, mut highlight) = self.get_state(lines, y, syntax, syntaxes, highlighter);
+        let line = lines[y].get_raw();
+        let ops = parse.parse_line(line, syntaxes);
+        let literals = get_literal_ranges(highlight.path.clone(), &ops, line.len());
+        let ranges = HighlightIterator::new(&mut highlight, &ops, line, highlighter)
+            .map(|(style, text)| (style, text.to_string()))
+            .collect();
+        if self.states.len() == y + 1 {
+            self.states.push((parse, highlight));
+        }
+        HighlightedLine { ranges, literals }
+    }
+}
+
+// Byte ranges of a line inside strings or comments, from the scopes at its start and its
+// parse operations. Each byte has the scopes of the operations at or before it.
+fn get_literal_ranges(
+    mut stack: ScopeStack,
+    ops: &[(usize, ScopeStackOp)],
+    len: usize,
+) -> Vec<(usize, usize)> {
+    let string = Scope::new("string").unwrap();
+    let comment = Scope::new("comment").unwrap();
+    let is_literal = |stack: &ScopeStack| {
+        stack
+            .as_slice()
+            .iter()
+            .any(|s| string.is_prefix_of(*s) || comment.is_prefix_of(*s))
+    };
+    let mut ranges: Vec<(usize, usize)> = vec![];
+    let mut push = |start: usize, end: usize| match ranges.last_mut() {
+        Some(last) if last.1 == start => last.1 = end,
+        _ if start < end => ranges.push((start, end)),
+        _ => {}
+    };
+    let mut start = 0;
+    for (index, op) in ops {
+        if is_literal(&stack) {
+            push(start, min(*index, len));
+        }
+        start = min(*index, len);
+        stack.apply(op);
+    }
+    if is_literal(&stack) {
+        push(start, len);
+    }
+    ranges
+}
+
+// Longest the highlighting thread works before sending what it has and checking for edits
+const WORK_BUDGET: Duration = Duration::from_millis(20);
+
+// A line's text split into styled ranges, and the byte ranges of it inside strings or comments
+#[derive(Clone, Default)]
+pub struct HighlightedLine {
+    pub ranges: Vec<(Style, String)>,
+    pub literals: Vec<(usize, usize)>,
+}
+
+impl HighlightedLine {
+    // Whether byte `x` of the line is inside a string or comment
+    pub fn is_literal(&self, x: usize) -> bool {
+        self.literals
+            .iter()
+            .any(|(start, end)| *start <= x && x < *end)
+    }
+}
+
+// Highlighted lines from `start` on, produced for the buffer version given first
+type Response = (usize, usize, Vec<HighlightedLine>);
+
+enum Request {
+    // Starts over with a new version, syntax and theme
+    Reset(usize, Option<String>, Box<Theme>),
+    // Replaces the lines from the first line up to the second with a new version's
+    Edit(usize, usize, usize, Vec<Line>),
+    // Highlights lines up to (but not including) a line
+    Show(usize),
+}
+
+// Highlights lines on a background thread so input and drawing never wait on syntect
+pub struct HighlightWorker {
+    requests: Sender<Request>,
+    responses: Receiver<Response>,
+}
+
+impl HighlightWorker {
+    pub fn new(syntaxes: SyntaxSet) -> Self {
+        let (requests, request_receiver) = channel();
+        let (response_sender, responses) = channel();
+        thread::spawn(move || run_worker(syntaxes, request_receiver, response_sender));
+        HighlightWorker {
+            requests,
+            responses,
+        }
+    }
+
+    pub fn reset(&self, version: usize, syntax: Option<String>, theme: Theme) {
+        self.requests
+            .send(Request::Reset(version, syntax, Box::new(theme)))
+            .ok();
+    }
+
+    pub fn edit(&self, version: usize, from: usize, to: usize, lines: Vec<Line>) {
+        self.requests
+            .send(Request::Edit(version, from, to, lines))
+            .ok();
+    }
+
+    pub fn show(&self, until: usize) {
+        self.requests.send(Request::Show(until)).ok();
+    }
+
+    // Takes the next batch of highlighted lines without waiting for one
+    pub fn try_receive(&self) -> Option<(usize, usize, Vec<HighlightedLine>)> {
+        self.responses.try_recv().ok()
+    }
+}
+
+fn run_worker(syntaxes: SyntaxSet, requests: Receiver<Request>, responses: Sender<Response>) {
+    let mut cache = HighlightCache::default();
+    let mut lines: Vec<Line> = vec![];
+    let mut syntax = None;
+    let mut theme = Theme::default();
+    let mut version = 0;
+    let mut until = 0;
+    let mut next = 0;
+    loop {
+        // Take every pending request before doing more work, waiting for one when idle
+        let working = syntax.is_some() && next < min(until, lines.len());
+        let request = if working {
+            match requests.try_recv() {
+                Ok(request) => Some(request),
+                Err(TryRecvError::Empty) => None,
+                Err(TryRecvError::Disconnected) => return,
+            }
+        } else {
+            match requests.recv() {
+                Ok(request) => Some(request),
+                Err(_) => return,
+            }
+        };
+        if let Some(request) = request {
+            match request {
+                Request::Reset(new_version, new_syntax, new_theme) => {
+                    version = new_version;
+                    syntax = new_syntax;
+                    theme = *new_theme;
+                    lines.clear();
+                    cache.clear();
+                    next = 0;
+                }
+                Request::Edit(new_version, from, to, new_lines) => {
+                    version = new_version;
+                    let to = min(to, lines.len());
+                    lines.splice(min(from, to)..to, new_lines);
+                    cache.invalidate(from);
+                    next = min(next, from);
+                }
+                Request::Show(line) => until = line,
+            }
+            continue;
+        }
+
+        let syntax = match syntax
+            .as_ref()
+            .and_then(|name| syntaxes.find_syntax_by_name(name))
+        {
+            Some(syntax) => syntax,
+            None => {
+                until = 0;
+                continue;
+            }
+        };
+        let highlighter = Highlighter::new(&theme);
+        let start = next;
+        let started = Instant::now();
+        let mut highlighted = vec![];
+        while next < min(until, lines.len()) && started.elapsed() < WORK_BUDGET {
+            highlighted.push(cache.highlight_line(&lines, next, syntax, &syntaxes, &highlighter));
+            next += 1;
+        }
+        if responses.send((version, start, highlighted)).is_err() {
+            return;
+        }
+    }
+}
@@ -0,0 +1,123 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::line::Line;
+
+// How a buffer is indented, configurable per file extension in settings.toml as
+// `rs = { style = "spaces", width = 4 }` or `go = { style = "tabs" }` under `[indent]`
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "style", content = "width", rename_all = "lowercase")]
+pub enum IndentStyle {
+    #[default]
+    Tabs,
+    Spaces(usize),
+}
+
+pub type IndentOverrides = HashMap<String, IndentStyle>;
+
+impl fmt::Display for IndentStyle {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            IndentStyle::Tabs => write!(f, "Tabs"),
+            IndentStyle::Spaces(width) => write!(f, "Spaces: {}", width),
+        }
+    }
+}
+
+impl IndentStyle {
+    // The text inserted for one level of indentation
+    pub fn get_unit(&self) -> String {
+        match self {
+            IndentStyle::Tabs => "\t".to_string(),
+            IndentStyle::Spaces(width) => " ".repeat(*width),
+        }
+    }
+
+    // Screen columns one level of indentation takes up
+    pub fn get_width(&self, tab_size: usize) -> usize {
+        match self {
+            IndentStyle::Tabs => tab_size,
+            IndentStyle::Spaces(width) => *width,
+        }
+    }
+
+    // Guesses the style from leading whitespace, or None if nothing is indented
+    pub fn detect(lines: &[Line]) -> Option<IndentStyle> {
+        let mut tabs = 0;
+        let mut spaces = 0;
+        let mut deltas: HashMap<usize, usize> = HashMap::new();
+        let mut previous = 0;
+        for line in lines {
+            if line.get_clean_raw().trim().is_empty() {
+                continue;
+            }
+            let indent = line.get_indent();
+            if indent.starts_with('\t') {
+                tabs += 1;
+                continue;
+            }
+            let width = indent.len();
+            if width > 0 {
+                spaces += 1;
+            }
+            if width != previous {
+                *deltas.entry(width.abs_diff(previous)).or_insert(0) += 1;
+            }
+            previous = width;
+        }
+
+        if tabs == 0 && spaces == 0 {
+            None
+        } else if tabs >= spaces {
+            Some(IndentStyle::Tabs)
+        } else {
+            // Steps wider than 8 are usually alignment, unless there are no others
+            let most_common = |max_delta: usize| {
+                deltas
+                    .iter()
+                    .filter(|(delta, _)| **delta <= max_delta)
+                    .max_by_key(|(delta, count)| (**count, **delta))
+                    .map(|(delta, _)| IndentStyle::Spaces(*delta))
+            };
+            most_common(8).or_else(|| most_common(usize::MAX))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn detect(lines: &[&str]) -> Option<IndentStyle> {
+        let lines: Vec<Line> = lines.iter().map(|l| Line::new(l.to_string())).collect();
+        IndentStyle::detect(&lines)
+    }
+
+    #[test]
+    fn detects_the_most_common_step() {
+        assert_eq!(
+            detect(&["a\n", "  b\n", "    c\n", "  d\n"]),
+            Some(IndentStyle::Spaces(2))
+        );
+        assert_eq!(
+            detect(&["a\n", "\tb\n", "\t\tc\n"]),
+            Some(IndentStyle::Tabs)
+        );
+        assert_eq!(detect(&["a\n", "b\n"]), None);
+    }
+
+    #[test]
+    fn wide_steps_still_mean_spaces() {
+        assert_eq!(
+            detect(&["a\n", "            b\n", "c\n", "            d\n"]),
+            Some(IndentStyle::Spaces(12))
+        );
+        // Alignment wider than 8 doesn't count when there are narrower steps
+        assert_eq!(
+            detect(&["a\n", "    b\n", "                    c\n", "    d\n"]),
+            Some(IndentStyle::Spaces(4))
+        );
+    }
+}
@@ -0,0 +1,458 @@
+use std::collections::HashMap;
+use std::fmt;
+
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+
+use crate::command::is_command;
+use crate::settings::Profile;
+
+// Maps key sequences such as `"ctrl-k ctrl-c"` to commands under `[keys]` in settings.toml
+pub type KeyBindings = HashMap<String, String>;
+
+// The bindings used unless settings.toml changes them
+const DEFAULT_BINDINGS: &[(&str, &str)] = &[
+    ("ctrl-q", "quit"),
+    ("ctrl-z", "undo"),
+    ("ctrl-y", "redo"),
+    ("ctrl-p", "prev-editor"),
+    ("ctrl-n", "next-editor"),
+    ("ctrl-b", "new-editor"),
+    ("ctrl-r", "reload"),
+    ("ctrl-s", "save"),
+    ("ctrl-o", "open"),
+    ("ctrl-e", "palette"),
+    ("ctrl-g", "goto"),
+    ("alt-,", "back"),
+    ("alt-.", "forward"),
+    // Terminals usually report Ctrl-] as Ctrl-5 and Ctrl-/ as Ctrl-7
+    ("ctrl-]", "bracket"),
+    ("ctrl-5", "bracket"),
+    ("ctrl-/", "comment"),
+    ("ctrl-7", "comment"),
+    ("ctrl-d", "duplicate"),
+    ("ctrl-j", "join"),
+    ("ctrl-k", "delete-lines"),
+    ("alt-up", "move-up"),
+    ("alt-down", "move-down"),
+    ("alt--", "fold"),
+    ("alt-=", "unfold"),
+    ("alt-_", "fold-all"),
+    ("alt-+", "unfold-all"),
+    ("alt-z", "wrap"),
+    ("f2", "next-mark"),
+    ("shift-f2", "prev-mark"),
+    ("ctrl-f2", "mark"),
+    ("f3", "record-macro"),
+    ("f4", "play-macro"),
+    ("shift-f4", "play-macro-lines"),
+    ("ctrl-c", "copy"),
+    ("ctrl-x", "cut"),
+    ("ctrl-v", "paste"),
+    ("alt-v", "paste-from"),
+    ("alt-shift-left", "block-left"),
+    ("alt-shift-right", "block-right"),
+    ("alt-shift-up", "block-up"),
+    ("alt-shift-down", "block-down"),
+    ("left", "left"),
+    ("right", "right"),
+    ("up", "up"),
+    ("down", "down"),
+    ("shift-left", "select-left"),
+    ("shift-right", "select-right"),
+    ("shift-up", "select-up"),
+    ("shift-down", "select-down"),
+    ("ctrl-left", "word-left"),
+    ("ctrl-right", "word-right"),
+    ("ctrl-shift-left", "select-word-left"),
+    ("ctrl-shift-right", "select-word-right"),
+    ("alt-left", "subword-left"),
+    ("alt-right", "subword-right"),
+    ("ctrl-up", "scroll-up"),
+    ("ctrl-down", "scroll-down"),
+    ("ctrl-shift-up", "select-scroll-up"),
+    ("ctrl-shift-down", "select-scroll-down"),
+    ("home", "home"),
+    ("end", "end"),
+    ("shift-home", "select-home"),
+    ("shift-end", "select-end"),
+    ("ctrl-home", "file-start"),
+    ("ctrl-end", "file-end"),
+    ("ctrl-shift-home", "select-file-start"),
+    ("ctrl-shift-end", "select-file-end"),
+    ("pageup", "page-up"),
+    ("pagedown", "page-down"),
+    ("shift-pageup", "select-page-up"),
+    ("shift-pagedown", "select-page-down"),
+    ("backspace", "backspace"),
+    ("ctrl-backspace", "delete-word-left"),
+    ("ctrl-h", "delete-word-left"),
+    ("delete", "delete"),
+    ("ctrl-delete", "delete-word-right"),
+    ("enter", "newline"),
+    ("tab", "indent"),
+    ("backtab", "dedent"),
+];
+
+// Bindings `profile = "emacs"` puts on top of the defaults
+const EMACS_BINDINGS: &[(&str, &str)] = &[
+    ("ctrl-a", "home"),
+    ("ctrl-e", "end"),
+    ("ctrl-f", "right"),
+    ("ctrl-b", "left"),
+    ("ctrl-n", "down"),
+    ("ctrl-p", "up"),
+    ("alt-f", "word-right"),
+    ("alt-b", "word-left"),
+    ("ctrl-v", "page-down"),
+    ("alt-v", "page-up"),
+    ("alt-<", "file-start"),
+    ("alt->", "file-end"),
+    ("ctrl-space", "set-mark"),
+    ("ctrl-g", "cancel"),
+    ("ctrl-k", "kill-line"),
+    ("ctrl-w", "cut"),
+    ("alt-w", "copy"),
+    ("ctrl-y", "paste"),
+    ("alt-y", "yank-pop"),
+    ("ctrl-d", "delete"),
+    ("alt-d", "delete-word-right"),
+    ("alt-backspace", "delete-word-left"),
+    ("ctrl-s", "search-forward"),
+    ("ctrl-r", "search-backward"),
+    ("ctrl-/", "undo"),
+    ("ctrl-7", "undo"),
+    ("ctrl-x u", "undo"),
+    ("alt-x", "palette"),
+    ("alt-g g", "goto"),
+    ("alt-g alt-g", "goto"),
+    ("ctrl-x ctrl-s", "save"),
+    ("ctrl-x ctrl-f", "open"),
+    ("ctrl-x k", "quit"),
+    ("ctrl-x ctrl-c", "quit"),
+    ("ctrl-x left", "prev-editor"),
+    ("ctrl-x right", "next-editor"),
+    ("ctrl-x (", "record-macro"),
+    ("ctrl-x )", "record-macro"),
+    ("ctrl-x e", "play-macro"),
+];
+
+const KEY_NAMES: &[(&str, KeyCode)] = &[
+    ("backspace", KeyCode::Backspace),
+    ("enter", KeyCode::Enter),
+    ("left", KeyCode::Left),
+    ("right", KeyCode::Right),
+    ("up", KeyCode::Up),
+    ("down", KeyCode::Down),
+    ("home", KeyCode::Home),
+    ("end", KeyCode::End),
+    ("pageup", KeyCode::PageUp),
+    ("pagedown", KeyCode::PageDown),
+    ("tab", KeyCode::Tab),
+    ("backtab", KeyCode::BackTab),
+    ("delete", KeyCode::Delete),
+    ("insert", KeyCode::Insert),
+    ("esc", KeyCode::Esc),
+    ("space", KeyCode::Char(' ')),
+];
+
+// A key with its modifiers, written like `ctrl-shift-left`
+#[derive(Clone, Copy, Debug, Eq, Hash, PartialEq)]
+pub struct KeyChord {
+    code: KeyCode,
+    modifiers: KeyModifiers,
+}
+
+impl fmt::Display for KeyChord {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (modifier, name) in [
+            (KeyModifiers::CONTROL, "ctrl-"),
+            (KeyModifiers::ALT, "alt-"),
+            (KeyModifiers::SHIFT, "shift-"),
+        ] {
+            if self.modifiers.contains(modifier) {
+                write!(f, "{}", name)?;
+            }
+        }
+        match self.code {
+            KeyCode::F(n) => write!(f, "f{}", n),
+            KeyCode::Char(c) if c != ' ' => write!(f, "{}", c),
+            code => {
+                let name = KEY_NAMES.iter().find(|(_, c)| *c == code);
+                write!(f, "{}", name.map_or("?", |(name, _)| name))
+            }
+        }
+    }
+}
+
+impl KeyChord {
+    pub fn new(code: KeyCode, modifiers: KeyModifiers) -> Self {
+        // Shifted characters and Shift-Tab already carry the shift
+        let modifiers = match code {
+            KeyCode::Char(_) | KeyCode::BackTab => modifiers - KeyModifiers::SHIFT,
+            _ => modifiers,
+        };
+        KeyChord { code, modifiers }
+    }
+
+    pub fn from_event(event: &KeyEvent) -> Self {
+        KeyChord::new(event.code, event.modifiers)
+    }
+
+    pub fn to_event(self) -> KeyEvent {
+        KeyEvent::new(self.code, self.modifiers)
+    }
+
+    pub fn get_code(&self) -> KeyCode {
+        self.code
+    }
+
+    pub fn get_modifiers(&self) -> KeyModifiers {
+        self.modifiers
+    }
+
+    pub fn parse(text: &str) -> Option<KeyChord> {
+        let mut rest = text;
+        let mut modifiers = KeyModifiers::NONE;
+        loop {
+            let lower = rest.to_lowercase();
+            // A lone `-` after the modifiers is the minus key itself
+            let modifier = if lower.len() > 1 && lower.starts_with("ctrl-") {
+                KeyModifiers::CONTROL
+            } else if lower.len() > 1 && lower.starts_with("alt-") {
+                KeyModifiers::ALT
+            } else if lower.len() > 1 && lower.starts_with("shift-") {
+                KeyModifiers::SHIFT
+            } else {
+                break;
+            };
+            modifiers |= modifier;
+            rest = &rest[rest.find('-').unwrap() + 1..];
+        }
+        let mut chars = rest.chars();
+        let code = match (chars.next(), chars.next()) {
+            (Some(c), None) => KeyCode::Char(c),
+            _ => {
+                let lower = rest.to_lowercase();
+                match KEY_NAMES.iter().find(|(name, _)| *name == lower) {
+                    Some((_, code)) => *code,
+                    None => KeyCode::F(lower.strip_prefix('f')?.parse().ok()?),
+                }
+            }
+        };
+        Some(KeyChord::new(code, modifiers))
+    }
+
+    // Characters typed without Ctrl or Alt are inserted rather than looked up
+    pub fn get_char(&self) -> Option<char> {
+        match self.code {
+            KeyCode::Char(c) if (self.modifiers - KeyModifiers::SHIFT).is_empty() => Some(c),
+            _ => None,
+        }
+    }
+}
+
+pub fn parse_sequence(text: &str) -> Option<Vec<KeyChord>> {
+    let sequence: Option<Vec<KeyChord>> = text.split_whitespace().map(KeyChord::parse).collect();
+    sequence.filter(|s| !s.is_empty())
+}
+
+pub fn format_sequence(sequence: &[KeyChord]) -> String {
+    let chords: Vec<String> = sequence.iter().map(|c| c.to_string()).collect();
+    chords.join(" ")
+}
+
+pub enum KeyMatch {
+    // The keys so far start at least one longer sequence
+    Pending,
+    Command(String),
+    Unbound(Vec<KeyChord>),
+}
+
+pub struct Keymap {
+    bindings: HashMap<Vec<KeyChord>, String>,
+    pending: Vec<KeyChord>,
+}
+
+impl Keymap {
+    // Builds the default bindings with the profile's and then the user's on top, describing any
+    // that can't be used. Binding a sequence to "none" removes it.
+    pub fn new(overrides: &KeyBindings, profile: Profile) -> (Keymap, Vec<String>) {
+        let mut bindings = HashMap::new();
+        for (keys, command) in DEFAULT_BINDINGS {
+            bindings.insert(parse_sequence(keys).unwrap(), command.to_string());
+        }
+        let profile_bindings = match profile {
+            Profile::Emacs => EMACS_BINDINGS,
+            Profile::Default | Profile::Vim => &[],
+        };
+        for (keys, command) in profile_bindings {
+            let sequence = parse_sequence(keys).unwrap();
+            // A default key the profile uses to start a sequence, such as Ctrl-x, gives way
+            bindings.retain(|k: &Vec<KeyChord>, _| {
+                !sequence.starts_with(k) && !k.starts_with(&sequence)
+            });
+            bindings.insert(sequence, command.to_string());
+        }
+        let mut errors = vec![];
+        let mut overrides: Vec<(&String, &String)> = overrides.iter().collect();
+        overrides.sort();
+        for (keys, command) in overrides {
+            let name = command.split_whitespace().next().unwrap_or("");
+            match parse_sequence(keys) {
+                None => errors.push(format!("Invalid key binding `{}`", keys)),
+                Some(sequence) if name == "none" => {
+                    bindings.remove(&sequence);
+                }
+                Some(_) if !is_command(name) => {
+                    errors.push(format!("Unknown command `{}` bound to `{}`", name, keys))
+                }
+                Some(sequence) => {
+                    // A binding the new one starts with or is the start of could never run
+                    let mut conflicts: Vec<Vec<KeyChord>> = bindings
+                        .keys()
+                        .filter(|k| {
+                            **k != sequence && (sequence.starts_with(k) || k.starts_with(&sequence))
+                        })
+                        .cloned()
+                        .collect();
+                    conflicts.sort_by_key(|k| format_sequence(k));
+                    for conflict in conflicts {
+                        let old = bindings.remove(&conflict).unwrap_or_default();
+                        errors.push(format!(
+                            "`{}` ({}) was unbound as it conflicts with `{}`",
+                            format_sequence(&conflict),
+                            old,
+                            keys
+                        ));
+                    }
+                    bindings.insert(sequence, command.trim().to_string());
+                }
+            }
+        }
+        let keymap = Keymap {
+            bindings,
+            pending: vec![],
+        };
+        (keymap, errors)
+    }
+
+    // Adds a key to the sequence being typed, preferring longer sequences over shorter ones
+    pub fn feed(&mut self, chord: KeyChord) -> KeyMatch {
+        self.pending.push(chord);
+        let pending = &self.pending;
+        let longer = self
+            .bindings
+            .keys()
+            .any(|k| k.len() > pending.len() && k.starts_with(pending));
+        if longer {
+            return KeyMatch::Pending;
+        }
+        let sequence = std::mem::take(&mut self.pending);
+        match self.bindings.get(&sequence) {
+            Some(command) => KeyMatch::Command(command.clone()),
+            None => KeyMatch::Unbound(sequence),
+        }
+    }
+
+    // The command a single key runs on its own, without starting a sequence
+    pub fn get_command(&self, chord: KeyChord) -> Option<&str> {
+        self.bindings.get(&vec![chord]).map(|c| c.as_str())
+    }
+
+    pub fn get_pending(&self) -> &[KeyChord] {
+        &self.pending
+    }
+
+    // The active bindings as (keys, command), ordered by command
+    pub fn get_bindings(&self) -> Vec<(String, String)> {
+        let mut bindings: Vec<(String, String)> = self
+            .bindings
+            .iter()
+            .map(|(keys, command)| (format_sequence(keys), command.clone()))
+            .collect();
+        bindings.sort_by(|a, b| a.1.cmp(&b.1).then(a.0.cmp(&b.0)));
+        bindings
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chord(code: KeyCode, modifiers: KeyModifiers) -> Option<KeyChord> {
+        Some(KeyChord::new(code, modifiers))
+    }
+
+    #[test]
+    fn parses_modifiers_in_any_case() {
+        assert_eq!(
+            KeyChord::parse("ctrl-a"),
+            chord(KeyCode::Char('a'), KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            KeyChord::parse("Ctrl-Alt-Left"),
+            chord(KeyCode::Left, KeyModifiers::CONTROL | KeyModifiers::ALT)
+        );
+        assert_eq!(
+            KeyChord::parse("shift-tab"),
+            chord(KeyCode::Tab, KeyModifiers::SHIFT)
+        );
+        assert_eq!(
+            KeyChord::parse("space"),
+            chord(KeyCode::Char(' '), KeyModifiers::NONE)
+        );
+        assert_eq!(KeyChord::parse("hyper-a"), None);
+    }
+
+    #[test]
+    fn lone_minus_after_modifiers() {
+        assert_eq!(
+            KeyChord::parse("-"),
+            chord(KeyCode::Char('-'), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            KeyChord::parse("alt--"),
+            chord(KeyCode::Char('-'), KeyModifiers::ALT)
+        );
+        assert_eq!(
+            KeyChord::parse("ctrl-alt--"),
+            chord(
+                KeyCode::Char('-'),
+                KeyModifiers::CONTROL | KeyModifiers::ALT
+            )
+        );
+        assert_eq!(KeyChord::parse("ctrl-"), None);
+    }
+
+    #[test]
+    fn function_keys_and_letters() {
+        assert_eq!(
+            KeyChord::parse("f12"),
+            chord(KeyCode::F(12), KeyModifiers::NONE)
+        );
+        assert_eq!(
+            KeyChord::parse("ctrl-F2"),
+            chord(KeyCode::F(2), KeyModifiers::CONTROL)
+        );
+        assert_eq!(
+            KeyChord::parse("f"),
+            chord(KeyCode::Char('f'), KeyModifiers::NONE)
+        );
+        assert_eq!(KeyChord::parse("fx"), None);
+    }
+
+    #[test]
+    fn formats_what_it_parses() {
+        for text in ["ctrl-shift-left", "alt--", "f12", "f", "space", "ctrl-k"] {
+            assert_eq!(KeyChord::parse(text).unwrap().to_string(), text);
+        }
+        assert_eq!(
+            parse_sequence("ctrl-k  ctrl-c"),
+            Some(vec![
+                KeyChord::new(KeyCode::Char('k'), KeyModifiers::CONTROL),
+                KeyChord::new(KeyCode::Char('c'), KeyModifiers::CONTROL),
+            ])
+        );
+        assert_eq!(parse_sequence(" "), None);
+    }
+}
@@ -0,0 +1,69 @@
+use crate::clip::Clip;
+
+// Recently copied and cut text, newest last. Pasting uses the entry being yanked, which is the
+// newest until yank-pop moves back through the older ones.
+pub struct KillRing {
+    clips: Vec<Clip>,
+    index: usize,
+    // How many kills are kept before the oldest is dropped
+    size: usize,
+}
+
+impl KillRing {
+    pub fn new(size: usize) -> Self {
+        KillRing {
+            clips: vec![],
+            index: 0,
+            size,
+        }
+    }
+
+    pub fn set_size(&mut self, size: usize) {
+        self.size = size;
+        if self.clips.len() > size {
+            self.clips.drain(..self.clips.len() - size);
+            self.index = self.clips.len() - 1;
+        }
+    }
+
+    // Adds a kill as the newest entry, ignoring empty ones
+    pub fn push(&mut self, clip: Clip) {
+        if clip.get_text().is_empty() {
+            return;
+        }
+        if self.clips.len() == self.size {
+            self.clips.remove(0);
+        }
+        self.clips.push(clip);
+        self.index = self.clips.len() - 1;
+    }
+
+    // Joins a kill onto the newest entry
+    pub fn append(&mut self, clip: Clip) {
+        match self.clips.last_mut() {
+            Some(last) => {
+                last.append(&clip);
+                self.index = self.clips.len() - 1;
+            }
+            None => self.push(clip),
+        }
+    }
+
+    pub fn get(&self) -> Option<&Clip> {
+        self.clips.get(self.index)
+    }
+
+    // The kills from newest to oldest
+    pub fn get_recent(&self) -> impl Iterator<Item = &Clip> {
+        self.clips.iter().rev()
+    }
+
+    // Moves to the next older entry, wrapping around to the newest
+    pub fn rotate(&mut self) -> Option<&Clip> {
+        if self.clips.is_empty() {
+            return None;
+        }
+        self.index = self.index.checked_sub(1).unwrap_or(self.clips.len() - 1);
+        self.get()
+    }
+}
@@ -1,5 +1,27 @@
 pub mod buffer;
+pub mod clip;
+pub mod command;
+pub mod comment;
 pub mod editor;
+pub mod editorconfig;
+pub mod fold;
+pub mod fuzzy;
+pub mod glob;
+pub mod highlight;
+pub mod indentation;
+pub mod keymap;
+pub mod kill_ring;
 pub mod line;
+pub mod macros;
+pub mod mark;
+pub mod palette;
+pub mod picker;
 pub mod prompt;
+pub mod registers;
 pub mod render_config;
+pub mod search;
+pub mod settings;
+pub mod syntax;
+pub mod vim;
+pub mod word;
+pub mod wrap;
@@ -1,5 +1,46 @@
+use unicode_width::UnicodeWidthChar;
+
 use crate::render_config::RenderConfig;
 
+// Number of screen columns a character takes up once rendered
+pub fn char_width(c: char, options: &RenderConfig) -> usize {
+    if c == '\t' {
+        options.tab_size
+    } else {
+        c.width().unwrap_or(0)
+    }
+}
+
+// Byte index of the first character in a rendered line drawn at or after screen column `col`
+pub fn render_index_at_col(rendered: &str, col: usize) -> usize {
+    let mut width = 0;
+    for (i, c) in rendered.char_indices() {
+        if width >= col {
+            return i;
+        }
+        width += c.width().unwrap_or(0);
+    }
+    rendered.len()
+}
+
+// Splits text into lines, each keeping its `\n`, `\r\n` or `\r` ending
+pub fn split_lines(text: &str) -> Vec<&str> {
+    let mut lines = vec![];
+    let mut start = 0;
+    let bytes = text.as_bytes();
+    for (i, b) in bytes.iter().enumerate() {
+        let ends = *b == b'\n' || (*b == b'\r' && bytes.get(i + 1) != Some(&b'\n'));
+        if ends {
+            lines.push(&text[start..=i]);
+            start = i + 1;
+        }
+    }
+    if start < text.len() {
+        lines.push(&text[start..]);
+    }
+    lines
+}
+
 #[derive(Clone)] // Needed in buffer
 pub struct Line {
     raw: String,
@@ -18,9 +59,87 @@ impl Line {
         self.raw.replace("\r", "").replace("\n", "")
     }
 
+    // Leading tabs and spaces of the line
+    pub fn get_indent(&self) -> &str {
+        let indent = self
+            .raw
+            .find(|c| c != ' ' && c != '\t')
+            .unwrap_or_else(|| self.get_clean_raw().len());
+        self.raw.split_at(indent).0
+    }
+
+    pub fn get_line_ending(&self) -> &str {
+        self.raw.split_at(self.get_clean_raw().len()).1
+    }
+
     pub fn render(&self, options: &RenderConfig) -> String {
         let rendered = self.get_clean_raw();
 
         rendered.replace('\t', &" ".repeat(options.tab_size))
     }
+
+    // Converts a byte index into the line to the screen column it is drawn at
+    pub fn get_display_col(&self, cx: usize, options: &RenderConfig) -> usize {
+        self.get_clean_raw()
+            .char_indices()
+            .take_while(|(i, _)| *i < cx)
+            .map(|(_, c)| char_width(c, options))
+            .sum()
+    }
+
+    // Converts a screen column to the byte index of the character drawn there
+    pub fn get_byte_index(&self, col: usize, options: &RenderConfig) -> usize {
+        let clean = self.get_clean_raw();
+        let mut width = 0;
+        for (i, c) in clean.char_indices() {
+            width += char_width(c, options);
+            if width > col {
+                return i;
+            }
+        }
+        clean.len()
+    }
+
+    pub fn get_display_width(&self, options: &RenderConfig) -> usize {
+        self.get_display_col(self.raw.len(), options)
+    }
+
+    // Converts a byte index into the line to a byte index into the rendered line
+    pub fn get_render_index(&self, cx: usize, options: &RenderConfig) -> usize {
+        let clean = self.get_clean_raw();
+        let cx = std::cmp::min(cx, clean.len());
+        clean
+            .split_at(cx)
+            .0
+            .replace('\t', &" ".repeat(options.tab_size))
+            .len()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_lines_keeps_every_ending() {
+        assert_eq!(
+            split_lines("a\nb\r\nc\rd"),
+            vec!["a\n", "b\r\n", "c\r", "d"]
+        );
+        assert_eq!(split_lines("a\r\r"), vec!["a\r", "\r"]);
+        assert_eq!(split_lines(""), Vec::<&str>::new());
+    }
+
+    #[test]
+    fn cr_lines_round_trip() {
+        let lines: Vec<Line> = split_lines("one\rtwo\r")
+            .into_iter()
+            .map(|l| Line::new(l.to_string()))
+            .collect();
+        assert_eq!(lines.len(), 2);
+        assert_eq!(lines[0].get_clean_raw(), "one");
+        assert_eq!(lines[0].get_line_ending(), "\r");
+        let joined: String = lines.iter().map(|l| l.get_raw()).collect();
+        assert_eq!(joined, "one\rtwo\r");
+    }
 }
@@ -0,0 +1,46 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+
+use crate::keymap::{format_sequence, parse_sequence, KeyChord};
+
+// Named key sequences saved in macros.toml in the config folder, written like key bindings:
+// `indent-twice = "home tab tab"`
+pub type Macros = BTreeMap<String, Vec<KeyChord>>;
+
+// Reads the saved macros, describing any that can't be used
+pub fn load_macros(path: &Path) -> (Macros, Vec<String>) {
+    let contents = match std::fs::read_to_string(path) {
+        Ok(contents) => contents,
+        Err(e) if e.kind() == std::io::ErrorKind::NotFound => return (Macros::new(), vec![]),
+        Err(e) => return (Macros::new(), vec![format!("Couldn't read macros: {}", e)]),
+    };
+    let table: BTreeMap<String, String> = match toml::from_str(&contents) {
+        Ok(table) => table,
+        Err(e) => return (Macros::new(), vec![format!("Invalid macros: {}", e)]),
+    };
+    let mut macros = Macros::new();
+    let mut errors = vec![];
+    for (name, keys) in table {
+        match parse_sequence(&keys) {
+            Some(keys) => {
+                macros.insert(name, keys);
+            }
+            None => errors.push(format!("Invalid keys in macro `{}`", name)),
+        }
+    }
+    (macros, errors)
+}
+
+// Adds or replaces a macro in the macros file, keeping the others
+pub fn save_macro(path: &Path, name: &str, keys: &[KeyChord]) -> std::io::Result<()> {
+    let contents = std::fs::read_to_string(path).unwrap_or_default();
+    let mut table: BTreeMap<String, String> = toml::from_str(&contents)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    table.insert(name.to_string(), format_sequence(keys));
+    let contents = toml::to_string(&table)
+        .map_err(|e| std::io::Error::new(std::io::ErrorKind::InvalidData, e))?;
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, contents)
+}
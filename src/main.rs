@@ -1,17 +1,21 @@
 use std::cmp::min;
-use std::path::PathBuf;
+use std::io::{Stdout, Write};
+use std::path::{Path, PathBuf};
+use std::time::{Duration, Instant, SystemTime};
 
 use clap::{App, Arg};
 use crossterm::{
-    event::{read, EnableMouseCapture, Event, KeyCode, KeyModifiers, MouseEventKind},
+    event::{
+        poll, read, DisableMouseCapture, EnableMouseCapture, Event, KeyCode, KeyEvent,
+        KeyModifiers, MouseEvent, MouseEventKind,
+    },
     execute,
     terminal::{disable_raw_mode, enable_raw_mode, EnterAlternateScreen, LeaveAlternateScreen},
     ExecutableCommand,
 };
 use dirs::home_dir;
-use serde_derive::Deserialize;
 use syntect::{
-    highlighting::{Color as SynColor, ThemeSet},
+    highlighting::{Color as SynColor, Theme, ThemeSet},
     parsing::SyntaxSet,
 };
 use tui::{
@@ -21,416 +25,1271 @@ use tui::{
 };
 
 use redit::{
+    clip::Clip,
+    command::{
+        find_commands, get_movement, is_command, parse_command, quote_word, split_words, COMMANDS,
+    },
     editor::{Editor, Movement},
+    indentation::IndentStyle,
+    keymap::{format_sequence, KeyChord, KeyMatch, Keymap},
+    macros::{load_macros, save_macro, Macros},
+    palette::{History, Suggestions},
+    picker::Picker,
     prompt::Prompt,
+    registers::Registers,
+    search::Search,
+    settings::{folder_changed, get_config_stamp, save_theme, ClipboardMode, Profile, Settings},
+    vim::{Vim, VimResult},
+    wrap::WrapMode,
 };
 
-#[derive(Deserialize)]
-struct Config {
-    theme: String,
+const MULTI_CLICK_TIME: Duration = Duration::from_millis(500);
+// How many items PageUp and PageDown move through in a picker
+const PICKER_PAGE: isize = 10;
+// How many characters of copied text the paste picker shows
+const PICKER_SUMMARY_LENGTH: usize = 80;
+// How often the config folder is checked for changes when `watch_config` is set
+const WATCH_INTERVAL: Duration = Duration::from_secs(1);
+// How long to wait for input before checking for highlighted lines from the background thread
+const HIGHLIGHT_POLL_TIME: Duration = Duration::from_millis(30);
+
+// Palette suggestions for commands, showing their arguments and descriptions
+fn get_command_items(commands: Vec<&(&str, &str, &str)>) -> Vec<(String, String, String)> {
+    commands
+        .into_iter()
+        .map(|(name, usage, description)| {
+            let label = format!("{} {}", name, usage);
+            (label, description.to_string(), format!("{} ", name))
+        })
+        .collect()
 }
 
-fn edit(file: Option<&str>) -> crossterm::Result<()> {
-    let mut ps = SyntaxSet::load_defaults_newlines().into_builder();
-    let config_dir = home_dir()
-        .unwrap_or_else(|| PathBuf::from("~"))
-        .join(".config/redit");
-    let config_file = config_dir.join("settings.toml");
-    let mut config: Config = Config {
-        theme: "Solarized (dark)".to_string(),
+// The names in a directory starting with `dir`'s text, with a `/` after directories and
+// hidden ones only if asked for
+fn get_dir_entries(dir: &str, hidden: bool) -> Vec<String> {
+    let path = if dir.is_empty() { "." } else { dir };
+    let entries = match std::fs::read_dir(path) {
+        Ok(entries) => entries,
+        Err(_) => return vec![],
     };
-    if config_file.exists() {
-        let contents = std::fs::read_to_string(config_file)?;
-        config = toml::from_str(&contents).expect("Failed to parse settings");
-    }
-    let syntax_dir = config_dir.join("syntaxes");
-    if syntax_dir.exists() && ps.add_from_folder(syntax_dir, true).is_err() {
-        eprintln!("Couldn't load syntaxes");
-    }
-    let ps = ps.build();
-    let theme_dir = config_dir.join("themes");
-    let mut theme_set = ThemeSet::load_defaults();
-    if theme_dir.exists() && theme_set.add_from_folder(theme_dir).is_err() {
-        eprintln!("Couldn't load themes");
-    }
-    let theme = &theme_set.themes[&config.theme];
-    let bg = theme.settings.background.unwrap_or(SynColor::BLACK);
-    let bg_color = TuiColor::Rgb(bg.r, bg.g, bg.b);
+    let mut names: Vec<String> = entries
+        .filter_map(|entry| {
+            let entry = entry.ok()?;
+            let mut name = entry.file_name().into_string().ok()?;
+            if name.starts_with('.') && !hidden {
+                return None;
+            }
+            if entry.path().is_dir() {
+                name.push('/');
+            }
+            Some(name)
+        })
+        .collect();
+    names.sort();
+    names
+}
+
+// The foreground, background and accent colors of a theme for the tabs, borders and prompt
+fn get_chrome_colors(theme: &Theme) -> (TuiColor, TuiColor, TuiColor) {
     let fg = theme.settings.foreground.unwrap_or(SynColor::WHITE);
-    let fg_color = TuiColor::Rgb(fg.r, fg.g, fg.b);
+    let bg = theme.settings.background.unwrap_or(SynColor::BLACK);
     let sel = theme.settings.accent.unwrap_or(SynColor {
         r: 0,
         g: 0xFF,
         b: 0xFF,
         a: 0xFF,
     });
-    let sel_color = TuiColor::Rgb(sel.r, sel.g, sel.b);
+    (
+        TuiColor::Rgb(fg.r, fg.g, fg.b),
+        TuiColor::Rgb(bg.r, bg.g, bg.b),
+        TuiColor::Rgb(sel.r, sel.g, sel.b),
+    )
+}
 
-    let mut editors = vec![Editor::new(ps.clone())];
-    let mut editor_index = 0;
-    let mut e = editors.get_mut(editor_index).unwrap();
-    e.load_theme(theme.clone());
-    if let Some(file) = file {
-        if file.starts_with('~') {
-            let path = home_dir()
-                .expect("Cannot find home directory")
-                .join(file.split_at(2).1);
-            e.open_file(&path.to_str().expect("Failed to use home directory"))?;
+// Reads the settings, syntaxes, themes, key bindings and macros from the config folder,
+// describing any problems
+fn load_config(config_dir: &Path) -> (Settings, SyntaxSet, ThemeSet, Keymap, Macros, Vec<String>) {
+    let mut warnings = vec![];
+    let syntaxes = load_syntaxes(config_dir, &mut warnings);
+    let themes = load_themes(config_dir, &mut warnings);
+    let (settings, keymap, macros, setting_warnings) = load_settings(config_dir, &themes);
+    warnings.extend(setting_warnings);
+    (settings, syntaxes, themes, keymap, macros, warnings)
+}
+
+fn load_syntaxes(config_dir: &Path, warnings: &mut Vec<String>) -> SyntaxSet {
+    let mut ps = SyntaxSet::load_defaults_newlines().into_builder();
+    let syntax_dir = config_dir.join("syntaxes");
+    if syntax_dir.exists() && ps.add_from_folder(syntax_dir, true).is_err() {
+        warnings.push("Couldn't load syntaxes".to_string());
+    }
+    ps.build()
+}
+
+fn load_themes(config_dir: &Path, warnings: &mut Vec<String>) -> ThemeSet {
+    let theme_dir = config_dir.join("themes");
+    let mut theme_set = ThemeSet::load_defaults();
+    if theme_dir.exists() && theme_set.add_from_folder(theme_dir).is_err() {
+        warnings.push("Couldn't load themes".to_string());
+    }
+    theme_set
+}
+
+// The settings, key bindings and macros, with the theme checked against `themes`
+fn load_settings(config_dir: &Path, themes: &ThemeSet) -> (Settings, Keymap, Macros, Vec<String>) {
+    let (mut settings, mut warnings) = Settings::load(&config_dir.join("settings.toml"));
+    warnings.extend(settings.validate_theme(&themes.themes));
+    let (keymap, key_warnings) = Keymap::new(&settings.keys, settings.profile);
+    warnings.extend(key_warnings);
+    let (macros, macro_warnings) = load_macros(&config_dir.join("macros.toml"));
+    warnings.extend(macro_warnings);
+    (settings, keymap, macros, warnings)
+}
+
+// A register named by a single letter, such as `a` or `A` to append to it
+fn get_register_name(arg: &str) -> Option<char> {
+    let mut chars = arg.chars();
+    match (chars.next(), chars.next()) {
+        (Some(c), None) if c.is_ascii_alphabetic() => Some(c),
+        _ => None,
+    }
+}
+
+// The start of some copied text on one line, for listing it in a picker
+fn summarize(text: &str) -> String {
+    let summary: String = text
+        .chars()
+        .filter(|c| *c != '\r')
+        .take(PICKER_SUMMARY_LENGTH)
+        .map(|c| match c {
+            '\n' => '↵',
+            '\t' => ' ',
+            c => c,
+        })
+        .collect();
+    if text.chars().count() > PICKER_SUMMARY_LENGTH {
+        summary + "…"
+    } else {
+        summary
+    }
+}
+
+// What an open picker is choosing from
+enum PickerKind {
+    // The command run by each listed item, such as a key binding or macro
+    Commands(Vec<String>),
+    Theme,
+}
+
+// The editors and everything shared between them
+struct Session {
+    clicks: usize,
+    colors: (TuiColor, TuiColor, TuiColor),
+    config_dir: PathBuf,
+    config_stamp: Vec<(PathBuf, Option<SystemTime>)>,
+    editor_index: usize,
+    editors: Vec<Editor>,
+    // The lines run from the palette
+    history: History,
+    keymap: Keymap,
+    last_click: Option<(Instant, u16, u16)>,
+    // The name of the command run before the current one, to join consecutive kills
+    last_command: String,
+    last_input: Instant,
+    last_search: String,
+    last_watch: Instant,
+    // The last recorded macro
+    macro_keys: Vec<KeyChord>,
+    macros: Macros,
+    // Whether movements extend the selection from the mark
+    mark: bool,
+    picker: Option<(PickerKind, Picker)>,
+    prompt: Option<Prompt>,
+    quit: bool,
+    recording: Option<Vec<KeyChord>>,
+    // Where the keys of the current command start in the recording, to leave out the ones that
+    // stop it
+    recording_start: usize,
+    registers: Registers,
+    // Keys are being handled again rather than typed, so they aren't recorded
+    replaying: bool,
+    search: Option<Search>,
+    settings: Settings,
+    // Commands or completions shown above the palette
+    suggestions: Option<Suggestions>,
+    syntaxes: SyntaxSet,
+    theme: Theme,
+    themes: ThemeSet,
+    vim: Option<Vim>,
+    // Where the last paste started, for yank-pop to replace it
+    yank_start: (usize, usize),
+}
+
+impl Session {
+    fn new(config_dir: PathBuf) -> (Self, Vec<String>) {
+        let (settings, syntaxes, themes, keymap, macros, warnings) = load_config(&config_dir);
+        let theme = themes.themes[&settings.theme].clone();
+        let mut session = Session {
+            clicks: 0,
+            colors: get_chrome_colors(&theme),
+            config_stamp: get_config_stamp(&config_dir),
+            config_dir,
+            editor_index: 0,
+            editors: vec![],
+            history: History::new(),
+            keymap,
+            last_click: None,
+            last_command: String::new(),
+            last_input: Instant::now(),
+            last_search: String::new(),
+            last_watch: Instant::now(),
+            macro_keys: vec![],
+            macros,
+            mark: false,
+            picker: None,
+            prompt: None,
+            quit: false,
+            recording: None,
+            recording_start: 0,
+            registers: Registers::new(settings.clipboard_history),
+            replaying: false,
+            search: None,
+            settings,
+            suggestions: None,
+            syntaxes,
+            theme,
+            themes,
+            vim: None,
+            yank_start: (0, 0),
+        };
+        session.set_profile();
+        session.new_editor();
+        (session, warnings)
+    }
+
+    fn editor(&mut self) -> &mut Editor {
+        &mut self.editors[self.editor_index]
+    }
+
+    fn new_editor(&mut self) {
+        let mut editor = Editor::new(self.syntaxes.clone());
+        editor.load_theme(self.theme.clone());
+        editor.apply_settings(&self.settings);
+        self.editors.push(editor);
+        self.editor_index = self.editors.len() - 1;
+    }
+
+    fn open_prompt(&mut self, text: &str) {
+        self.prompt = Some(Prompt::new(Some(text.to_string())));
+        self.suggestions = None;
+    }
+
+    fn get_theme_names(&self) -> Vec<String> {
+        let mut names: Vec<String> = self.themes.themes.keys().cloned().collect();
+        names.sort_by_key(|n| n.to_lowercase());
+        names
+    }
+
+    // Shows a theme around the editors and in the current one, or every one if `all` is set,
+    // without choosing it
+    fn show_theme(&mut self, name: &str, all: bool) {
+        self.theme = self.themes.themes[name].clone();
+        self.colors = get_chrome_colors(&self.theme);
+        if all {
+            for editor in self.editors.iter_mut() {
+                editor.load_theme(self.theme.clone());
+            }
         } else {
-            e.open_file(&file)?;
+            let theme = self.theme.clone();
+            self.editor().load_theme(theme);
         }
     }
 
-    let mut stdout = std::io::stdout();
-    execute!(stdout, EnterAlternateScreen, EnableMouseCapture)?;
-
-    enable_raw_mode()?;
+    // Keeps copied or cut text in the kill ring and the register `name` if given, or joins it
+    // to the last kill if `append` is set
+    fn kill(&mut self, name: Option<char>, clip: Clip, append: bool) -> crossterm::Result<()> {
+        if append {
+            self.registers.append(clip);
+        } else {
+            self.registers.store(name, clip);
+        }
+        self.share_clipboard()
+    }
 
-    let backend = CrosstermBackend::new(stdout);
-    let mut terminal = Terminal::new(backend)?;
+    // Also puts the newest kill on the system clipboard when the settings ask for it
+    fn share_clipboard(&self) -> crossterm::Result<()> {
+        if let (ClipboardMode::System, Some(clip)) =
+            (self.settings.clipboard, self.registers.get(None))
+        {
+            let mut stdout = std::io::stdout();
+            stdout.write_all(clip.to_osc52().as_bytes())?;
+            stdout.flush()?;
+        }
+        Ok(())
+    }
 
-    let mut clipboard = None;
-    let mut prompt: Option<Prompt> = None;
+    // Starts or stops modal editing to match the settings, keeping the mode if it's unchanged
+    fn set_profile(&mut self) {
+        match self.settings.profile {
+            Profile::Vim if self.vim.is_none() => self.vim = Some(Vim::new()),
+            Profile::Vim => {}
+            Profile::Default | Profile::Emacs => self.vim = None,
+        }
+    }
 
-    terminal.draw(|f| {
-        use tui::{
-            layout::{Constraint, Direction, Layout},
-            style::Style,
-            text::Spans,
-            widgets::{Block, Borders, Tabs},
+    // Loads the settings, key bindings and macros again and applies them without touching the
+    // buffers. Syntaxes and themes are slow to build, so they're only rebuilt when their folder
+    // changed.
+    fn reload_config(&mut self) -> crossterm::Result<()> {
+        let stamp = get_config_stamp(&self.config_dir);
+        let mut warnings = vec![];
+        let syntax_dir = self.config_dir.join("syntaxes");
+        let syntaxes_changed = folder_changed(&self.config_stamp, &stamp, &syntax_dir);
+        if syntaxes_changed {
+            self.syntaxes = load_syntaxes(&self.config_dir, &mut warnings);
+        }
+        let theme_dir = self.config_dir.join("themes");
+        if folder_changed(&self.config_stamp, &stamp, &theme_dir) {
+            self.themes = load_themes(&self.config_dir, &mut warnings);
+        }
+        let (settings, keymap, macros, setting_warnings) =
+            load_settings(&self.config_dir, &self.themes);
+        warnings.extend(setting_warnings);
+        if settings.mouse != self.settings.mouse {
+            if settings.mouse {
+                execute!(std::io::stdout(), EnableMouseCapture)?;
+            } else {
+                execute!(std::io::stdout(), DisableMouseCapture)?;
+            }
+        }
+        self.settings = settings;
+        self.keymap = keymap;
+        self.macros = macros;
+        self.registers
+            .set_history_size(self.settings.clipboard_history);
+        self.set_profile();
+        self.theme = self.themes.themes[&self.settings.theme].clone();
+        self.colors = get_chrome_colors(&self.theme);
+        for editor in self.editors.iter_mut() {
+            if syntaxes_changed {
+                editor.set_syntaxes(self.syntaxes.clone());
+            }
+            editor.load_theme(self.theme.clone());
+            editor.apply_settings(&self.settings);
+        }
+        self.config_stamp = stamp;
+        let message = if warnings.is_empty() {
+            "Config reloaded.".to_string()
+        } else {
+            warnings.join("; ")
         };
-        let size = f.size();
-        let main_block = Block::default()
-            .borders(Borders::ALL)
-            .style(TuiStyle::default().fg(fg_color).bg(bg_color));
-        let inner_area = main_block.inner(size);
-        let mut constraints = vec![Constraint::Length(1), Constraint::Min(1)];
-        if prompt.is_some() {
-            constraints.push(Constraint::Length(2));
-        }
-        let chunks = Layout::default()
-            .direction(Direction::Vertical)
-            .constraints(constraints)
-            .split(inner_area);
-        let tabs = Tabs::new(editors.iter().map(|e| Spans::from(e.get_title())).collect())
-            .select(editor_index)
-            .highlight_style(Style::default().fg(sel_color))
-            .divider("|");
-        f.render_widget(main_block, size);
-        f.render_widget(tabs, chunks[0]);
-        f.render_widget(&mut editors[editor_index], chunks[1]);
-        if let Some(prompt) = prompt.clone() {
-            f.render_widget(prompt, chunks[2]);
-            // prompt_cursor = chunks[2];
-        }
-    })?;
-    let cur_pos = editors[editor_index].get_rel_cursor();
-
-    terminal.set_cursor(cur_pos.0, cur_pos.1)?;
-    terminal.show_cursor()?;
-
-    loop {
-        e = editors.get_mut(editor_index).unwrap();
-
-        let event = read()?;
-        match event {
-            Event::Resize(width, height) => {
-                #[cfg(target_family = "windows")]
-                terminal.resize(tui::layout::Rect {
-                    x: 0,
-                    y: 0,
-                    width,
-                    height,
-                })?;
-                #[cfg(target_family = "unix")]
-                terminal.resize(tui::layout::Rect {
-                    x: 0,
-                    y: 0,
-                    width: width - 1,
-                    height: height - 1,
-                })?;
+        self.editor().set_message(&message);
+        Ok(())
+    }
+
+    // Takes a config file the editor wrote itself into the stamp, so watching the config folder
+    // doesn't reload everything for it
+    fn restamp(&mut self, path: &Path) {
+        let modified = std::fs::metadata(path).and_then(|m| m.modified()).ok();
+        match self
+            .config_stamp
+            .binary_search_by(|(p, _)| p.as_path().cmp(path))
+        {
+            Ok(i) => self.config_stamp[i].1 = modified,
+            Err(i) => self.config_stamp.insert(i, (path.to_path_buf(), modified)),
+        }
+    }
+
+    // Runs a command such as `goto 12:4`, from a key binding or the palette. Commands missing
+    // a required argument open the palette to ask for it.
+    fn run(&mut self, line: &str) -> crossterm::Result<()> {
+        let (name, words) = match parse_command(line) {
+            Ok(command) => command,
+            Err(err) => {
+                self.editor().set_message(&err);
+                return Ok(());
             }
-            Event::Mouse(event) => {
-                let cur_pos = (event.column, event.row);
-                let cur_pos = (
-                    cur_pos.0 - min(cur_pos.0, e.draw_area.x + 1),
-                    cur_pos.1 - min(cur_pos.1, e.draw_area.y),
-                );
-                match event.kind {
-                    MouseEventKind::ScrollDown => e.move_cursor(
-                        Movement::ScrollDown(2),
-                        event.modifiers.intersects(KeyModifiers::SHIFT),
-                    ),
-                    MouseEventKind::ScrollUp => e.move_cursor(
-                        Movement::ScrollUp(2),
-                        event.modifiers.intersects(KeyModifiers::SHIFT),
-                    ),
-                    MouseEventKind::Down(_) => e.move_cursor(
-                        Movement::AbsoluteScreen(cur_pos.0, cur_pos.1),
-                        event.modifiers.intersects(KeyModifiers::SHIFT),
-                    ),
-                    MouseEventKind::Drag(_) => {
-                        e.move_cursor(Movement::AbsoluteScreen(cur_pos.0 + 1, cur_pos.1), true)
-                        // +1 to include char under cursor
+        };
+        let name = name.as_str();
+        // Names and paths with spaces can be given without quotes to commands taking one argument
+        let args = words.join(" ");
+        let args = args.as_str();
+        let needs_args = COMMANDS.iter().any(|(command, usage, _)| {
+            *command == name && !usage.is_empty() && !usage.starts_with('[')
+        });
+        if needs_args && args.is_empty() {
+            self.open_prompt(&format!("{} ", name));
+            return Ok(());
+        }
+
+        let arg = words.first().map(|word| word.as_str());
+        let last_command = std::mem::replace(&mut self.last_command, name.to_string());
+        let e = &mut self.editors[self.editor_index];
+        if let Some(movement) = get_movement(name) {
+            e.move_cursor(movement, self.mark);
+            return Ok(());
+        }
+        // Anything other than a movement ends the selection started from the mark
+        let mark = std::mem::replace(&mut self.mark, false);
+        if let Some(movement) = name.strip_prefix("select-").and_then(get_movement) {
+            e.move_cursor(movement, true);
+            return Ok(());
+        }
+        match name {
+            "palette" => self.open_prompt(""),
+            "quit" => {
+                if e.try_quit() {
+                    if self.editors.len() == 1 {
+                        self.quit = true;
+                    } else {
+                        self.editors.remove(self.editor_index);
+                        self.editor_index = 0;
                     }
-                    _ => continue,
                 }
             }
-            Event::Key(event) => {
-                let dist = if event.modifiers.intersects(KeyModifiers::CONTROL) {
-                    5
+            "save" => {
+                if !args.is_empty() {
+                    e.save_as(PathBuf::from(args))?;
+                } else if !e.save()? {
+                    self.open_prompt("save ");
+                }
+            }
+            "open" => {
+                let path = PathBuf::from(args);
+                if !path.exists() {
+                    e.set_message(&"File does not exist");
+                } else {
+                    e.open_file(&path)?;
+                }
+            }
+            "reload" => {
+                e.try_reload()?;
+            }
+            "new-editor" => self.new_editor(),
+            "prev-editor" => {
+                if self.editor_index == 0 {
+                    self.editor_index = self.editors.len() - 1;
+                } else {
+                    self.editor_index -= 1;
+                }
+            }
+            "next-editor" => {
+                if self.editor_index == self.editors.len() - 1 {
+                    self.editor_index = 0;
                 } else {
-                    1
+                    self.editor_index += 1;
+                }
+            }
+            "undo" => e.undo(),
+            "redo" => e.redo(),
+            "copy" => {
+                let clip = e.copy();
+                if mark {
+                    e.clear_selection();
+                }
+                self.kill(None, clip, false)?;
+            }
+            "cut" => {
+                let clip = e.cut();
+                self.kill(None, clip, false)?;
+            }
+            "copy-to" | "cut-to" => match arg.and_then(get_register_name) {
+                Some(register) => {
+                    let clip = if name == "copy-to" { e.copy() } else { e.cut() };
+                    self.kill(Some(register), clip, false)?;
+                }
+                None => e.set_message(&"Specify a register from a to z"),
+            },
+            "kill-line" => {
+                let clip = e.kill_line();
+                self.kill(None, clip, last_command == "kill-line")?;
+            }
+            "paste" => {
+                self.yank_start = e.get_selection().map_or(e.get_cursor(), |(start, _)| start);
+                e.paste(self.registers.get(None));
+            }
+            "paste-from" => {
+                let registers = &self.registers;
+                let clip = match arg {
+                    None => return self.show_registers(),
+                    Some(arg) => match arg.parse() {
+                        Ok(n) => registers.get_recent(n),
+                        Err(_) => get_register_name(arg).and_then(|r| registers.get(Some(r))),
+                    },
                 };
-                match event.code {
-                    KeyCode::Char('q') if event.modifiers == KeyModifiers::CONTROL => {
-                        if e.try_quit() {
-                            if editors.len() == 1 {
-                                break;
-                            } else {
-                                editors.remove(editor_index);
-                                editor_index = 0;
-                            }
-                        }
-                    }
-                    KeyCode::Char('z') if event.modifiers == KeyModifiers::CONTROL => {
-                        if prompt.is_none() {
-                            e.undo();
-                        }
-                    }
-                    KeyCode::Char('y') if event.modifiers == KeyModifiers::CONTROL => {
-                        if prompt.is_none() {
-                            e.redo();
-                        }
-                    }
-                    KeyCode::Char('p') if event.modifiers == KeyModifiers::CONTROL => {
-                        if prompt.is_none() {
-                            if editor_index == 0 {
-                                editor_index = editors.len() - 1;
-                            } else {
-                                editor_index -= 1;
-                            }
-                        }
-                    }
-                    KeyCode::Char('n') if event.modifiers == KeyModifiers::CONTROL => {
-                        if prompt.is_none() {
-                            if editor_index == editors.len() - 1 {
-                                editor_index = 0;
-                            } else {
-                                editor_index += 1;
-                            }
-                        }
-                    }
-                    KeyCode::Char('b') if event.modifiers == KeyModifiers::CONTROL => {
-                        if prompt.is_none() {
-                            editors.push(Editor::new(ps.clone()));
-                            let n = editors.len() - 1;
-                            e = editors.get_mut(n).unwrap();
-                            e.load_theme(theme.clone());
-                        }
-                    }
-                    KeyCode::Char('r') if event.modifiers == KeyModifiers::CONTROL => {
-                        if prompt.is_none() {
-                            e.try_reload()?;
-                        }
-                    }
-                    KeyCode::Char('s') if event.modifiers == KeyModifiers::CONTROL => {
-                        if prompt.is_none() && !e.save()? {
-                            prompt = Some(Prompt::new(Some("save ".to_string())));
-                        }
-                    }
-                    KeyCode::Char('o') if event.modifiers == KeyModifiers::CONTROL => {
-                        if prompt.is_none() {
-                            prompt = Some(Prompt::new(Some("open ".to_string())));
-                        }
-                    }
-                    KeyCode::Char('e') if event.modifiers == KeyModifiers::CONTROL => {
-                        if prompt.is_none() {
-                            prompt = Some(Prompt::new(Some("".to_string())));
-                        }
-                    }
-                    KeyCode::Char('c') if event.modifiers == KeyModifiers::CONTROL => {
-                        if prompt.is_none() {
-                            clipboard = Some(e.copy());
-                        }
-                    }
-                    KeyCode::Char('x') if event.modifiers == KeyModifiers::CONTROL => {
-                        if prompt.is_none() {
-                            clipboard = Some(e.cut());
-                        }
-                    }
-                    KeyCode::Char('v') if event.modifiers == KeyModifiers::CONTROL => {
-                        if prompt.is_none() {
-                            e.paste(&clipboard);
-                        }
-                    }
-                    KeyCode::Left => {
-                        if let Some(ref mut prompt) = prompt {
-                            prompt.move_cursor(-1);
-                        } else {
-                            e.move_cursor(
-                                Movement::Relative(-dist, 0),
-                                event.modifiers.intersects(KeyModifiers::SHIFT),
-                            );
-                        }
-                    }
-                    KeyCode::Right => {
-                        if let Some(ref mut prompt) = prompt {
-                            prompt.move_cursor(1);
-                        } else {
-                            e.move_cursor(
-                                Movement::Relative(dist, 0),
-                                event.modifiers.intersects(KeyModifiers::SHIFT),
-                            );
-                        }
-                    }
-                    KeyCode::Up => {
-                        if prompt.is_none() {
-                            e.move_cursor(
-                                if event.modifiers.intersects(KeyModifiers::CONTROL) {
-                                    Movement::ScrollUp(1)
-                                } else {
-                                    Movement::Relative(0, -1)
-                                },
-                                event.modifiers.intersects(KeyModifiers::SHIFT),
-                            );
-                        }
-                    }
-                    KeyCode::Down => {
-                        if prompt.is_none() {
-                            e.move_cursor(
-                                if event.modifiers.intersects(KeyModifiers::CONTROL) {
-                                    Movement::ScrollDown(1)
-                                } else {
-                                    Movement::Relative(0, 1)
-                                },
-                                event.modifiers.intersects(KeyModifiers::SHIFT),
-                            );
-                        }
-                    }
-                    KeyCode::Home => {
-                        if prompt.is_none() {
-                            e.move_cursor(
-                                Movement::Home,
-                                event.modifiers.intersects(KeyModifiers::SHIFT),
-                            );
-                        }
-                    }
-                    KeyCode::End => {
-                        if prompt.is_none() {
-                            e.move_cursor(
-                                Movement::End,
-                                event.modifiers.intersects(KeyModifiers::SHIFT),
-                            );
-                        }
+                match clip {
+                    Some(clip) => e.paste(Some(clip)),
+                    None => e.set_message(&format!("Nothing to paste in {}", args)),
+                }
+            }
+            "yank-pop" => {
+                // Like Emacs, yank-pop without a paste before it lists what could be pasted
+                if last_command != "paste" && last_command != "yank-pop" {
+                    return self.show_registers();
+                } else if let Some(clip) = self.registers.rotate() {
+                    e.begin_undo_group();
+                    e.select_region(self.yank_start, e.get_cursor());
+                    e.paste(Some(clip));
+                    e.end_undo_group();
+                }
+            }
+            "set-mark" => {
+                let cursor = e.get_cursor();
+                e.select_region(cursor, cursor);
+                self.mark = true;
+                e.set_message(&"Mark set");
+            }
+            "cancel" => {
+                e.clear_selection();
+                e.set_message(&"Quit");
+            }
+            "search-forward" | "search-backward" => {
+                e.record_jump();
+                let search = Search::new(e, name == "search-forward");
+                e.set_message(&search.get_status());
+                self.search = Some(search);
+            }
+            "goto" => {
+                let mut position = args.split(':');
+                let line = position.next().unwrap_or("").trim().parse();
+                let col = position.next().map_or(Ok(1), |c| c.trim().parse());
+                match (line, col) {
+                    (Ok(line), Ok(col)) => e.goto(line, col),
+                    _ => e.set_message(&"Specify line[:column] to go to"),
+                }
+            }
+            "back" => e.jump_back(),
+            "forward" => e.jump_forward(),
+            "bracket" => {
+                e.jump_to_bracket();
+            }
+            "mark" => match arg.and_then(|n| n.chars().next()) {
+                Some(name) => e.set_mark(name),
+                None => e.toggle_bookmark(),
+            },
+            "jump" => match arg.and_then(|n| n.chars().next()) {
+                Some(name) => e.jump_to_mark(name),
+                None => e.set_message(&"Specify mark to jump to"),
+            },
+            "marks" => e.list_marks(),
+            "next-mark" => e.next_mark(true),
+            "prev-mark" => e.next_mark(false),
+            "clear-marks" => e.clear_marks(),
+            "fold" => e.fold(),
+            "unfold" => e.unfold(),
+            "fold-all" => e.fold_all(),
+            "unfold-all" => e.unfold_all(),
+            "wrap" => match arg {
+                None => e.toggle_wrap(),
+                Some("off") => e.set_wrap(WrapMode::Off),
+                Some("window") => e.set_wrap(WrapMode::Window),
+                Some(width) => match width.parse() {
+                    Ok(width) if width > 0 => e.set_wrap(WrapMode::Column(width)),
+                    _ => e.set_message(&"Specify off, window or a column"),
+                },
+            },
+            "indent" => {
+                let mut args = words.iter().map(|word| word.as_str());
+                match (args.next(), args.next()) {
+                    (None, _) => e.indent(),
+                    (Some("tabs"), _) => {
+                        e.convert_indentation(IndentStyle::Tabs);
                     }
-                    KeyCode::PageUp => {
-                        if prompt.is_none() {
-                            e.move_cursor(
-                                Movement::PageUp,
-                                event.modifiers.intersects(KeyModifiers::SHIFT),
-                            );
-                        }
+                    (Some("spaces"), width) => match width.map_or(Ok(4), |w| w.parse()) {
+                        Ok(width) if width > 0 => e.convert_indentation(IndentStyle::Spaces(width)),
+                        _ => e.set_message(&"Invalid indent width"),
+                    },
+                    _ => {
+                        e.set_message(&"Specify tabs or spaces [width]");
                     }
-                    KeyCode::PageDown => {
-                        if prompt.is_none() {
-                            e.move_cursor(
-                                Movement::PageDown,
-                                event.modifiers.intersects(KeyModifiers::SHIFT),
-                            );
+                }
+            }
+            "dedent" => e.dedent(),
+            "duplicate" => e.duplicate(),
+            "move-up" => e.move_lines(true),
+            "move-down" => e.move_lines(false),
+            "join" => e.join_lines(),
+            "delete-lines" => e.delete_lines(),
+            "sort" => e.sort_lines(),
+            "reverse" => e.reverse_lines(),
+            "unique" => e.unique_lines(),
+            "comment" => {
+                e.toggle_comment();
+            }
+            "newline" => e.do_return(),
+            "backspace" => e.backspace_char(),
+            "delete" => e.delete_char(),
+            "delete-word-left" => e.delete_word_left(),
+            "delete-word-right" => e.delete_word_right(),
+            "block-left" => e.move_cursor_block(Movement::Relative(-1, 0)),
+            "block-right" => e.move_cursor_block(Movement::Relative(1, 0)),
+            "block-up" => e.move_cursor_block(Movement::Relative(0, -1)),
+            "block-down" => e.move_cursor_block(Movement::Relative(0, 1)),
+            "syntax" => e.set_syntax(args),
+            "theme" => {
+                let names = self.get_theme_names();
+                if args.is_empty() {
+                    let selected = names.iter().position(|n| *n == self.settings.theme);
+                    let picker = Picker::new(
+                        "Theme (Enter to apply, Ctrl-s to save)",
+                        names,
+                        selected.unwrap_or(0),
+                    );
+                    self.picker = Some((PickerKind::Theme, picker));
+                } else if let Some(name) = names.iter().find(|n| n.eq_ignore_ascii_case(args)) {
+                    self.settings.theme = name.clone();
+                    self.show_theme(name, true);
+                    self.editor().set_message(&format!("Theme set to {}", name));
+                } else {
+                    self.editor()
+                        .set_message(&format!("Unknown theme {}", args));
+                }
+            }
+            "save-theme" => {
+                let path = self.config_dir.join("settings.toml");
+                let message = match save_theme(&path, &self.settings.theme) {
+                    Ok(()) => format!("Saved theme {}", self.settings.theme),
+                    Err(err) => format!("Couldn't save theme: {}", err),
+                };
+                self.restamp(&path);
+                self.editor().set_message(&message);
+            }
+            "reload-config" => self.reload_config()?,
+            "record-macro" => match self.recording.take() {
+                Some(mut keys) => {
+                    keys.truncate(self.recording_start);
+                    e.set_message(&format!("Recorded macro {}", format_sequence(&keys)));
+                    self.macro_keys = keys;
+                }
+                None if self.replaying => e.set_message(&"Can't record while playing a macro"),
+                None => {
+                    self.recording = Some(vec![]);
+                    e.set_message(&"Recording macro");
+                }
+            },
+            "play-macro" => self.play_macro(&words, false)?,
+            "play-macro-lines" => self.play_macro(&words, true)?,
+            "save-macro" => match arg {
+                _ if self.macro_keys.is_empty() => e.set_message(&"No macro recorded"),
+                Some(name) if name.parse::<usize>().is_ok() => {
+                    e.set_message(&"Macro names can't be numbers")
+                }
+                Some(name) => {
+                    let path = self.config_dir.join("macros.toml");
+                    let message = match save_macro(&path, name, &self.macro_keys) {
+                        Ok(()) => {
+                            self.macros
+                                .insert(name.to_string(), self.macro_keys.clone());
+                            format!("Saved macro {}", name)
                         }
+                        Err(err) => format!("Couldn't save macro: {}", err),
+                    };
+                    self.restamp(&path);
+                    self.editor().set_message(&message);
+                }
+                None => e.set_message(&"Specify a name for the macro"),
+            },
+            "macros" => {
+                if self.macros.is_empty() {
+                    e.set_message(&"No saved macros");
+                    return Ok(());
+                }
+                let width = self.macros.keys().map(|name| name.len()).max();
+                let items = self
+                    .macros
+                    .iter()
+                    .map(|(name, keys)| {
+                        let keys = format_sequence(keys);
+                        format!("{:<width$} {}", name, keys, width = width.unwrap_or(0))
+                    })
+                    .collect();
+                let commands = self
+                    .macros
+                    .keys()
+                    .map(|name| format!("play-macro {}", quote_word(name, true)))
+                    .collect();
+                let picker = Picker::new("Macros (Enter to play)", items, 0);
+                self.picker = Some((PickerKind::Commands(commands), picker));
+            }
+            "bindings" => {
+                let bindings = self.keymap.get_bindings();
+                let width = bindings.iter().map(|(keys, _)| keys.len()).max();
+                let items = bindings
+                    .iter()
+                    .map(|(keys, command)| {
+                        format!("{:<width$} {}", keys, command, width = width.unwrap_or(0))
+                    })
+                    .collect();
+                let commands = bindings.into_iter().map(|(_, command)| command).collect();
+                let picker = Picker::new("Key bindings (Enter to run)", items, 0);
+                self.picker = Some((PickerKind::Commands(commands), picker));
+            }
+            _ => {
+                e.set_message(&format!("Command not recognized {}", name));
+            }
+        }
+        Ok(())
+    }
+
+    // Lists the registers and recent copies and cuts in a picker that pastes the selected one
+    fn show_registers(&mut self) -> crossterm::Result<()> {
+        let entries = self.registers.get_entries();
+        if entries.is_empty() {
+            self.editor().set_message(&"Nothing copied or cut yet");
+            return Ok(());
+        }
+        let width = entries.iter().map(|(name, _)| name.len()).max();
+        let items = entries
+            .iter()
+            .map(|(name, clip)| {
+                let text = summarize(&clip.get_text());
+                format!("{:<width$} {}", name, text, width = width.unwrap_or(0))
+            })
+            .collect();
+        let commands = entries
+            .iter()
+            .map(|(name, _)| format!("paste-from {}", name))
+            .collect();
+        let picker = Picker::new("Paste (Enter to paste)", items, 0);
+        self.picker = Some((PickerKind::Commands(commands), picker));
+        Ok(())
+    }
+
+    // Plays the last recorded macro, or the saved one named by the first word, as many times as
+    // a count in the last word or once from the start of each selected line. Everything it
+    // changes in the current editor undoes at once.
+    fn play_macro(&mut self, words: &[String], on_lines: bool) -> crossterm::Result<()> {
+        let (words, count) = match words.split_last().map(|(last, rest)| (last.parse(), rest)) {
+            Some((Ok(count), rest)) => (rest, count),
+            _ => (words, 1),
+        };
+        let keys = match words.first() {
+            Some(name) => self.macros.get(name).cloned(),
+            None => Some(self.macro_keys.clone()),
+        };
+        let e = &mut self.editors[self.editor_index];
+        let keys = match keys {
+            _ if self.replaying => {
+                e.set_message(&"Macros can't play other macros");
+                return Ok(());
+            }
+            _ if self.recording.is_some() => {
+                e.set_message(&"Can't play a macro while recording");
+                return Ok(());
+            }
+            Some(keys) if !keys.is_empty() => keys,
+            Some(_) => {
+                e.set_message(&"No macro recorded");
+                return Ok(());
+            }
+            None => {
+                e.set_message(&format!("Unknown macro {}", words[0]));
+                return Ok(());
+            }
+        };
+        // A selection ending at the start of a line doesn't include that line
+        let lines = match e.get_selection() {
+            _ if !on_lines => None,
+            Some((start, end)) if end.0 == 0 && end.1 > start.1 => Some((start.1, end.1 - 1)),
+            Some((start, end)) => Some((start.1, end.1)),
+            None => {
+                e.set_message(&"Select the lines to play the macro on");
+                return Ok(());
+            }
+        };
+
+        let index = self.editor_index;
+        self.editors[index].begin_undo_group();
+        self.replaying = true;
+        let result = match lines {
+            Some((top, bottom)) => self.replay_on_lines(&keys, top, bottom),
+            None => (0..count).try_for_each(|_| self.replay(&keys)),
+        };
+        self.replaying = false;
+        if let Some(e) = self.editors.get_mut(index) {
+            e.end_undo_group();
+        }
+        result
+    }
+
+    fn replay(&mut self, keys: &[KeyChord]) -> crossterm::Result<()> {
+        for key in keys {
+            if self.quit {
+                break;
+            }
+            self.handle_key(key.to_event())?;
+        }
+        Ok(())
+    }
+
+    // Replays keys from the start of each line from top to bottom, following lines the macro
+    // adds or removes
+    fn replay_on_lines(
+        &mut self,
+        keys: &[KeyChord],
+        top: usize,
+        bottom: usize,
+    ) -> crossterm::Result<()> {
+        let index = self.editor_index;
+        let (mut y, mut bottom) = (top as isize, bottom as isize);
+        while y <= bottom && index == self.editor_index && !self.quit {
+            let e = &mut self.editors[index];
+            let count = e.get_line_count() as isize;
+            e.clear_selection();
+            e.move_cursor(Movement::Absolute(0, y as usize), false);
+            self.replay(keys)?;
+            let added = self.editors[index].get_line_count() as isize - count;
+            bottom += added;
+            y += 1 + added;
+        }
+        Ok(())
+    }
+
+    // Handles a key, returning whether anything needs to be drawn again
+    fn handle_key(&mut self, event: KeyEvent) -> crossterm::Result<bool> {
+        if let (Some(keys), false) = (self.recording.as_mut(), self.replaying) {
+            let idle = self.picker.is_none()
+                && self.prompt.is_none()
+                && self.keymap.get_pending().is_empty();
+            if idle {
+                self.recording_start = keys.len();
+            }
+            keys.push(KeyChord::from_event(&event));
+        }
+        if self.picker.is_some() {
+            return self.handle_picker_key(event);
+        }
+        if self.prompt.is_some() {
+            return self.handle_prompt_key(event);
+        }
+        let chord = KeyChord::from_event(&event);
+        if self.search.is_some() && self.handle_search_key(chord) {
+            return Ok(true);
+        }
+        if let Some(vim) = self.vim.as_mut() {
+            let e = &mut self.editors[self.editor_index];
+            match vim.handle_key(chord, e, &mut self.registers) {
+                VimResult::Handled => return Ok(true),
+                VimResult::Yanked => {
+                    self.share_clipboard()?;
+                    return Ok(true);
+                }
+                VimResult::Command(command) => {
+                    self.run(&command)?;
+                    return Ok(true);
+                }
+                VimResult::Repeat(keys, count) => {
+                    let replaying = std::mem::replace(&mut self.replaying, true);
+                    let result = (0..count).try_for_each(|_| self.replay(&keys));
+                    self.replaying = replaying;
+                    result?;
+                    return Ok(true);
+                }
+                VimResult::Unhandled => {}
+            }
+        }
+        match self.keymap.feed(chord) {
+            KeyMatch::Pending => {
+                let message = format!("{} ...", format_sequence(self.keymap.get_pending()));
+                self.editor().set_message(&message);
+            }
+            KeyMatch::Command(command) => self.run(&command)?,
+            KeyMatch::Unbound(keys) if keys.len() > 1 => {
+                let message = format!("{} is not bound", format_sequence(&keys));
+                self.editor().set_message(&message);
+            }
+            KeyMatch::Unbound(_) => match chord.get_char() {
+                Some(c) => {
+                    // Typing after setting the mark inserts rather than replacing the selection
+                    if std::mem::take(&mut self.mark) {
+                        self.editor().clear_selection();
                     }
-                    KeyCode::Backspace if event.modifiers == KeyModifiers::NONE => {
-                        if let Some(ref mut prompt) = prompt {
-                            prompt.backspace();
-                        } else {
-                            e.backspace_char();
-                        }
+                    self.last_command.clear();
+                    self.editor().write_char(c)
+                }
+                None => return Ok(false),
+            },
+        }
+        Ok(true)
+    }
+
+    // Handles a key during an incremental search, returning false when it ends the search
+    // without being used so it can go through the key bindings
+    fn handle_search_key(&mut self, chord: KeyChord) -> bool {
+        let search = self.search.as_mut().unwrap();
+        let e = &mut self.editors[self.editor_index];
+        match (chord.get_char(), self.keymap.get_command(chord)) {
+            (Some(c), _) => search.add_char(c, e),
+            (_, Some("search-forward")) => search.next(e, true, &self.last_search),
+            (_, Some("search-backward")) => search.next(e, false, &self.last_search),
+            (_, Some("backspace")) => search.backspace(e),
+            (_, Some("cancel")) => {
+                search.cancel(e);
+                e.set_message(&"Quit");
+                self.search = None;
+                return true;
+            }
+            _ => {
+                search.finish(e);
+                if !search.get_query().is_empty() {
+                    self.last_search = search.get_query().to_string();
+                    e.set_message(&format!("Searched for {}", self.last_search));
+                }
+                self.search = None;
+                return matches!(chord.get_code(), KeyCode::Enter | KeyCode::Esc);
+            }
+        }
+        e.set_message(&search.get_status());
+        true
+    }
+
+    fn handle_prompt_key(&mut self, event: KeyEvent) -> crossterm::Result<bool> {
+        let prompt = self.prompt.as_mut().unwrap();
+        match event.code {
+            KeyCode::Enter => {
+                // A partly typed command name, or any text once a suggestion was picked with
+                // Up or Down, runs the picked suggestion
+                if let Some(suggestions) = &self.suggestions {
+                    let text = prompt.get_text().trim();
+                    if suggestions.was_moved()
+                        || text.contains(char::is_whitespace)
+                        || !is_command(text)
+                    {
+                        prompt.set_text(suggestions.get_replacement());
                     }
-                    KeyCode::Enter if event.modifiers == KeyModifiers::NONE => {
-                        if prompt.is_none() {
-                            e.do_return();
-                        } else {
-                            let response = prompt
-                                .unwrap()
-                                .take_answer()
-                                .unwrap_or_else(|| "".to_string());
-                            prompt = None;
-                            let info: Vec<&str> = response.split(' ').collect();
-                            match info[0] {
-                                "save" => {
-                                    if info.len() > 1 {
-                                        e.save_as(std::path::PathBuf::from(info[1]))?;
-                                    } else {
-                                        e.set_message(&"Specify path to save");
-                                    }
-                                }
-                                "open" => {
-                                    if info.len() > 1 {
-                                        let path = std::path::PathBuf::from(info[1]);
-                                        if !path.exists() {
-                                            e.set_message(&"File does not exist");
-                                        } else {
-                                            e.open_file(&path)?;
-                                        }
-                                    } else {
-                                        e.set_message(&"Specify file to open");
-                                    }
-                                }
-                                "reload" => {
-                                    e.try_reload()?;
-                                }
-                                "quit" => {
-                                    if e.try_quit() {
-                                        if editors.len() == 1 {
-                                            break;
-                                        } else {
-                                            editors.remove(editor_index);
-                                            editor_index = 0;
-                                        }
-                                    }
-                                }
-                                _ => {
-                                    e.set_message(&format!("Command not recognized {}", info[0]));
-                                }
-                            }
-                        }
+                }
+                let response = prompt.take_answer().unwrap_or_default();
+                self.prompt = None;
+                self.suggestions = None;
+                self.history.push(&response);
+                self.run(&response)?;
+            }
+            KeyCode::Esc => {
+                self.prompt = None;
+                self.suggestions = None;
+                self.history.reset();
+            }
+            KeyCode::Backspace => {
+                prompt.backspace();
+                self.update_suggestions();
+            }
+            KeyCode::Delete => {
+                prompt.delete_char();
+                self.update_suggestions();
+            }
+            KeyCode::Left if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                prompt.move_word(false)
+            }
+            KeyCode::Right if event.modifiers.contains(KeyModifiers::CONTROL) => {
+                prompt.move_word(true)
+            }
+            KeyCode::Left => prompt.move_cursor(-1),
+            KeyCode::Right => prompt.move_cursor(1),
+            KeyCode::Home => prompt.move_home(),
+            KeyCode::End => prompt.move_end(),
+            KeyCode::Char('u') if event.modifiers == KeyModifiers::CONTROL => {
+                prompt.delete_to_start();
+                self.update_suggestions();
+            }
+            KeyCode::Char('w') if event.modifiers == KeyModifiers::CONTROL => {
+                prompt.delete_word_left();
+                self.update_suggestions();
+            }
+            KeyCode::Up | KeyCode::Down => {
+                let up = event.code == KeyCode::Up;
+                if let Some(suggestions) = self.suggestions.as_mut() {
+                    suggestions.move_selection(if up { -1 } else { 1 });
+                } else {
+                    let line = if up {
+                        self.history.older(prompt.get_text())
+                    } else {
+                        self.history.newer()
+                    };
+                    if let Some(line) = line {
+                        prompt.set_text(line);
                     }
-                    KeyCode::Delete if event.modifiers == KeyModifiers::NONE => {
-                        if let Some(ref mut prompt) = prompt {
-                            prompt.delete_char();
-                        } else {
-                            e.delete_char();
-                        }
+                }
+            }
+            KeyCode::Tab => match self.suggestions.take() {
+                Some(suggestions) => {
+                    prompt.set_text(suggestions.get_replacement());
+                    // Accepting a directory goes on to complete inside it
+                    if suggestions.get_replacement().ends_with('/') {
+                        self.complete_argument();
                     }
-                    KeyCode::Esc if event.modifiers == KeyModifiers::NONE => {
-                        if prompt.is_some() {
-                            let mut un_prompt = prompt.unwrap();
-                            un_prompt.take_answer();
-                            prompt = None;
-                        }
+                }
+                None => self.complete_argument(),
+            },
+            KeyCode::Char(c) if KeyChord::from_event(&event).get_char().is_some() => {
+                prompt.add_char(c);
+                self.update_suggestions();
+            }
+            _ => return Ok(false),
+        }
+        Ok(true)
+    }
+
+    // Suggests the commands matching the name being typed in the palette
+    fn update_suggestions(&mut self) {
+        let text = self.prompt.as_ref().map_or("", |p| p.get_text());
+        self.suggestions = None;
+        if text.is_empty() || text.contains(char::is_whitespace) {
+            return;
+        }
+        let items = get_command_items(find_commands(text));
+        if !items.is_empty() {
+            self.suggestions = Some(Suggestions::new(items));
+        }
+    }
+
+    // Completes the palette's last argument from the file names, themes, syntaxes or macros it
+    // could be, or the command name when there's no argument yet
+    fn complete_argument(&mut self) {
+        let text = match &self.prompt {
+            Some(prompt) => prompt.get_text().to_string(),
+            None => return,
+        };
+        let (words, _) = split_words(&text);
+        let name = words.first().map_or("", |(_, word)| word.as_str());
+        if words.len() < 2 && !text.ends_with(char::is_whitespace) {
+            let commands = if name.is_empty() {
+                COMMANDS.iter().collect()
+            } else {
+                find_commands(name)
+            };
+            let items = get_command_items(commands);
+            match items.len() {
+                0 => {}
+                1 => self.prompt.as_mut().unwrap().set_text(&items[0].2),
+                _ => self.suggestions = Some(Suggestions::new(items)),
+            }
+            return;
+        }
+        // Arguments with spaces are completed as one, as `run` joins them
+        let start = words.get(1).map_or(text.len(), |(start, _)| *start);
+        let typed: Vec<&str> = words[1..].iter().map(|(_, word)| word.as_str()).collect();
+        let typed = typed.join(" ");
+        let (dir, partial, candidates) = match name {
+            "theme" => ("", typed.as_str(), self.get_theme_names()),
+            "syntax" => ("", typed.as_str(), self.editor().get_syntax_names()),
+            "play-macro" | "play-macro-lines" => {
+                ("", typed.as_str(), self.macros.keys().cloned().collect())
+            }
+            "save" | "open" => {
+                let (dir, partial) = match typed.rfind('/') {
+                    Some(i) => typed.split_at(i + 1),
+                    None => ("", typed.as_str()),
+                };
+                (dir, partial, get_dir_entries(dir, partial.starts_with('.')))
+            }
+            _ => return,
+        };
+        let partial_lower = partial.to_lowercase();
+        let matches: Vec<&String> = candidates
+            .iter()
+            .filter(|c| c.to_lowercase().starts_with(&partial_lower))
+            .collect();
+        let first = match matches.first() {
+            Some(first) => first,
+            None => return,
+        };
+        let mut common: Vec<char> = first.chars().collect();
+        for other in &matches[1..] {
+            let shared = common
+                .iter()
+                .zip(other.chars())
+                .take_while(|(a, b)| a.to_lowercase().eq(b.to_lowercase()))
+                .count();
+            common.truncate(shared);
+        }
+        let common: String = common.into_iter().collect();
+        let prefix = &text[..start];
+        let replace = |completed: &str, close: bool| {
+            format!(
+                "{}{}",
+                prefix,
+                quote_word(&format!("{}{}", dir, completed), close)
+            )
+        };
+        if matches.len() == 1 {
+            let text = replace(first, !first.ends_with('/'));
+            self.prompt.as_mut().unwrap().set_text(&text);
+            return;
+        }
+        if common.len() > partial.len() {
+            self.prompt
+                .as_mut()
+                .unwrap()
+                .set_text(&replace(&common, false));
+        }
+        let items = matches
+            .iter()
+            .map(|m| (m.to_string(), String::new(), replace(m, !m.ends_with('/'))))
+            .collect();
+        self.suggestions = Some(Suggestions::new(items));
+    }
+
+    fn handle_picker_key(&mut self, event: KeyEvent) -> crossterm::Result<bool> {
+        let (kind, picker) = self.picker.as_mut().unwrap();
+        let previewing = matches!(kind, PickerKind::Theme);
+        let chosen = self.settings.theme.clone();
+        match event.code {
+            KeyCode::Up => picker.move_selection(-1),
+            KeyCode::Down => picker.move_selection(1),
+            KeyCode::PageUp => picker.move_selection(-PICKER_PAGE),
+            KeyCode::PageDown => picker.move_selection(PICKER_PAGE),
+            KeyCode::Home => picker.move_selection(isize::MIN / 2),
+            KeyCode::End => picker.move_selection(isize::MAX / 2),
+            KeyCode::Enter => {
+                let selected = picker.get_selected().cloned();
+                let command = match kind {
+                    PickerKind::Commands(commands) => commands.get(picker.get_index()).cloned(),
+                    PickerKind::Theme => None,
+                };
+                self.picker = None;
+                if let Some(command) = command {
+                    self.run(&command)?;
+                } else if let (true, Some(name)) = (previewing, selected) {
+                    self.settings.theme = name;
+                    let message = format!("Theme set to {}", self.settings.theme);
+                    self.editor().set_message(&message);
+                }
+            }
+            KeyCode::Char('s') if previewing && event.modifiers == KeyModifiers::CONTROL => {
+                self.settings.theme = picker.get_selected().unwrap().clone();
+                self.picker = None;
+                let path = self.config_dir.join("settings.toml");
+                let message = match save_theme(&path, &self.settings.theme) {
+                    Ok(()) => format!("Theme set to {} and saved", self.settings.theme),
+                    Err(err) => format!(
+                        "Theme set to {} but not saved: {}",
+                        self.settings.theme, err
+                    ),
+                };
+                self.restamp(&path);
+                self.editor().set_message(&message);
+            }
+            KeyCode::Esc => self.picker = None,
+            _ => return Ok(false),
+        }
+        // Preview the selected theme in the current editor, then once closed apply the chosen
+        // one to every editor or go back to the one they have
+        if previewing {
+            match &self.picker {
+                Some((_, picker)) => {
+                    if let Some(name) = picker.get_selected().cloned() {
+                        self.show_theme(&name, false);
                     }
-                    KeyCode::Char(c)
-                        if event.modifiers == KeyModifiers::NONE
-                            || event.modifiers == KeyModifiers::SHIFT =>
+                }
+                None => {
+                    let name = self.settings.theme.clone();
+                    self.show_theme(&name, name != chosen);
+                }
+            }
+        }
+        Ok(true)
+    }
+
+    // Handles a mouse event, returning whether anything needs to be drawn again
+    fn handle_mouse(&mut self, event: MouseEvent) -> bool {
+        if self.picker.is_some() || !self.settings.mouse {
+            return false;
+        }
+        let e = &mut self.editors[self.editor_index];
+        let cur_pos = (event.column, event.row);
+        let cur_pos = (
+            cur_pos.0 - min(cur_pos.0, e.draw_area.x + 1),
+            cur_pos.1 - min(cur_pos.1, e.draw_area.y),
+        );
+        match event.kind {
+            MouseEventKind::ScrollDown => e.move_cursor(
+                Movement::ScrollDown(2),
+                event.modifiers.intersects(KeyModifiers::SHIFT),
+            ),
+            MouseEventKind::ScrollUp => e.move_cursor(
+                Movement::ScrollUp(2),
+                event.modifiers.intersects(KeyModifiers::SHIFT),
+            ),
+            MouseEventKind::Down(_) => {
+                let now = Instant::now();
+                self.clicks = match self.last_click {
+                    Some((time, x, y))
+                        if (x, y) == cur_pos && now.duration_since(time) < MULTI_CLICK_TIME =>
                     {
-                        if let Some(ref mut prompt) = prompt {
-                            prompt.add_char(c);
-                        } else {
-                            e.write_char(c);
-                        }
+                        self.clicks % 3 + 1
                     }
-                    _ => continue,
+                    _ => 1,
+                };
+                self.last_click = Some((now, cur_pos.0, cur_pos.1));
+                // Clicking ends a search and the selection from the mark
+                self.last_command.clear();
+                self.mark = false;
+                self.search = None;
+                e.move_cursor(
+                    Movement::AbsoluteScreen(cur_pos.0, cur_pos.1),
+                    event.modifiers.intersects(KeyModifiers::SHIFT),
+                );
+                match self.clicks {
+                    2 => e.select_word(),
+                    3 => e.select_line(),
+                    _ => {}
+                }
+            }
+            MouseEventKind::Drag(_) if event.modifiers.intersects(KeyModifiers::ALT) => {
+                e.move_cursor_block(Movement::AbsoluteScreen(cur_pos.0 + 1, cur_pos.1))
+            }
+            MouseEventKind::Drag(_) => {
+                e.move_cursor(Movement::AbsoluteScreen(cur_pos.0 + 1, cur_pos.1), true)
+                // +1 to include char under cursor
+            }
+            _ => return false,
+        }
+        true
+    }
+
+    // Watches the config folder, autosaves and takes highlighted lines while there's no input,
+    // returning whether anything needs to be drawn again
+    fn idle(&mut self) -> crossterm::Result<bool> {
+        let mut reload = false;
+        if self.settings.watch_config && self.last_watch.elapsed() >= WATCH_INTERVAL {
+            self.last_watch = Instant::now();
+            reload = get_config_stamp(&self.config_dir) != self.config_stamp;
+        }
+        if reload {
+            self.reload_config()?;
+        }
+        let autosave = self.settings.autosave > 0
+            && self.last_input.elapsed() >= Duration::from_secs(self.settings.autosave)
+            && self.editors.iter().any(|e| e.is_dirty());
+        if autosave {
+            self.last_input = Instant::now();
+            for editor in self.editors.iter_mut().filter(|e| e.is_dirty()) {
+                if let Err(err) = editor.autosave() {
+                    editor.set_message(&format!("Couldn't save: {}", err));
                 }
             }
         }
+        Ok(self.editor().receive_highlights() || autosave || reload)
+    }
 
-        if prompt.is_none() {
-            if let Some(prompt_message) = editors[editor_index].take_prompt() {
-                prompt = Some(Prompt::new(Some(prompt_message)));
+    fn draw(&mut self, terminal: &mut Terminal<CrosstermBackend<Stdout>>) -> crossterm::Result<()> {
+        if self.prompt.is_none() {
+            if let Some(prompt_message) = self.editor().take_prompt() {
+                self.open_prompt(&prompt_message);
             }
         }
+        let mut mode = self.vim.as_ref().map(|v| v.get_status());
+        if self.recording.is_some() {
+            mode = Some(mode.map_or("RECORDING".to_string(), |m| m + " RECORDING"));
+        }
+        self.editor().set_mode(mode);
 
+        let (fg_color, bg_color, sel_color) = self.colors;
+        let editor_index = self.editor_index;
+        let editors = &mut self.editors;
+        let prompt = &mut self.prompt;
+        let suggestions = &mut self.suggestions;
+        let picker = &mut self.picker;
         let mut prompt_cursor = tui::layout::Rect::default();
         terminal.hide_cursor()?;
         terminal.draw(|f| {
@@ -460,19 +1319,100 @@ fn edit(file: Option<&str>) -> crossterm::Result<()> {
             f.render_widget(main_block, size);
             f.render_widget(tabs, chunks[0]);
             f.render_widget(&mut editors[editor_index], chunks[1]);
-            if let Some(prompt) = prompt.clone() {
+            if let Some(prompt) = prompt.as_mut() {
                 f.render_widget(prompt, chunks[2]);
                 prompt_cursor = chunks[2];
+                if let Some(suggestions) = suggestions.as_mut() {
+                    suggestions.set_style(TuiStyle::default().fg(fg_color).bg(bg_color));
+                    f.render_widget(suggestions, chunks[1]);
+                }
+            }
+            if let Some((_, picker)) = picker.as_mut() {
+                picker.set_style(TuiStyle::default().fg(fg_color).bg(bg_color));
+                f.render_widget(picker, chunks[1]);
             }
         })?;
-        let cur_pos = if let Some(prompt) = prompt.clone() {
+        let cur_pos = if let Some(prompt) = &self.prompt {
             let cur = prompt.get_cursor();
             (prompt_cursor.x + cur.0, prompt_cursor.y + cur.1)
         } else {
-            editors[editor_index].get_rel_cursor()
+            self.editor().get_rel_cursor()
         };
         terminal.set_cursor(cur_pos.0, cur_pos.1)?;
         terminal.show_cursor()?;
+        Ok(())
+    }
+}
+
+fn edit(file: Option<&str>) -> crossterm::Result<()> {
+    let config_dir = home_dir()
+        .unwrap_or_else(|| PathBuf::from("~"))
+        .join(".config/redit");
+    let (mut session, warnings) = Session::new(config_dir);
+    let e = session.editor();
+    if let Some(file) = file {
+        if file.starts_with('~') {
+            let path = home_dir()
+                .expect("Cannot find home directory")
+                .join(file.split_at(2).1);
+            e.open_file(&path.to_str().expect("Failed to use home directory"))?;
+        } else {
+            e.open_file(&file)?;
+        }
+    }
+
+    // Problems with the settings are shown rather than stopping the editor from starting
+    if !warnings.is_empty() {
+        e.set_message(&warnings.join("; "));
+    }
+
+    let mut stdout = std::io::stdout();
+    execute!(stdout, EnterAlternateScreen)?;
+    if session.settings.mouse {
+        execute!(stdout, EnableMouseCapture)?;
+    }
+
+    enable_raw_mode()?;
+
+    let backend = CrosstermBackend::new(stdout);
+    let mut terminal = Terminal::new(backend)?;
+
+    session.draw(&mut terminal)?;
+
+    while !session.quit {
+        let event = if poll(HIGHLIGHT_POLL_TIME)? {
+            Some(read()?)
+        } else {
+            None
+        };
+        if event.is_some() {
+            session.last_input = Instant::now();
+        }
+        let changed = match event {
+            None => session.idle()?,
+            Some(Event::Resize(width, height)) => {
+                #[cfg(target_family = "windows")]
+                terminal.resize(tui::layout::Rect {
+                    x: 0,
+                    y: 0,
+                    width,
+                    height,
+                })?;
+                #[cfg(target_family = "unix")]
+                terminal.resize(tui::layout::Rect {
+                    x: 0,
+                    y: 0,
+                    width: width - 1,
+                    height: height - 1,
+                })?;
+                true
+            }
+            Some(Event::Mouse(event)) => session.handle_mouse(event),
+            Some(Event::Key(event)) => session.handle_key(event)?,
+        };
+        if changed && !session.quit {
+            session.draw(&mut terminal)?;
+        }
     }
 
     disable_raw_mode()?;
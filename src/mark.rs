@@ -0,0 +1,24 @@
+// A bookmarked line, optionally named so it can be jumped to directly
+#[derive(Clone, PartialEq)]
+pub struct Mark {
+    line: usize,
+    name: Option<char>,
+}
+
+impl Mark {
+    pub fn new(line: usize, name: Option<char>) -> Self {
+        Mark { line, name }
+    }
+
+    pub fn get_line(&self) -> usize {
+        self.line
+    }
+
+    pub fn get_name(&self) -> Option<char> {
+        self.name
+    }
+
+    pub fn set_line(&mut self, line: usize) {
+        self.line = line;
+    }
+}
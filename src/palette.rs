@@ -0,0 +1,157 @@
+use std::cmp::min;
+
+use tui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Modifier, Style},
+    widgets::{Clear, Widget},
+};
+
+// How many lines run from the palette are remembered
+const HISTORY_SIZE: usize = 100;
+// The most suggestions shown at once
+const SUGGESTION_ROWS: usize = 8;
+
+// Lines run from the palette, oldest first, to go back through with Up and Down
+pub struct History {
+    lines: Vec<String>,
+    // The line being shown, or None when back at what was typed
+    index: Option<usize>,
+    typed: String,
+}
+
+impl History {
+    pub fn new() -> Self {
+        History {
+            lines: vec![],
+            index: None,
+            typed: String::new(),
+        }
+    }
+
+    // Remembers a line unless it repeats the last one
+    pub fn push(&mut self, line: &str) {
+        let line = line.trim();
+        if !line.is_empty() && self.lines.last().map(|l| l.as_str()) != Some(line) {
+            if self.lines.len() == HISTORY_SIZE {
+                self.lines.remove(0);
+            }
+            self.lines.push(line.to_string());
+        }
+        self.reset();
+    }
+
+    pub fn reset(&mut self) {
+        self.index = None;
+        self.typed.clear();
+    }
+
+    // Goes back to the previous line, keeping `typed` to come back to
+    pub fn older(&mut self, typed: &str) -> Option<&str> {
+        let index = match self.index {
+            None if self.lines.is_empty() => return None,
+            None => {
+                self.typed = typed.to_string();
+                self.lines.len() - 1
+            }
+            Some(index) => index.saturating_sub(1),
+        };
+        self.index = Some(index);
+        Some(&self.lines[index])
+    }
+
+    // Goes forward to the next line, or what was typed after the last one
+    pub fn newer(&mut self) -> Option<&str> {
+        let index = self.index?;
+        if index + 1 < self.lines.len() {
+            self.index = Some(index + 1);
+            Some(&self.lines[index + 1])
+        } else {
+            self.index = None;
+            Some(&self.typed)
+        }
+    }
+}
+
+impl Default for History {
+    fn default() -> Self {
+        History::new()
+    }
+}
+
+// Choices drawn just above the palette, such as the commands matching the name being typed
+// with their descriptions, each replacing the palette's text when chosen
+pub struct Suggestions {
+    // (label, description, replacement)
+    items: Vec<(String, String, String)>,
+    // Whether the selection was moved from the first suggestion
+    moved: bool,
+    offset: usize,
+    selected: usize,
+    style: Style,
+}
+
+impl Widget for &mut Suggestions {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let rows = min(min(self.items.len(), SUGGESTION_ROWS), area.height as usize);
+        let area = Rect::new(
+            area.x,
+            area.y + area.height - rows as u16,
+            area.width,
+            rows as u16,
+        );
+        Clear.render(area, buf);
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if rows > 0 && self.selected >= self.offset + rows {
+            self.offset = self.selected + 1 - rows;
+        }
+        let width = self.items.iter().map(|i| i.0.chars().count()).max();
+        let width = width.unwrap_or(0);
+        for (row, (label, description, _)) in
+            self.items.iter().enumerate().skip(self.offset).take(rows)
+        {
+            let style = if row == self.selected {
+                self.style.add_modifier(Modifier::REVERSED)
+            } else {
+                self.style
+            };
+            let y = area.y + (row - self.offset) as u16;
+            let text = format!(" {:<width$}  {}", label, description, width = width);
+            buf.set_style(Rect::new(area.x, y, area.width, 1), style);
+            buf.set_stringn(area.x, y, text, area.width as usize, style);
+        }
+    }
+}
+
+impl Suggestions {
+    pub fn new(items: Vec<(String, String, String)>) -> Self {
+        Suggestions {
+            items,
+            moved: false,
+            offset: 0,
+            selected: 0,
+            style: Style::default(),
+        }
+    }
+
+    pub fn set_style(&mut self, style: Style) {
+        self.style = style;
+    }
+
+    // Moves the selection, wrapping around at either end
+    pub fn move_selection(&mut self, dy: isize) {
+        let len = self.items.len() as isize;
+        self.selected = (self.selected as isize + dy).rem_euclid(len) as usize;
+        self.moved = true;
+    }
+
+    pub fn was_moved(&self) -> bool {
+        self.moved
+    }
+
+    // The palette text for the selected suggestion
+    pub fn get_replacement(&self) -> &str {
+        &self.items[self.selected].2
+    }
+}
@@ -0,0 +1,85 @@
+use std::cmp::min;
+
+use tui::{
+    buffer::Buffer,
+    layout::Rect,
+    style::{Modifier, Style},
+    widgets::{Block, Borders, Clear, Widget},
+};
+
+// A list of choices drawn over the editor, such as the themes to preview
+pub struct Picker {
+    items: Vec<String>,
+    offset: usize,
+    selected: usize,
+    style: Style,
+    title: String,
+}
+
+impl Widget for &mut Picker {
+    fn render(self, area: Rect, buf: &mut Buffer) {
+        let width = min(area.width, 50);
+        let height = min(area.height, self.items.len() as u16 + 2);
+        let area = Rect::new(
+            area.x + (area.width - width) / 2,
+            area.y + (area.height - height) / 2,
+            width,
+            height,
+        );
+        Clear.render(area, buf);
+        let block = Block::default()
+            .title(self.title.clone())
+            .borders(Borders::ALL)
+            .style(self.style);
+        let inner_area = block.inner(area);
+        block.render(area, buf);
+
+        // Keep the selected item on screen
+        let rows = inner_area.height as usize;
+        if self.selected < self.offset {
+            self.offset = self.selected;
+        } else if rows > 0 && self.selected >= self.offset + rows {
+            self.offset = self.selected + 1 - rows;
+        }
+        for (row, item) in self.items.iter().enumerate().skip(self.offset).take(rows) {
+            let style = if row == self.selected {
+                self.style.add_modifier(Modifier::REVERSED)
+            } else {
+                self.style
+            };
+            let y = inner_area.y + (row - self.offset) as u16;
+            buf.set_style(Rect::new(inner_area.x, y, inner_area.width, 1), style);
+            buf.set_stringn(inner_area.x, y, item, inner_area.width as usize, style);
+        }
+    }
+}
+
+impl Picker {
+    pub fn new(title: &str, items: Vec<String>, selected: usize) -> Self {
+        Picker {
+            items,
+            offset: 0,
+            selected,
+            style: Style::default(),
+            title: title.to_string(),
+        }
+    }
+
+    pub fn set_style(&mut self, style: Style) {
+        self.style = style;
+    }
+
+    // Moves the selection, stopping at the first and last items
+    pub fn move_selection(&mut self, dy: isize) {
+        let last = self.items.len().saturating_sub(1) as isize;
+        self.selected = (self.selected as isize + dy).clamp(0, last) as usize;
+    }
+
+    pub fn get_selected(&self) -> Option<&String> {
+        self.items.get(self.selected)
+    }
+
+    pub fn get_index(&self) -> usize {
+        self.selected
+    }
+}
@@ -4,22 +4,54 @@ use tui::{
     style::Style,
     widgets::{Block, Borders, Widget},
 };
+use unicode_segmentation::UnicodeSegmentation;
+use unicode_width::UnicodeWidthStr;
 
+use crate::word::{char_class, CharClass};
+
+// A single line of input. The cursor is a byte index that always sits between graphemes, and
+// the text scrolls sideways to keep it in view when it doesn't fit.
 #[derive(Clone)]
 pub struct Prompt {
     cx: usize,
+    // How many columns of the text are scrolled out of view on the left
+    offset: usize,
     response: Option<String>,
 }
 
-impl Widget for Prompt {
+impl Widget for &mut Prompt {
     fn render(self, area: Rect, buf: &mut Buffer) {
         let block = Block::default().borders(Borders::TOP);
         let inner_area = block.inner(area);
         block.render(area, buf);
+        if inner_area.width == 0 || inner_area.height == 0 {
+            return;
+        }
+        // One column goes to the > character
+        let width = inner_area.width as usize - 1;
+        let col = self.get_text()[..self.cx].width();
+        if col < self.offset {
+            self.offset = col;
+        } else if col >= self.offset + width {
+            self.offset = col + 1 - width;
+        }
+        let mut visible = String::new();
+        let mut start = 0;
+        for grapheme in self.get_text().graphemes(true) {
+            let end = start + grapheme.width();
+            if start >= self.offset && end <= self.offset + width {
+                visible.push_str(grapheme);
+            } else if start < self.offset && end > self.offset {
+                // Pad the part of a wide grapheme left in view
+                visible.push_str(&" ".repeat(end - self.offset));
+            }
+            start = end;
+        }
+        let marker = if self.offset > 0 { "<" } else { ">" };
         buf.set_stringn(
             inner_area.x,
             inner_area.y,
-            ">".to_string() + self.response.as_ref().unwrap_or(&"".to_string()),
+            marker.to_string() + &visible,
             inner_area.width as usize,
             Style::default(),
         );
@@ -29,54 +61,234 @@ impl Widget for Prompt {
 impl Prompt {
     pub fn new(message: Option<String>) -> Self {
         Prompt {
-            cx: message.clone().map(|s| s.len()).unwrap_or(0),
+            cx: message.as_ref().map(|s| s.len()).unwrap_or(0),
+            offset: 0,
             response: message,
         }
     }
 
-    pub fn delete_char(&mut self) {
-        if let Some(res) = &self.response {
-            if self.cx < res.len() {
-                let mut res = res.to_string();
-                res.remove(self.cx).to_string();
-                self.response = Some(res);
-            }
+    // The byte index of the next or previous word stop from the cursor, moving over whole
+    // graphemes classed by their first character
+    fn word_boundary(&self, forward: bool) -> usize {
+        let text = self.get_text();
+        let class = |g: &str| char_class(g.chars().next().unwrap_or(' '));
+        let mut graphemes: Vec<(usize, &str)> = if forward {
+            text[self.cx..]
+                .grapheme_indices(true)
+                .map(|(i, g)| (self.cx + i + g.len(), g))
+                .collect()
+        } else {
+            text[..self.cx].grapheme_indices(true).rev().collect()
+        };
+        let skipped = graphemes
+            .iter()
+            .take_while(|(_, g)| class(g) == CharClass::Whitespace)
+            .count();
+        graphemes.drain(..skipped);
+        let word_class = match graphemes.first() {
+            Some((_, g)) => class(g),
+            None if forward => return text.len(),
+            None => return 0,
+        };
+        graphemes
+            .iter()
+            .take_while(|(_, g)| class(g) == word_class)
+            .last()
+            .map_or(self.cx, |(i, _)| *i)
+    }
+
+    // The first grapheme boundary at or after byte index `i`
+    fn grapheme_end(&self, i: usize) -> usize {
+        self.get_text()
+            .grapheme_indices(true)
+            .map(|(start, g)| start + g.len())
+            .find(|end| *end >= i)
+            .unwrap_or(i)
+    }
+
+    // The start of the grapheme before the cursor
+    fn prev_grapheme(&self) -> usize {
+        let text = self.get_text();
+        text[..self.cx]
+            .grapheme_indices(true)
+            .next_back()
+            .map_or(0, |(i, _)| i)
+    }
+
+    // The end of the grapheme after the cursor
+    fn next_grapheme(&self) -> usize {
+        let text = self.get_text();
+        text[self.cx..]
+            .graphemes(true)
+            .next()
+            .map_or(self.cx, |g| self.cx + g.len())
+    }
+
+    // Removes the text between two byte indices around the cursor, leaving it at the start
+    fn remove(&mut self, start: usize, end: usize) {
+        if let Some(res) = self.response.as_mut() {
+            res.replace_range(start..end, "");
         }
+        self.cx = start;
+    }
+
+    pub fn delete_char(&mut self) {
+        let end = self.next_grapheme();
+        self.remove(self.cx, end);
     }
 
     pub fn add_char(&mut self, c: char) {
-        let mut res = self.response.as_ref().unwrap_or(&"".to_string()).clone();
-        res.push(c);
-        self.response = Some(res);
-        self.cx += 1;
+        let res = self.response.get_or_insert_with(String::new);
+        res.insert(self.cx, c);
+        // Keep the cursor after the grapheme if the character joined the one following it
+        self.cx = self.grapheme_end(self.cx + c.len_utf8());
     }
 
     pub fn backspace(&mut self) {
-        if self.cx > 0 {
-            self.cx -= 1;
-            self.delete_char();
-        }
+        let start = self.prev_grapheme();
+        self.remove(start, self.cx);
     }
 
+    // Moves the cursor by whole graphemes
     pub fn move_cursor(&mut self, dx: isize) {
-        if dx >= 0 {
-            self.cx = std::cmp::min(
-                self.response.as_ref().unwrap_or(&"".to_string()).len(),
-                self.cx + dx as usize,
-            );
-        } else if self.cx as isize + dx >= 0 {
-            self.cx = (self.cx as isize + dx) as usize;
-        } else {
-            self.cx = 0;
+        for _ in 0..dx.unsigned_abs() {
+            self.cx = if dx < 0 {
+                self.prev_grapheme()
+            } else {
+                self.next_grapheme()
+            };
         }
     }
 
+    pub fn move_home(&mut self) {
+        self.cx = 0;
+    }
+
+    pub fn move_end(&mut self) {
+        self.cx = self.get_text().len();
+    }
+
+    pub fn move_word(&mut self, forward: bool) {
+        self.cx = self.word_boundary(forward);
+    }
+
+    // Deletes everything before the cursor
+    pub fn delete_to_start(&mut self) {
+        self.remove(0, self.cx);
+    }
+
+    pub fn delete_word_left(&mut self) {
+        let start = self.word_boundary(false);
+        self.remove(start, self.cx);
+    }
+
+    pub fn get_text(&self) -> &str {
+        self.response.as_deref().unwrap_or("")
+    }
+
+    // Replaces the text, putting the cursor at its end
+    pub fn set_text(&mut self, text: &str) {
+        self.cx = text.len();
+        self.response = Some(text.to_string());
+    }
+
+    // The cursor's position within the area last rendered in
     pub fn get_cursor(&self) -> (u16, u16) {
-        (self.cx as u16 + 1, 1) // +1 for > character and 1 for top border
+        let col = self.get_text()[..self.cx]
+            .width()
+            .saturating_sub(self.offset);
+        (col as u16 + 1, 1) // +1 for > character and 1 for top border
     }
 
     pub fn take_answer(&mut self) -> Option<String> {
         self.cx = 0;
+        self.offset = 0;
         self.response.take()
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn render(prompt: &mut Prompt, width: u16) {
+        let area = Rect::new(0, 0, width, 2);
+        prompt.render(area, &mut Buffer::empty(area));
+    }
+
+    #[test]
+    fn inserts_in_the_middle() {
+        let mut prompt = Prompt::new(Some("ac".to_string()));
+        prompt.move_cursor(-1);
+        prompt.add_char('b');
+        prompt.add_char('é');
+        assert_eq!(prompt.get_text(), "abéc");
+        prompt.move_cursor(1);
+        prompt.add_char('d');
+        assert_eq!(prompt.get_text(), "abécd");
+    }
+
+    #[test]
+    fn deletes_whole_graphemes() {
+        let mut prompt = Prompt::new(Some("aé日".to_string()));
+        prompt.backspace();
+        prompt.backspace();
+        assert_eq!(prompt.get_text(), "a");
+
+        let mut prompt = Prompt::new(Some("ae\u{301}x".to_string()));
+        prompt.move_cursor(-1);
+        prompt.backspace();
+        assert_eq!(prompt.get_text(), "ax");
+
+        let mut prompt = Prompt::new(Some("e\u{301}x".to_string()));
+        prompt.move_home();
+        prompt.delete_char();
+        assert_eq!(prompt.get_text(), "x");
+    }
+
+    #[test]
+    fn combining_characters_join_the_grapheme_before() {
+        let mut prompt = Prompt::new(Some("ex".to_string()));
+        prompt.move_cursor(-1);
+        prompt.add_char('\u{301}');
+        prompt.add_char('y');
+        assert_eq!(prompt.get_text(), "e\u{301}yx");
+    }
+
+    #[test]
+    fn moves_and_deletes_by_word() {
+        let mut prompt = Prompt::new(Some("open my file.txt".to_string()));
+        prompt.delete_word_left();
+        assert_eq!(prompt.get_text(), "open my file.");
+        prompt.move_word(false);
+        prompt.move_word(false);
+        prompt.delete_word_left();
+        assert_eq!(prompt.get_text(), "open file.");
+        prompt.move_home();
+        prompt.move_word(true);
+        prompt.add_char('!');
+        assert_eq!(prompt.get_text(), "open! file.");
+        prompt.move_end();
+        prompt.move_word(true);
+        prompt.delete_word_left();
+        assert_eq!(prompt.get_text(), "open! file");
+    }
+
+    #[test]
+    fn cursor_with_wide_characters_scrolled() {
+        let mut prompt = Prompt::new(Some("日本語テキスト".to_string()));
+        // Five columns of text fit after the > character
+        render(&mut prompt, 6);
+        assert_eq!(prompt.get_cursor(), (5, 1));
+        prompt.move_cursor(-1);
+        render(&mut prompt, 6);
+        assert_eq!(prompt.get_cursor(), (3, 1));
+        prompt.move_home();
+        render(&mut prompt, 6);
+        assert_eq!(prompt.get_cursor(), (1, 1));
+        // Past the right edge the text scrolls so the cursor is in the last column
+        prompt.move_cursor(3);
+        render(&mut prompt, 6);
+        assert_eq!(prompt.get_cursor(), (5, 1));
+    }
+}
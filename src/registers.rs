@@ -0,0 +1,90 @@
+use std::collections::BTreeMap;
+
+use crate::clip::Clip;
+use crate::kill_ring::KillRing;
+
+// Copied and cut text shared by every editor: the kill ring of recent copies and cuts, which are
+// also numbered from `1` for the newest, and registers `a` to `z` kept until replaced
+pub struct Registers {
+    kill_ring: KillRing,
+    named: BTreeMap<char, Clip>,
+}
+
+// Whether a character names a register, with capitals appending to the lowercase one
+pub fn is_register(name: char) -> bool {
+    name.is_ascii_alphanumeric() && name != '0'
+}
+
+impl Registers {
+    pub fn new(history_size: usize) -> Self {
+        Registers {
+            kill_ring: KillRing::new(history_size),
+            named: BTreeMap::new(),
+        }
+    }
+
+    pub fn set_history_size(&mut self, size: usize) {
+        self.kill_ring.set_size(size);
+    }
+
+    // Keeps a copy or cut in the kill ring and in the named register if there is one
+    pub fn store(&mut self, name: Option<char>, clip: Clip) {
+        match name {
+            Some(name) if name.is_ascii_lowercase() => {
+                self.named.insert(name, clip.clone());
+            }
+            Some(name) if name.is_ascii_uppercase() => {
+                let name = name.to_ascii_lowercase();
+                match self.named.get_mut(&name) {
+                    Some(named) => named.append(&clip),
+                    None => {
+                        self.named.insert(name, clip.clone());
+                    }
+                }
+            }
+            _ => {}
+        }
+        self.kill_ring.push(clip);
+    }
+
+    // Joins a kill onto the newest one, as consecutive kills do
+    pub fn append(&mut self, clip: Clip) {
+        self.kill_ring.append(clip);
+    }
+
+    // The text a register holds, or the kill ring's current entry without one
+    pub fn get(&self, name: Option<char>) -> Option<&Clip> {
+        match name {
+            Some(name) if name.is_ascii_digit() => {
+                self.get_recent(name.to_digit(10).unwrap() as usize)
+            }
+            Some(name) if name.is_ascii_alphabetic() => self.named.get(&name.to_ascii_lowercase()),
+            _ => self.kill_ring.get(),
+        }
+    }
+
+    // The `n`th most recent copy or cut, counting from 1
+    pub fn get_recent(&self, n: usize) -> Option<&Clip> {
+        self.kill_ring.get_recent().nth(n.checked_sub(1)?)
+    }
+
+    // Moves the kill ring back to the next older entry for yank-pop
+    pub fn rotate(&mut self) -> Option<&Clip> {
+        self.kill_ring.rotate()
+    }
+
+    // Every entry as (name, text): the named registers and then the kill ring from newest to
+    // oldest, numbered from 1
+    pub fn get_entries(&self) -> Vec<(String, &Clip)> {
+        let named = self
+            .named
+            .iter()
+            .map(|(name, clip)| (name.to_string(), clip));
+        let recent = self
+            .kill_ring
+            .get_recent()
+            .enumerate()
+            .map(|(i, clip)| ((i + 1).to_string(), clip));
+        named.chain(recent).collect()
+    }
+}
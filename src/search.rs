@@ -0,0 +1,102 @@
+use crate::editor::{Editor, Movement};
+
+// An incremental search that moves to the closest match as the query is typed, selecting it
+pub struct Search {
+    forward: bool,
+    // Where the search started, to go back to when it's cancelled
+    origin: (usize, usize),
+    query: String,
+    // The start of the selected match
+    found: Option<(usize, usize)>,
+    failing: bool,
+}
+
+impl Search {
+    pub fn new(e: &Editor, forward: bool) -> Self {
+        Search {
+            forward,
+            origin: e.get_cursor(),
+            query: String::new(),
+            found: None,
+            failing: false,
+        }
+    }
+
+    pub fn get_query(&self) -> &str {
+        &self.query
+    }
+
+    // Describes the search for the message bar, like `Failing I-search backward: foo`
+    pub fn get_status(&self) -> String {
+        format!(
+            "{}I-search{}: {}",
+            if self.failing { "Failing " } else { "" },
+            if self.forward { "" } else { " backward" },
+            self.query
+        )
+    }
+
+    // Extends the query, keeping the current match if it still matches
+    pub fn add_char(&mut self, c: char, e: &mut Editor) {
+        self.query.push(c);
+        self.find(e, self.found.unwrap_or(self.origin));
+    }
+
+    // Shortens the query and searches again from where the search started
+    pub fn backspace(&mut self, e: &mut Editor) {
+        self.query.pop();
+        self.found = None;
+        self.failing = false;
+        if self.query.is_empty() {
+            e.move_cursor(Movement::Absolute(self.origin.0, self.origin.1), false);
+        } else {
+            self.find(e, self.origin);
+        }
+    }
+
+    // Moves to the next match in a direction, reusing the last query when nothing is typed yet
+    pub fn next(&mut self, e: &mut Editor, forward: bool, last_query: &str) {
+        if self.query.is_empty() {
+            self.query = last_query.to_string();
+        }
+        self.forward = forward;
+        let from = match self.found {
+            Some((x, y)) if forward => (x + 1, y),
+            Some((0, y)) => {
+                let y = y.checked_sub(1).unwrap_or(e.get_line_count() - 1);
+                (usize::MAX, y)
+            }
+            Some((x, y)) => (x - 1, y),
+            None => self.origin,
+        };
+        self.find(e, from);
+    }
+
+    // Goes back to where the search started
+    pub fn cancel(&self, e: &mut Editor) {
+        e.move_cursor(Movement::Absolute(self.origin.0, self.origin.1), false);
+    }
+
+    // Leaves the cursor on the match
+    pub fn finish(&self, e: &mut Editor) {
+        e.clear_selection();
+    }
+
+    // Selects the closest match from `from`, with the cursor after it searching forward and
+    // before it searching backward
+    fn find(&mut self, e: &mut Editor, from: (usize, usize)) {
+        match e.find(&self.query, from, self.forward) {
+            Some(start) => {
+                let end = (start.0 + self.query.len(), start.1);
+                if self.forward {
+                    e.select_region(start, end);
+                } else {
+                    e.select_region(end, start);
+                }
+                self.found = Some(start);
+                self.failing = false;
+            }
+            None => self.failing = true,
+        }
+    }
+}
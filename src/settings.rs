@@ -0,0 +1,263 @@
+use std::collections::BTreeMap;
+use std::path::{Path, PathBuf};
+use std::time::SystemTime;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::fold::FoldMethod;
+use crate::indentation::{IndentOverrides, IndentStyle};
+use crate::keymap::KeyBindings;
+use crate::render_config::RenderConfig;
+use crate::syntax::SyntaxOverrides;
+use crate::wrap::{WrapMode, WrapOverrides};
+
+// The settings.toml format this build reads, bumped when a setting changes meaning
+pub const SETTINGS_VERSION: u32 = 1;
+pub const DEFAULT_THEME: &str = "Solarized (dark)";
+
+// How many invalid lines are skipped before giving up on the settings file
+const MAX_ERRORS: usize = 20;
+
+// Where copied and cut text goes, set with `clipboard = "internal"` or `clipboard = "system"`
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum ClipboardMode {
+    #[default]
+    Internal,
+    // Also copies to the system clipboard through the terminal (OSC 52)
+    System,
+}
+
+// How keys edit text, set with `profile = "vim"` for modal editing or `profile = "emacs"`
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Profile {
+    #[default]
+    Default,
+    Vim,
+    // Emacs key bindings on top of the defaults
+    Emacs,
+}
+
+// Everything settings.toml can set, each falling back to its default when left out
+#[derive(Clone, Debug, Deserialize, Serialize)]
+#[serde(default)]
+pub struct Settings {
+    pub version: u32,
+    pub theme: String,
+    pub tab_size: usize,
+    pub indent_style: IndentStyle,
+    pub line_numbers: bool,
+    pub soft_wrap: WrapMode,
+    pub scrolloff: usize,
+    pub rulers: Vec<usize>,
+    // Seconds without input before modified files are saved, or 0 to never save automatically
+    pub autosave: u64,
+    pub mouse: bool,
+    pub clipboard: ClipboardMode,
+    // How many copies and cuts are kept to paste again
+    pub clipboard_history: usize,
+    pub profile: Profile,
+    // Reloads the settings, themes and syntaxes when files in the config folder change
+    pub watch_config: bool,
+    pub fold: FoldMethod,
+    pub indent: IndentOverrides,
+    pub wrap: WrapOverrides,
+    pub syntax: SyntaxOverrides,
+    pub keys: KeyBindings,
+}
+
+impl Default for Settings {
+    fn default() -> Self {
+        Settings {
+            version: SETTINGS_VERSION,
+            theme: DEFAULT_THEME.to_string(),
+            tab_size: RenderConfig::default().tab_size,
+            indent_style: IndentStyle::default(),
+            line_numbers: true,
+            soft_wrap: WrapMode::default(),
+            scrolloff: 0,
+            rulers: vec![],
+            autosave: 0,
+            mouse: true,
+            clipboard: ClipboardMode::default(),
+            clipboard_history: 60,
+            profile: Profile::default(),
+            watch_config: false,
+            fold: FoldMethod::default(),
+            indent: IndentOverrides::new(),
+            wrap: WrapOverrides::new(),
+            syntax: SyntaxOverrides::new(),
+            keys: KeyBindings::new(),
+        }
+    }
+}
+
+impl Settings {
+    // Reads a settings file, returning the defaults for anything missing or invalid along with
+    // a description of each problem
+    pub fn load(path: &Path) -> (Settings, Vec<String>) {
+        match std::fs::read_to_string(path) {
+            Ok(contents) => Settings::parse(&contents),
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => (Settings::default(), vec![]),
+            Err(e) => (
+                Settings::default(),
+                vec![format!("Couldn't read settings: {}", e)],
+            ),
+        }
+    }
+
+    // Skips the line of each error in turn so one bad value doesn't lose the rest
+    pub fn parse(contents: &str) -> (Settings, Vec<String>) {
+        let mut lines: Vec<&str> = contents.lines().collect();
+        let mut errors = vec![];
+        let mut settings = loop {
+            match toml::from_str::<Settings>(&lines.join("\n")) {
+                Ok(settings) => break settings,
+                Err(e) => {
+                    errors.push(format!("Invalid setting: {}", e));
+                    match e.line_col() {
+                        Some((line, _))
+                            if line < lines.len()
+                                && !lines[line].is_empty()
+                                && errors.len() < MAX_ERRORS =>
+                        {
+                            lines[line] = "";
+                        }
+                        _ => break Settings::default(),
+                    }
+                }
+            }
+        };
+        settings.validate(&mut errors);
+
+        // Settings that don't exist are most likely misspelled
+        let known = toml::Value::try_from(Settings::default()).ok();
+        let parsed = toml::from_str::<toml::Value>(&lines.join("\n")).ok();
+        if let (Some(toml::Value::Table(known)), Some(toml::Value::Table(parsed))) = (known, parsed)
+        {
+            for key in parsed.keys().filter(|k| !known.contains_key(*k)) {
+                let line = lines.iter().position(|l| {
+                    l.trim_start()
+                        .strip_prefix(key.as_str())
+                        .is_some_and(|rest| rest.trim_start().starts_with('='))
+                });
+                match line {
+                    Some(line) => {
+                        errors.push(format!("Unknown setting `{}` at line {}", key, line + 1))
+                    }
+                    None => errors.push(format!("Unknown setting `{}`", key)),
+                }
+            }
+        }
+        (settings, errors)
+    }
+
+    fn validate(&mut self, errors: &mut Vec<String>) {
+        let defaults = Settings::default();
+        if self.version > SETTINGS_VERSION {
+            errors.push(format!(
+                "Settings are version {} but only version {} is supported",
+                self.version, SETTINGS_VERSION
+            ));
+        }
+        if self.tab_size == 0 {
+            errors.push("tab_size must be at least 1".to_string());
+            self.tab_size = defaults.tab_size;
+        }
+        if self.clipboard_history == 0 {
+            errors.push("clipboard_history must be at least 1".to_string());
+            self.clipboard_history = defaults.clipboard_history;
+        }
+        if self.indent_style == IndentStyle::Spaces(0) {
+            errors.push("indent_style width must be at least 1".to_string());
+            self.indent_style = defaults.indent_style;
+        }
+        let mut zero_widths: Vec<String> = self
+            .indent
+            .iter()
+            .filter(|(_, style)| **style == IndentStyle::Spaces(0))
+            .map(|(extension, _)| extension.clone())
+            .collect();
+        zero_widths.sort();
+        for extension in zero_widths {
+            errors.push(format!("indent width for {} must be at least 1", extension));
+            self.indent.remove(&extension);
+        }
+    }
+
+    // Falls back to the default theme when the chosen one isn't loaded
+    pub fn validate_theme<T>(&mut self, themes: &BTreeMap<String, T>) -> Option<String> {
+        if themes.contains_key(&self.theme) {
+            None
+        } else {
+            let warning = format!("Unknown theme {}, using {}", self.theme, DEFAULT_THEME);
+            self.theme = DEFAULT_THEME.to_string();
+            Some(warning)
+        }
+    }
+}
+
+// Sets the top level `theme` key of a settings file, keeping the rest of it as written
+pub fn save_theme(path: &Path, name: &str) -> std::io::Result<()> {
+    let contents = std::fs::read_to_string(path).unwrap_or_default();
+    let setting = format!("theme = {}", toml::Value::String(name.to_string()));
+    let mut lines: Vec<String> = contents.lines().map(|l| l.to_string()).collect();
+    let top_level = lines
+        .iter()
+        .position(|l| l.trim_start().starts_with('['))
+        .unwrap_or(lines.len());
+    let existing = lines[..top_level].iter().position(|l| {
+        let l = l.trim_start();
+        l.strip_prefix("theme")
+            .is_some_and(|rest| rest.trim_start().starts_with('='))
+    });
+    match existing {
+        Some(y) => lines[y] = setting,
+        None => lines.insert(0, setting),
+    }
+    if let Some(dir) = path.parent() {
+        std::fs::create_dir_all(dir)?;
+    }
+    std::fs::write(path, lines.join("\n") + "\n")
+}
+
+// The files of the config folder with when they were last modified, to notice changes
+pub fn get_config_stamp(dir: &Path) -> Vec<(PathBuf, Option<SystemTime>)> {
+    let mut files = vec![];
+    let mut dirs = vec![dir.to_path_buf()];
+    while let Some(dir) = dirs.pop() {
+        let entries = match std::fs::read_dir(&dir) {
+            Ok(entries) => entries,
+            Err(_) => continue,
+        };
+        for entry in entries.flatten() {
+            let path = entry.path();
+            let metadata = entry.metadata().ok();
+            if metadata.as_ref().is_some_and(|m| m.is_dir()) {
+                dirs.push(path);
+            } else {
+                files.push((path, metadata.and_then(|m| m.modified().ok())));
+            }
+        }
+    }
+    files.sort();
+    files
+}
+
+// Whether any file under `dir` was added, removed or modified between two stamps of the config
+// folder
+pub fn folder_changed(
+    old: &[(PathBuf, Option<SystemTime>)],
+    new: &[(PathBuf, Option<SystemTime>)],
+    dir: &Path,
+) -> bool {
+    let in_dir = |stamp: &[(PathBuf, Option<SystemTime>)]| {
+        stamp
+            .iter()
+            .filter(|(path, _)| path.starts_with(dir))
+            .cloned()
+            .collect::<Vec<_>>()
+    };
+    in_dir(old) != in_dir(new)
+}
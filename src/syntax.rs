@@ -0,0 +1,130 @@
+use std::collections::HashMap;
+use std::path::Path;
+
+use syntect::parsing::{SyntaxReference, SyntaxSet};
+
+use crate::glob::glob_match;
+use crate::line::Line;
+
+// Maps file name globs (or path globs when they contain a `/`) to syntax names
+pub type SyntaxOverrides = HashMap<String, String>;
+
+// How many lines at the start and end of a file are searched for a modeline
+const MODELINE_LINES: usize = 5;
+
+// Picks a syntax from the user's glob mappings, a modeline, the file name, the extension and
+// finally the first line (such as a shebang), in that order
+pub fn detect_syntax<'a>(
+    syntaxes: &'a SyntaxSet,
+    path: Option<&Path>,
+    lines: &[Line],
+    overrides: &SyntaxOverrides,
+) -> Option<&'a SyntaxReference> {
+    let name = path
+        .and_then(|p| p.file_name())
+        .map(|n| n.to_string_lossy().to_string());
+
+    if let Some(path) = path {
+        let full = path.to_string_lossy().replace('\\', "/");
+        // The longest matching pattern is taken as the most specific
+        let mut patterns: Vec<(&String, &String)> = overrides.iter().collect();
+        patterns.sort_by(|a, b| b.0.len().cmp(&a.0.len()).then(a.0.cmp(b.0)));
+        for (pattern, syntax) in patterns {
+            let matches = if pattern.contains('/') {
+                glob_match(&format!("**/{}", pattern.trim_start_matches('/')), &full)
+            } else {
+                name.as_ref().is_some_and(|n| glob_match(pattern, n))
+            };
+            if matches {
+                if let Some(syntax) = find_syntax(syntaxes, syntax) {
+                    return Some(syntax);
+                }
+            }
+        }
+    }
+
+    let start = lines.iter().take(MODELINE_LINES);
+    let end = lines.iter().skip(MODELINE_LINES).rev().take(MODELINE_LINES);
+    if let Some(syntax) = start
+        .chain(end)
+        .find_map(|l| parse_modeline(&l.get_clean_raw()))
+        .and_then(|mode| find_syntax(syntaxes, &mode))
+    {
+        return Some(syntax);
+    }
+
+    if let Some(syntax) = name
+        .as_ref()
+        .and_then(|n| syntaxes.find_syntax_by_extension(n))
+    {
+        return Some(syntax);
+    }
+    if let Some(syntax) = path
+        .and_then(|p| p.extension())
+        .and_then(|e| syntaxes.find_syntax_by_extension(&e.to_string_lossy()))
+    {
+        return Some(syntax);
+    }
+
+    let first = lines.first()?.get_clean_raw();
+    syntaxes
+        .find_syntax_by_first_line(&first)
+        .or_else(|| parse_shebang(&first).and_then(|i| find_syntax(syntaxes, &i)))
+}
+
+// Finds a syntax by its exact name, then by extension or case-insensitive name
+pub fn find_syntax<'a>(syntaxes: &'a SyntaxSet, name: &str) -> Option<&'a SyntaxReference> {
+    syntaxes
+        .find_syntax_by_name(name)
+        .or_else(|| syntaxes.find_syntax_by_token(name))
+}
+
+// Reads the file type from a Vim (`vim: set ft=python:`) or Emacs (`-*- mode: python -*-`)
+// modeline
+fn parse_modeline(line: &str) -> Option<String> {
+    if let Some(start) = line.find("-*-") {
+        let rest = &line[start + 3..];
+        let inner = &rest[..rest.find("-*-")?];
+        let mode = if inner.contains(':') {
+            inner.split(';').find_map(|v| {
+                let (key, value) = v.split_once(':')?;
+                if key.trim().eq_ignore_ascii_case("mode") {
+                    Some(value.trim())
+                } else {
+                    None
+                }
+            })?
+        } else {
+            inner.trim()
+        };
+        return Some(mode.to_string()).filter(|m| !m.is_empty());
+    }
+
+    let start = ["vim:", "vi:", "ex:"].iter().find_map(|marker| {
+        line.match_indices(marker)
+            .find(|(i, _)| *i == 0 || line[..*i].ends_with(|c: char| c.is_whitespace()))
+            .map(|(i, _)| i + marker.len())
+    })?;
+    line[start..]
+        .split(|c: char| c.is_whitespace() || c == ':')
+        .find_map(|option| {
+            let (key, value) = option.split_once('=')?;
+            match key {
+                "ft" | "filetype" | "syn" | "syntax" => Some(value.to_string()),
+                _ => None,
+            }
+        })
+        .filter(|m| !m.is_empty())
+}
+
+// Returns the interpreter of a shebang without any version, for example `python` for
+// `#!/usr/bin/env python3`
+fn parse_shebang(line: &str) -> Option<String> {
+    let mut words = line.strip_prefix("#!")?.split_whitespace();
+    let mut interpreter = words.next()?.rsplit('/').next()?;
+    if interpreter == "env" {
+        interpreter = words.find(|w| !w.starts_with('-') && !w.contains('='))?;
+    }
+    let interpreter = interpreter.trim_end_matches(|c: char| c.is_ascii_digit() || c == '.');
+    Some(interpreter.to_string()).filter(|i| !i.is_empty())
+}
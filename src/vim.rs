@@ -0,0 +1,1109 @@
+use std::cmp::{max, min};
+use std::fmt;
+
+use crossterm::event::{KeyCode, KeyModifiers};
+
+use crate::editor::{Editor, Movement};
+use crate::keymap::KeyChord;
+use crate::registers::{is_register, Registers};
+use crate::word::{char_class, CharClass};
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Mode {
+    Normal,
+    Insert,
+    Visual,
+    VisualLine,
+}
+
+impl fmt::Display for Mode {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            Mode::Normal => "NORMAL",
+            Mode::Insert => "INSERT",
+            Mode::Visual => "VISUAL",
+            Mode::VisualLine => "VISUAL LINE",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+pub enum VimResult {
+    Handled,
+    // Text was copied to the clipboard
+    Yanked,
+    // The key should go through the key bindings, as it does in insert mode
+    Unhandled,
+    // A command for the palette to run, such as opening it for `:`
+    Command(String),
+    // Keys to handle again a number of times to repeat the last change with `.`
+    Repeat(Vec<KeyChord>, usize),
+}
+
+// How much text a motion covers when an operator uses it
+#[derive(Clone, Copy, PartialEq)]
+enum Extent {
+    // Up to but not including the target
+    Exclusive,
+    // Including the character at the target
+    Inclusive,
+    // Every line from the cursor to the target
+    Linewise,
+}
+
+type Target = (usize, usize, Extent);
+
+// A normal mode command such as `3dw`, `ci(` or `"ayy`
+struct Command {
+    register: Option<char>,
+    count: Option<usize>,
+    operator: Option<char>,
+    key: char,
+    // The character after `f`, `t`, `g` or a text object's `i` or `a`
+    arg: Option<char>,
+}
+
+enum Parse {
+    Incomplete,
+    Invalid,
+    Complete(Command),
+}
+
+const OPERATORS: &str = "dcy<>";
+// Larger counts are cut down, so a mistyped one can't overflow or repeat a command for ever
+const MAX_COUNT: usize = 9_999_999;
+
+fn parse_count(keys: &[char]) -> (Option<usize>, usize) {
+    // A leading 0 is the motion to the start of the line rather than a count
+    let digits = match keys.first() {
+        Some('0') => 0,
+        _ => keys.iter().take_while(|c| c.is_ascii_digit()).count(),
+    };
+    let count = keys[..digits].iter().fold(None, |count: Option<usize>, c| {
+        let digit = c.to_digit(10).unwrap() as usize;
+        let count = count.unwrap_or(0).saturating_mul(10).saturating_add(digit);
+        Some(min(count, MAX_COUNT))
+    });
+    (count, digits)
+}
+
+// Parses `["register][count][operator[count]]key[arg]`, with operators acting straight away on
+// the selection in visual mode
+fn parse(keys: &[char], visual: bool) -> Parse {
+    let (register, start) = match (keys.first(), keys.get(1)) {
+        (Some('"'), None) => return Parse::Incomplete,
+        (Some('"'), Some('"')) => (None, 2),
+        (Some('"'), Some(name)) if is_register(*name) => (Some(*name), 2),
+        (Some('"'), Some(_)) => return Parse::Invalid,
+        _ => (None, 0),
+    };
+    let (count, digits) = parse_count(&keys[start..]);
+    let mut i = start + digits;
+    let mut operator = None;
+    let mut operator_count = None;
+    if i < keys.len() && !visual && OPERATORS.contains(keys[i]) {
+        operator = Some(keys[i]);
+        let (count, digits) = parse_count(&keys[i + 1..]);
+        operator_count = count;
+        i += 1 + digits;
+    }
+    let key = match keys.get(i) {
+        Some(key) => *key,
+        None => return Parse::Incomplete,
+    };
+    let takes_arg = match key {
+        'f' | 't' | 'F' | 'T' | 'g' => true,
+        'i' | 'a' => operator.is_some() && operator != Some(key),
+        _ => false,
+    };
+    let arg = if takes_arg {
+        match keys.get(i + 1) {
+            Some(arg) => Some(*arg),
+            None => return Parse::Incomplete,
+        }
+    } else {
+        None
+    };
+    if key == 'g' && arg != Some('g') {
+        return Parse::Invalid;
+    }
+    let count = match (count, operator_count) {
+        (None, None) => None,
+        (a, b) => Some(min(
+            a.unwrap_or(1).saturating_mul(b.unwrap_or(1)),
+            MAX_COUNT,
+        )),
+    };
+    Parse::Complete(Command {
+        register,
+        count,
+        operator,
+        key,
+        arg,
+    })
+}
+
+// The text of a line without its line break
+fn get_text(e: &Editor, y: usize) -> &str {
+    e.get_line(y)
+        .map_or("", |l| l.get_raw().trim_end_matches(&['\r', '\n'][..]))
+}
+
+// The last line, leaving out the empty line after a final line break
+fn get_last_line(e: &Editor) -> usize {
+    match e.get_line_count() - 1 {
+        0 => 0,
+        last if get_text(e, last).is_empty() => last - 1,
+        last => last,
+    }
+}
+
+fn next_char(text: &str, x: usize) -> usize {
+    text[min(x, text.len())..]
+        .chars()
+        .next()
+        .map_or(text.len(), |c| x + c.len_utf8())
+}
+
+fn prev_char(text: &str, x: usize) -> usize {
+    text[..min(x, text.len())]
+        .char_indices()
+        .last()
+        .map_or(0, |(i, _)| i)
+}
+
+fn get_indent_len(text: &str) -> usize {
+    text.len() - text.trim_start().len()
+}
+
+// Line breaks count as whitespace, and with `big` every other character is part of a word
+fn get_class(c: char, big: bool) -> CharClass {
+    match char_class(c) {
+        CharClass::Whitespace => CharClass::Whitespace,
+        _ if big => CharClass::Word,
+        class => class,
+    }
+}
+
+// The character at a position, with the end of each line but the last being its line break
+fn char_at(e: &Editor, (x, y): (usize, usize)) -> char {
+    get_text(e, y)[min(x, get_text(e, y).len())..]
+        .chars()
+        .next()
+        .unwrap_or('\n')
+}
+
+fn is_empty_line(e: &Editor, (x, y): (usize, usize)) -> bool {
+    x == 0 && get_text(e, y).is_empty()
+}
+
+fn step_forward(e: &Editor, (x, y): (usize, usize)) -> Option<(usize, usize)> {
+    let text = get_text(e, y);
+    if x < text.len() {
+        Some((next_char(text, x), y))
+    } else if y + 1 < e.get_line_count() {
+        Some((0, y + 1))
+    } else {
+        None
+    }
+}
+
+fn step_back(e: &Editor, (x, y): (usize, usize)) -> Option<(usize, usize)> {
+    if x > 0 {
+        Some((prev_char(get_text(e, y), x), y))
+    } else if y > 0 {
+        Some((get_text(e, y - 1).len(), y - 1))
+    } else {
+        None
+    }
+}
+
+// The start of the next word, stopping at empty lines
+fn word_forward(e: &Editor, from: (usize, usize), big: bool) -> (usize, usize) {
+    let class = get_class(char_at(e, from), big);
+    let mut pos = from;
+    loop {
+        let next = match step_forward(e, pos) {
+            Some(next) => next,
+            None => {
+                let y = e.get_line_count() - 1;
+                return (get_text(e, y).len(), y);
+            }
+        };
+        pos = next;
+        let c = get_class(char_at(e, pos), big);
+        if is_empty_line(e, pos) || (c != CharClass::Whitespace && c != class) {
+            return pos;
+        }
+        if c == CharClass::Whitespace {
+            // Past this word, so the next one of any class is the target
+            return match skip_whitespace(e, pos) {
+                Some(pos) => pos,
+                None => {
+                    let y = e.get_line_count() - 1;
+                    (get_text(e, y).len(), y)
+                }
+            };
+        }
+    }
+}
+
+fn skip_whitespace(e: &Editor, from: (usize, usize)) -> Option<(usize, usize)> {
+    let mut pos = from;
+    while get_class(char_at(e, pos), false) == CharClass::Whitespace {
+        pos = step_forward(e, pos)?;
+        if is_empty_line(e, pos) {
+            break;
+        }
+    }
+    Some(pos)
+}
+
+// The last character of the current or next word
+fn word_end(e: &Editor, from: (usize, usize), big: bool) -> (usize, usize) {
+    let mut pos = match step_forward(e, from) {
+        Some(pos) => pos,
+        None => return from,
+    };
+    while get_class(char_at(e, pos), big) == CharClass::Whitespace {
+        pos = match step_forward(e, pos) {
+            Some(pos) => pos,
+            None => return from,
+        };
+    }
+    let class = get_class(char_at(e, pos), big);
+    while let Some(next) = step_forward(e, pos) {
+        if next.1 != pos.1 || get_class(char_at(e, next), big) != class {
+            break;
+        }
+        pos = next;
+    }
+    pos
+}
+
+// The start of the current or previous word, stopping at empty lines
+fn word_back(e: &Editor, from: (usize, usize), big: bool) -> (usize, usize) {
+    let mut pos = match step_back(e, from) {
+        Some(pos) => pos,
+        None => return from,
+    };
+    while get_class(char_at(e, pos), big) == CharClass::Whitespace && !is_empty_line(e, pos) {
+        pos = match step_back(e, pos) {
+            Some(pos) => pos,
+            None => return pos,
+        };
+    }
+    let class = get_class(char_at(e, pos), big);
+    while let Some(prev) = step_back(e, pos) {
+        if prev.1 != pos.1 || get_class(char_at(e, prev), big) != class {
+            break;
+        }
+        pos = prev;
+    }
+    pos
+}
+
+// Finds the `count`th `c` after (or before) `x` on a line
+fn find_char(text: &str, x: usize, c: char, count: usize, forward: bool) -> Option<usize> {
+    let found = if forward {
+        text.char_indices()
+            .filter(|(i, ch)| *i > x && *ch == c)
+            .nth(count - 1)
+    } else {
+        text.char_indices()
+            .rev()
+            .filter(|(i, ch)| *i < x && *ch == c)
+            .nth(count - 1)
+    };
+    found.map(|(i, _)| i)
+}
+
+// Where a motion key moves the cursor to, and how much an operator using it covers
+fn get_target(e: &Editor, key: char, arg: Option<char>, count: Option<usize>) -> Option<Target> {
+    let line = count;
+    let count = count.unwrap_or(1);
+    let (x, y) = e.get_cursor();
+    let text = get_text(e, y);
+    let last = e.get_line_count() - 1;
+    let repeat =
+        |f: &dyn Fn((usize, usize)) -> (usize, usize)| (0..count).fold((x, y), |pos, _| f(pos));
+    Some(match key {
+        'h' => {
+            let x = (0..count).fold(x, |x, _| prev_char(text, x));
+            (x, y, Extent::Exclusive)
+        }
+        'l' | ' ' => {
+            let x = (0..count).fold(x, |x, _| next_char(text, x));
+            (x, y, Extent::Exclusive)
+        }
+        'j' => (x, min(y.saturating_add(count), last), Extent::Linewise),
+        'k' => (x, y.saturating_sub(count), Extent::Linewise),
+        '0' => (0, y, Extent::Exclusive),
+        '^' => (get_indent_len(text), y, Extent::Exclusive),
+        '$' => {
+            let y = min(y.saturating_add(count - 1), last);
+            (get_text(e, y).len(), y, Extent::Exclusive)
+        }
+        'w' | 'W' => {
+            let (x, y) = repeat(&|pos| word_forward(e, pos, key == 'W'));
+            (x, y, Extent::Exclusive)
+        }
+        'b' | 'B' => {
+            let (x, y) = repeat(&|pos| word_back(e, pos, key == 'B'));
+            (x, y, Extent::Exclusive)
+        }
+        'e' | 'E' => {
+            let (x, y) = repeat(&|pos| word_end(e, pos, key == 'E'));
+            (x, y, Extent::Inclusive)
+        }
+        // `G` goes to the last line and `gg` to the first, or both to the line given as a count
+        'G' | 'g' => {
+            let last = get_last_line(e);
+            let y = match line {
+                Some(line) => min(max(line, 1), last + 1) - 1,
+                None if key == 'G' => last,
+                None => 0,
+            };
+            (get_indent_len(get_text(e, y)), y, Extent::Linewise)
+        }
+        'f' | 't' | 'F' | 'T' => {
+            let forward = key == 'f' || key == 't';
+            let found = find_char(text, x, arg?, count, forward)?;
+            match key {
+                'f' => (found, y, Extent::Inclusive),
+                't' => (prev_char(text, found), y, Extent::Inclusive),
+                'F' => (found, y, Extent::Exclusive),
+                _ => (next_char(text, found), y, Extent::Exclusive),
+            }
+        }
+        _ => return None,
+    })
+}
+
+// The region of a text object such as `iw`, `a(` or `i"`, end exclusive
+fn get_text_object(
+    e: &Editor,
+    around: bool,
+    kind: char,
+) -> Option<((usize, usize), (usize, usize))> {
+    let (x, y) = e.get_cursor();
+    let text = get_text(e, y);
+    match kind {
+        'w' | 'W' => {
+            let big = kind == 'W';
+            let chars: Vec<(usize, char)> = text.char_indices().collect();
+            let i = chars.iter().position(|(b, _)| *b >= x)?;
+            let class = get_class(chars[i].1, big);
+            let same = |j: &usize| get_class(chars[*j].1, big) == class;
+            let mut start = i;
+            while start > 0 && same(&(start - 1)) {
+                start -= 1;
+            }
+            let mut end = i + 1;
+            while end < chars.len() && same(&end) {
+                end += 1;
+            }
+            let byte = |j: usize| chars.get(j).map_or(text.len(), |c| c.0);
+            if around {
+                // Take the whitespace after the word, or before it when there's none after
+                let is_space = |j: &usize| chars[*j].1.is_whitespace();
+                let mut after = end;
+                while after < chars.len() && is_space(&after) {
+                    after += 1;
+                }
+                if after > end {
+                    end = after;
+                } else {
+                    while start > 0 && is_space(&(start - 1)) {
+                        start -= 1;
+                    }
+                }
+            }
+            Some(((byte(start), y), (byte(end), y)))
+        }
+        '"' | '\'' | '`' => {
+            let quotes: Vec<usize> = text
+                .char_indices()
+                .filter(|(i, c)| *c == kind && !text[..*i].ends_with('\\'))
+                .map(|(i, _)| i)
+                .collect();
+            let (start, end) = quotes
+                .chunks(2)
+                .filter(|pair| pair.len() == 2)
+                .map(|pair| (pair[0], pair[1]))
+                .find(|(_, end)| *end >= x)?;
+            if around {
+                Some(((start, y), (end + 1, y)))
+            } else {
+                Some(((start + 1, y), (end, y)))
+            }
+        }
+        _ => {
+            let (open, close) = match kind {
+                '(' | ')' | 'b' => ('(', ')'),
+                '[' | ']' => ('[', ']'),
+                '{' | '}' | 'B' => ('{', '}'),
+                '<' | '>' => ('<', '>'),
+                _ => return None,
+            };
+            let start = find_unmatched(e, (x, y), open, close, false)?;
+            let end = find_unmatched(e, step_forward(e, start)?, close, open, true)?;
+            if around {
+                Some((start, step_forward(e, end).unwrap_or((end.0 + 1, end.1))))
+            } else {
+                Some((step_forward(e, start)?, end))
+            }
+        }
+    }
+}
+
+// Finds the first `c` from `from` (inclusive) that isn't matched by an `other` on the way
+fn find_unmatched(
+    e: &Editor,
+    from: (usize, usize),
+    c: char,
+    other: char,
+    forward: bool,
+) -> Option<(usize, usize)> {
+    let mut pos = from;
+    let mut depth = 0;
+    // A closer under the cursor belongs to the pair being searched for
+    if !forward && char_at(e, pos) == other {
+        pos = step_back(e, pos)?;
+    }
+    loop {
+        let ch = char_at(e, pos);
+        if ch == c {
+            if depth == 0 {
+                return Some(pos);
+            }
+            depth -= 1;
+        } else if ch == other {
+            depth += 1;
+        }
+        pos = if forward {
+            step_forward(e, pos)?
+        } else {
+            step_back(e, pos)?
+        };
+    }
+}
+
+// Keeps the cursor on a character in normal mode rather than past the end of the line
+fn clamp_cursor(e: &mut Editor) {
+    let (x, y) = e.get_cursor();
+    let text = get_text(e, y);
+    if x > 0 && x >= text.len() {
+        let x = prev_char(text, text.len());
+        e.move_cursor(Movement::Absolute(x, y), false);
+    }
+}
+
+// The modal editing layer on top of an editor, with normal, insert and visual modes
+pub struct Vim {
+    // Where the selection started in the visual modes
+    anchor: (usize, usize),
+    // The keys of the last change, repeated by `.`
+    last_change: Vec<KeyChord>,
+    mode: Mode,
+    pending: Vec<KeyChord>,
+    // Whether the keys typed in insert mode belong to the last change
+    recording: bool,
+    // The register the command being run copies to or pastes from, named with `"`
+    register: Option<char>,
+}
+
+impl Default for Vim {
+    fn default() -> Self {
+        Vim::new()
+    }
+}
+
+impl Vim {
+    pub fn new() -> Self {
+        Vim {
+            anchor: (0, 0),
+            last_change: vec![],
+            mode: Mode::Normal,
+            pending: vec![],
+            recording: false,
+            register: None,
+        }
+    }
+
+    pub fn get_mode(&self) -> Mode {
+        self.mode
+    }
+
+    // What the status bar shows, including any keys typed so far
+    pub fn get_status(&self) -> String {
+        let keys: String = self.pending.iter().filter_map(|k| k.get_char()).collect();
+        if keys.is_empty() {
+            self.mode.to_string()
+        } else {
+            format!("{} {}", self.mode, keys)
+        }
+    }
+
+    pub fn handle_key(
+        &mut self,
+        chord: KeyChord,
+        e: &mut Editor,
+        registers: &mut Registers,
+    ) -> VimResult {
+        if self.mode == Mode::Insert {
+            if self.recording {
+                self.last_change.push(chord);
+            }
+            if chord.get_code() == KeyCode::Esc {
+                self.recording = false;
+                self.mode = Mode::Normal;
+                let (x, y) = e.get_cursor();
+                if x > 0 {
+                    e.move_cursor(Movement::Absolute(prev_char(get_text(e, y), x), y), false);
+                }
+                return VimResult::Handled;
+            }
+            return VimResult::Unhandled;
+        }
+
+        let key = match (chord.get_code(), chord.get_char()) {
+            (_, Some(c)) => c,
+            (KeyCode::Esc, _) => {
+                let cancelled = !self.pending.is_empty();
+                self.pending.clear();
+                if !cancelled {
+                    self.mode = Mode::Normal;
+                    let (x, y) = e.get_cursor();
+                    e.move_cursor(Movement::Absolute(x, y), false);
+                    clamp_cursor(e);
+                }
+                return VimResult::Handled;
+            }
+            (KeyCode::Char('r'), _) if chord.get_modifiers() == KeyModifiers::CONTROL => {
+                e.redo();
+                clamp_cursor(e);
+                return VimResult::Handled;
+            }
+            (KeyCode::Left, _) | (KeyCode::Backspace, _) => 'h',
+            (KeyCode::Right, _) => 'l',
+            (KeyCode::Up, _) => 'k',
+            (KeyCode::Down, _) | (KeyCode::Enter, _) => 'j',
+            (KeyCode::Home, _) => '0',
+            (KeyCode::End, _) => '$',
+            _ if self.pending.is_empty() => return VimResult::Unhandled,
+            _ => {
+                self.pending.clear();
+                return VimResult::Handled;
+            }
+        };
+        self.pending
+            .push(KeyChord::new(KeyCode::Char(key), KeyModifiers::NONE));
+        let keys: Vec<char> = self.pending.iter().filter_map(|k| k.get_char()).collect();
+        let visual = self.mode != Mode::Normal;
+        let command = match parse(&keys, visual) {
+            Parse::Incomplete => return VimResult::Handled,
+            Parse::Invalid => {
+                self.pending.clear();
+                return VimResult::Handled;
+            }
+            Parse::Complete(command) => command,
+        };
+        let keys = std::mem::take(&mut self.pending);
+        self.register = command.register;
+        let (result, change) = if visual {
+            (self.run_visual(command, e, registers), false)
+        } else {
+            self.run_normal(command, e, registers)
+        };
+        if change {
+            self.last_change = keys;
+            self.recording = self.mode == Mode::Insert;
+        }
+        if self.mode == Mode::Normal {
+            clamp_cursor(e);
+        }
+        result
+    }
+
+    // Runs a normal mode command, returning whether it changed the text for `.` to repeat
+    fn run_normal(
+        &mut self,
+        command: Command,
+        e: &mut Editor,
+        registers: &mut Registers,
+    ) -> (VimResult, bool) {
+        let count = command.count.unwrap_or(1);
+        let (x, y) = e.get_cursor();
+        if let Some(operator) = command.operator {
+            let region = if command.key == operator {
+                let end = min(y.saturating_add(count), e.get_line_count()) - 1;
+                Some(((0, y), (0, end), Extent::Linewise))
+            } else if command.key == 'i' || command.key == 'a' {
+                get_text_object(e, command.key == 'a', command.arg.unwrap_or(' '))
+                    .map(|(start, end)| (start, end, Extent::Exclusive))
+            } else {
+                let motion = if operator == 'c' && command.key == 'w' {
+                    // `cw` changes to the end of the word like `ce`, unless on whitespace
+                    match char_at(e, (x, y)).is_whitespace() {
+                        true => 'l',
+                        false => 'e',
+                    }
+                } else if operator == 'c' && command.key == 'W' {
+                    'E'
+                } else {
+                    command.key
+                };
+                get_target(e, motion, command.arg, command.count).map(|(tx, ty, extent)| {
+                    // A word motion doesn't take the line break after the last word
+                    let (tx, ty) = if motion.eq_ignore_ascii_case(&'w') && ty > y {
+                        (get_text(e, y).len(), y)
+                    } else {
+                        (tx, ty)
+                    };
+                    ((x, y), (tx, ty), extent)
+                })
+            };
+            return match region {
+                Some((a, b, extent)) => {
+                    let result = self.operate(operator, a, b, extent, e, registers);
+                    (result, operator != 'y')
+                }
+                None => (VimResult::Handled, false),
+            };
+        }
+
+        let mut change = true;
+        let mut result = VimResult::Handled;
+        match command.key {
+            'x' | 'X' | 'D' | 'C' | 's' | 'S' | 'Y' => {
+                let (operator, key) = match command.key {
+                    'x' => ('d', 'l'),
+                    'X' => ('d', 'h'),
+                    'D' => ('d', '$'),
+                    'C' => ('c', '$'),
+                    's' => ('c', 'l'),
+                    'S' => ('c', 'c'),
+                    _ => ('y', 'y'),
+                };
+                let command = Command {
+                    register: command.register,
+                    count: command.count,
+                    operator: Some(operator),
+                    key,
+                    arg: None,
+                };
+                return self.run_normal(command, e, registers);
+            }
+            'p' | 'P' => {
+                if let Some(clip) = registers.get(self.register) {
+                    e.begin_undo_group();
+                    for _ in 0..count {
+                        if clip.is_linewise() {
+                            e.paste_lines(clip, command.key == 'p');
+                        } else {
+                            let (x, y) = e.get_cursor();
+                            if command.key == 'p' {
+                                let x = next_char(get_text(e, y), x);
+                                e.move_cursor(Movement::Absolute(x, y), false);
+                            }
+                            e.paste(Some(clip));
+                            // Leave the cursor on the last pasted character
+                            let (x, y) = e.get_cursor();
+                            let x = prev_char(get_text(e, y), x);
+                            e.move_cursor(Movement::Absolute(x, y), false);
+                        }
+                    }
+                    e.end_undo_group();
+                }
+            }
+            'i' => self.mode = Mode::Insert,
+            'a' => {
+                let x = next_char(get_text(e, y), x);
+                e.move_cursor(Movement::Absolute(x, y), false);
+                self.mode = Mode::Insert;
+            }
+            'I' => {
+                let x = get_indent_len(get_text(e, y));
+                e.move_cursor(Movement::Absolute(x, y), false);
+                self.mode = Mode::Insert;
+            }
+            'A' => {
+                e.move_cursor(Movement::End, false);
+                self.mode = Mode::Insert;
+            }
+            'o' | 'O' => {
+                e.open_line(command.key == 'O');
+                self.mode = Mode::Insert;
+            }
+            'J' => {
+                e.begin_undo_group();
+                for _ in 0..max(count, 2) - 1 {
+                    e.join_lines();
+                }
+                e.end_undo_group();
+            }
+            'u' => {
+                for _ in 0..count {
+                    e.undo();
+                }
+                change = false;
+            }
+            '.' => return (VimResult::Repeat(self.last_change.clone(), count), false),
+            ':' => {
+                result = VimResult::Command("palette".to_string());
+                change = false;
+            }
+            'v' | 'V' => {
+                self.anchor = (x, y);
+                self.mode = if command.key == 'v' {
+                    Mode::Visual
+                } else {
+                    Mode::VisualLine
+                };
+                e.select_region((x, y), (x, y));
+                self.select_lines(e);
+                change = false;
+            }
+            key => {
+                self.move_to(key, command, e);
+                change = false;
+            }
+        }
+        (result, change)
+    }
+
+    fn run_visual(
+        &mut self,
+        command: Command,
+        e: &mut Editor,
+        registers: &mut Registers,
+    ) -> VimResult {
+        let linewise = self.mode == Mode::VisualLine;
+        let cursor = e.get_cursor();
+        let (start, end) = if (self.anchor.1, self.anchor.0) <= (cursor.1, cursor.0) {
+            (self.anchor, cursor)
+        } else {
+            (cursor, self.anchor)
+        };
+        let extent = if linewise {
+            Extent::Linewise
+        } else {
+            Extent::Inclusive
+        };
+        let operator = match command.key {
+            'd' | 'x' => 'd',
+            'c' | 's' => 'c',
+            'y' | '>' | '<' => command.key,
+            _ => ' ',
+        };
+        if operator != ' ' {
+            self.mode = Mode::Normal;
+            return self.operate(operator, start, end, extent, e, registers);
+        }
+        match command.key {
+            'J' => {
+                e.join_lines();
+                self.mode = Mode::Normal;
+            }
+            'p' if !linewise => {
+                e.paste(registers.get(self.register));
+                self.mode = Mode::Normal;
+            }
+            'o' => {
+                e.select_region(cursor, self.anchor);
+                self.anchor = cursor;
+                self.select_lines(e);
+            }
+            'v' | 'V' => {
+                let mode = if command.key == 'v' {
+                    Mode::Visual
+                } else {
+                    Mode::VisualLine
+                };
+                if mode == self.mode {
+                    self.mode = Mode::Normal;
+                    e.move_cursor(Movement::Absolute(cursor.0, cursor.1), false);
+                } else {
+                    self.mode = mode;
+                    e.select_region(self.anchor, cursor);
+                    self.select_lines(e);
+                }
+            }
+            // The selection stays for palette commands such as `sort` to work on
+            ':' => {
+                self.mode = Mode::Normal;
+                return VimResult::Command("palette".to_string());
+            }
+            key => self.move_to(key, command, e),
+        }
+        VimResult::Handled
+    }
+
+    // Moves the cursor for a motion, extending the selection in the visual modes
+    fn move_to(&mut self, key: char, command: Command, e: &mut Editor) {
+        let count = command.count.unwrap_or(1);
+        let select = self.mode != Mode::Normal;
+        match key {
+            'j' => e.move_cursor(Movement::Relative(0, count as isize), select),
+            'k' => e.move_cursor(Movement::Relative(0, -(count as isize)), select),
+            _ => {
+                if let Some((x, y, _)) = get_target(e, key, command.arg, command.count) {
+                    if key == 'G' || key == 'g' {
+                        e.record_jump();
+                    }
+                    e.move_cursor(Movement::Absolute(x, y), select);
+                }
+            }
+        }
+        self.select_lines(e);
+    }
+
+    // Keeps whole lines selected in visual line mode
+    fn select_lines(&self, e: &mut Editor) {
+        if self.mode != Mode::VisualLine {
+            return;
+        }
+        let y = e.get_cursor().1;
+        let anchor = self.anchor.1;
+        if y >= anchor {
+            e.select_region((0, anchor), (get_text(e, y).len(), y));
+        } else {
+            e.select_region((get_text(e, anchor).len(), anchor), (0, y));
+        }
+    }
+
+    // Applies an operator to the text from `a` to `b`
+    fn operate(
+        &mut self,
+        operator: char,
+        a: (usize, usize),
+        b: (usize, usize),
+        extent: Extent,
+        e: &mut Editor,
+        registers: &mut Registers,
+    ) -> VimResult {
+        let (start, end) = if (a.1, a.0) <= (b.1, b.0) {
+            (a, b)
+        } else {
+            (b, a)
+        };
+        let (top, bottom) = (start.1, end.1 + 1);
+        if extent == Extent::Linewise || operator == '>' || operator == '<' {
+            match operator {
+                'y' => {
+                    registers.store(self.register, e.copy_lines(top, bottom));
+                    let x = if e.get_cursor().1 == top { a.0 } else { 0 };
+                    e.move_cursor(Movement::Absolute(x, top), false);
+                    return VimResult::Yanked;
+                }
+                'd' => {
+                    registers.store(self.register, e.cut_lines(top, bottom));
+                    let y = min(e.get_cursor().1, get_last_line(e));
+                    let x = get_indent_len(get_text(e, y));
+                    e.move_cursor(Movement::Absolute(x, y), false);
+                    return VimResult::Yanked;
+                }
+                'c' => {
+                    registers.store(self.register, e.copy_lines(top, bottom));
+                    let indent = get_indent_len(get_text(e, top));
+                    let last = get_text(e, bottom - 1).len();
+                    e.select_region((indent, top), (last, bottom - 1));
+                    if (indent, top) != (last, bottom - 1) {
+                        e.delete_char();
+                    }
+                    e.move_cursor(Movement::Absolute(indent, top), false);
+                    self.mode = Mode::Insert;
+                    return VimResult::Yanked;
+                }
+                _ => {
+                    e.shift_lines(top, bottom, operator == '<');
+                    let x = get_indent_len(get_text(e, top));
+                    e.move_cursor(Movement::Absolute(x, top), false);
+                    return VimResult::Handled;
+                }
+            }
+        }
+
+        let end = if extent == Extent::Inclusive {
+            step_forward(e, end).unwrap_or((get_text(e, end.1).len(), end.1))
+        } else {
+            end
+        };
+        if start == end {
+            if operator == 'c' {
+                self.mode = Mode::Insert;
+            }
+            e.move_cursor(Movement::Absolute(start.0, start.1), false);
+            return VimResult::Handled;
+        }
+        e.select_region(start, end);
+        match operator {
+            'y' => {
+                registers.store(self.register, e.copy());
+                e.move_cursor(Movement::Absolute(start.0, start.1), false);
+            }
+            _ => {
+                registers.store(self.register, e.cut());
+                if operator == 'c' {
+                    self.mode = Mode::Insert;
+                }
+            }
+        }
+        VimResult::Yanked
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use syntect::parsing::SyntaxSet;
+
+    use super::*;
+
+    fn editor(text: &str, cursor: (usize, usize)) -> Editor {
+        let mut file = tempfile::NamedTempFile::new().unwrap();
+        file.write_all(text.as_bytes()).unwrap();
+        let mut e = Editor::new(SyntaxSet::load_defaults_newlines());
+        e.open_file(&file.path()).unwrap();
+        e.move_cursor(Movement::Absolute(cursor.0, cursor.1), false);
+        e
+    }
+
+    fn get_lines(e: &Editor) -> Vec<&str> {
+        (0..e.get_line_count()).map(|y| get_text(e, y)).collect()
+    }
+
+    // Types `keys` in normal mode, returning the result of the last one
+    fn feed(vim: &mut Vim, e: &mut Editor, keys: &str) -> VimResult {
+        let mut registers = Registers::new(10);
+        let mut result = VimResult::Handled;
+        for c in keys.chars() {
+            let chord = KeyChord::new(KeyCode::Char(c), KeyModifiers::NONE);
+            result = vim.handle_key(chord, e, &mut registers);
+        }
+        result
+    }
+
+    fn parse_complete(keys: &str) -> Command {
+        let keys: Vec<char> = keys.chars().collect();
+        match parse(&keys, false) {
+            Parse::Complete(command) => command,
+            _ => panic!("{:?} didn't parse", keys),
+        }
+    }
+
+    #[test]
+    fn counts_multiply_and_saturate() {
+        let command = parse_complete("3d2w");
+        assert_eq!(command.count, Some(6));
+        assert_eq!((command.operator, command.key), (Some('d'), 'w'));
+        assert_eq!(parse_complete("12j").count, Some(12));
+        assert_eq!(
+            parse_complete("99999999999999999999j").count,
+            Some(MAX_COUNT)
+        );
+        assert_eq!(parse_complete("9999d9999d").count, Some(MAX_COUNT));
+    }
+
+    #[test]
+    fn registers_and_zero() {
+        let command = parse_complete("\"ayy");
+        assert_eq!(command.register, Some('a'));
+        assert_eq!((command.operator, command.key), (Some('y'), 'y'));
+        assert!(matches!(parse(&['"'], false), Parse::Incomplete));
+        assert!(matches!(parse(&['"', '!'], false), Parse::Invalid));
+        let command = parse_complete("0");
+        assert_eq!((command.count, command.key), (None, '0'));
+        let command = parse_complete("d0");
+        assert_eq!((command.operator, command.key), (Some('d'), '0'));
+        assert_eq!(parse_complete("10l").count, Some(10));
+    }
+
+    #[test]
+    fn pending_arguments() {
+        assert!(matches!(parse(&['d', 'g'], false), Parse::Incomplete));
+        assert!(matches!(parse(&['g', 'x'], false), Parse::Invalid));
+        assert!(matches!(parse(&['c', 'i'], false), Parse::Incomplete));
+        let command = parse_complete("dt,");
+        assert_eq!((command.key, command.arg), ('t', Some(',')));
+    }
+
+    #[test]
+    fn word_motions() {
+        let e = editor("foo.bar  baz\n\nqux", (0, 0));
+        assert_eq!(word_forward(&e, (0, 0), false), (3, 0));
+        assert_eq!(word_forward(&e, (3, 0), false), (4, 0));
+        assert_eq!(word_forward(&e, (4, 0), false), (9, 0));
+        assert_eq!(word_forward(&e, (0, 0), true), (9, 0));
+        // Empty lines are a stop of their own
+        assert_eq!(word_forward(&e, (9, 0), false), (0, 1));
+        assert_eq!(word_end(&e, (0, 0), false), (2, 0));
+        assert_eq!(word_end(&e, (0, 0), true), (6, 0));
+        assert_eq!(word_back(&e, (9, 0), false), (4, 0));
+        assert_eq!(word_back(&e, (9, 0), true), (0, 0));
+        assert_eq!(word_back(&e, (0, 2), false), (0, 1));
+    }
+
+    #[test]
+    fn line_targets() {
+        let e = editor("a\n  b\nc\nd\n", (0, 0));
+        assert_eq!(
+            get_target(&e, 'G', None, None).map(|t| (t.0, t.1)),
+            Some((0, 3))
+        );
+        assert_eq!(
+            get_target(&e, 'G', None, Some(2)).map(|t| (t.0, t.1)),
+            Some((2, 1))
+        );
+        assert_eq!(
+            get_target(&e, 'G', None, Some(99)).map(|t| (t.0, t.1)),
+            Some((0, 3))
+        );
+        assert_eq!(
+            get_target(&e, 'j', None, Some(MAX_COUNT)).map(|t| t.1),
+            Some(4)
+        );
+        let mut vim = Vim::new();
+        let mut e = editor("a\n  b\nc\nd\n", (0, 0));
+        feed(&mut vim, &mut e, "2G");
+        assert_eq!(e.get_cursor(), (2, 1));
+        feed(&mut vim, &mut e, "jdgg");
+        assert_eq!(get_lines(&e), vec!["d", ""]);
+    }
+
+    #[test]
+    fn text_objects() {
+        let mut vim = Vim::new();
+        let mut e = editor("foo bar baz", (5, 0));
+        feed(&mut vim, &mut e, "ciw");
+        assert_eq!(get_lines(&e), vec!["foo  baz"]);
+        assert_eq!(vim.get_mode(), Mode::Insert);
+
+        let mut vim = Vim::new();
+        let mut e = editor("say \"hi there\" now", (7, 0));
+        feed(&mut vim, &mut e, "da\"");
+        assert_eq!(get_lines(&e), vec!["say  now"]);
+
+        let mut vim = Vim::new();
+        let mut e = editor("f(\n  a,\n  (b)\n)", (3, 1));
+        feed(&mut vim, &mut e, "ci(");
+        assert_eq!(get_lines(&e), vec!["f()"]);
+        assert_eq!(e.get_cursor(), (2, 0));
+    }
+
+    #[test]
+    fn repeat_keeps_the_count_separate() {
+        let mut vim = Vim::new();
+        let mut e = editor("a\nb\nc\n", (0, 0));
+        feed(&mut vim, &mut e, "dd");
+        match feed(&mut vim, &mut e, "9999999.") {
+            VimResult::Repeat(keys, count) => {
+                assert_eq!(keys.len(), 2);
+                assert_eq!(count, MAX_COUNT);
+            }
+            _ => panic!("`.` didn't repeat"),
+        }
+    }
+}
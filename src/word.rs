@@ -0,0 +1,101 @@
+#[derive(Clone, Copy, PartialEq)]
+pub enum CharClass {
+    Word,
+    Punctuation,
+    Whitespace,
+}
+
+pub fn char_class(c: char) -> CharClass {
+    if c.is_alphanumeric() || c == '_' {
+        CharClass::Word
+    } else if c.is_whitespace() {
+        CharClass::Whitespace
+    } else {
+        CharClass::Punctuation
+    }
+}
+
+// Whether a sub-word starts at `cur`, e.g. the `W` in `someWord` or the `w` in `some_word`
+fn is_subword_start(prev: char, cur: char, next: Option<char>) -> bool {
+    (prev == '_' && cur != '_')
+        || (prev != '_' && cur == '_')
+        || (!prev.is_uppercase() && cur.is_uppercase())
+        || (prev.is_uppercase()
+            && cur.is_uppercase()
+            && matches!(next, Some(n) if n.is_lowercase()))
+}
+
+// Returns the byte index of the next word stop after `from` in `line`
+pub fn next_word_boundary(line: &str, from: usize, subword: bool) -> usize {
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let mut i = chars
+        .iter()
+        .position(|(b, _)| *b >= from)
+        .unwrap_or(chars.len());
+    while i < chars.len() && char_class(chars[i].1) == CharClass::Whitespace {
+        i += 1;
+    }
+    if i < chars.len() {
+        let class = char_class(chars[i].1);
+        i += 1;
+        while i < chars.len() && char_class(chars[i].1) == class {
+            if subword
+                && class == CharClass::Word
+                && is_subword_start(chars[i - 1].1, chars[i].1, chars.get(i + 1).map(|c| c.1))
+            {
+                break;
+            }
+            i += 1;
+        }
+    }
+    chars.get(i).map(|c| c.0).unwrap_or_else(|| line.len())
+}
+
+// Returns the byte index of the previous word stop before `from` in `line`
+pub fn prev_word_boundary(line: &str, from: usize, subword: bool) -> usize {
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let mut i = chars
+        .iter()
+        .position(|(b, _)| *b >= from)
+        .unwrap_or(chars.len());
+    while i > 0 && char_class(chars[i - 1].1) == CharClass::Whitespace {
+        i -= 1;
+    }
+    if i > 0 {
+        let class = char_class(chars[i - 1].1);
+        i -= 1;
+        while i > 0 && char_class(chars[i - 1].1) == class {
+            if subword
+                && class == CharClass::Word
+                && is_subword_start(chars[i - 1].1, chars[i].1, chars.get(i + 1).map(|c| c.1))
+            {
+                break;
+            }
+            i -= 1;
+        }
+    }
+    chars.get(i).map(|c| c.0).unwrap_or_else(|| line.len())
+}
+
+// Returns the byte range of the run of same-class characters around `at`
+pub fn word_at(line: &str, at: usize) -> (usize, usize) {
+    let chars: Vec<(usize, char)> = line.char_indices().collect();
+    let i = match chars.iter().position(|(b, _)| *b >= at) {
+        Some(i) => i,
+        None if !chars.is_empty() => chars.len() - 1,
+        None => return (0, 0),
+    };
+    let class = char_class(chars[i].1);
+    let mut start = i;
+    while start > 0 && char_class(chars[start - 1].1) == class {
+        start -= 1;
+    }
+    let mut end = i + 1;
+    while end < chars.len() && char_class(chars[end].1) == class {
+        end += 1;
+    }
+    (
+        chars[start].0,
+        chars.get(end).map(|c| c.0).unwrap_or_else(|| line.len()),
+    )
+}
@@ -0,0 +1,54 @@
+use std::cmp::min;
+use std::collections::HashMap;
+
+use serde_derive::{Deserialize, Serialize};
+
+use crate::line::char_width;
+use crate::render_config::RenderConfig;
+
+// Soft wrapping of long lines, configurable per file extension in settings.toml as
+// `md = { mode = "window" }` or `txt = { mode = "column", width = 80 }` under `[wrap]`
+#[derive(Clone, Copy, Debug, Default, Deserialize, PartialEq, Serialize)]
+#[serde(tag = "mode", content = "width", rename_all = "lowercase")]
+pub enum WrapMode {
+    #[default]
+    Off,
+    Window,
+    Column(usize),
+}
+
+pub type WrapOverrides = HashMap<String, WrapMode>;
+
+impl WrapMode {
+    // Screen columns a line wraps at given the columns available to it
+    pub fn get_width(&self, available: usize) -> Option<usize> {
+        match self {
+            WrapMode::Off => None,
+            WrapMode::Window => Some(available),
+            WrapMode::Column(width) => Some(min(*width, available)),
+        }
+        .filter(|width| *width > 0)
+    }
+}
+
+// Byte indices into `line` where each wrapped row starts, breaking after whitespace when possible
+pub fn wrap_rows(line: &str, width: usize, options: &RenderConfig) -> Vec<usize> {
+    let mut rows = vec![0];
+    let mut row_width = 0;
+    let mut break_at = None;
+    for (i, c) in line.char_indices() {
+        let w = char_width(c, options);
+        let row_start = *rows.last().unwrap();
+        if row_width + w > width && i > row_start {
+            let start = break_at.filter(|b| *b > row_start).unwrap_or(i);
+            rows.push(start);
+            row_width = line[start..i].chars().map(|c| char_width(c, options)).sum();
+            break_at = None;
+        }
+        row_width += w;
+        if c.is_whitespace() {
+            break_at = Some(i + c.len_utf8());
+        }
+    }
+    rows
+}